@@ -49,19 +49,18 @@ fn main() {
         );
 
         match debugger.step_forward() {
-            Ok(StepResult::Halted { reason }) => {
-                println!("  -> HALTED: {:?}\n", reason);
+            Ok(result @ StepResult::Halted { .. }) => {
+                println!("  -> {result}\n");
                 break;
             }
-            Ok(StepResult::Executed { opcode, gas_used }) => {
-                println!("  -> Executed {:?}, cost {} gas", opcode, gas_used);
+            Ok(result) => {
+                println!("  -> {result}");
                 step += 1;
             }
             Err(e) => {
-                println!("  -> ERROR: {:?}\n", e);
+                println!("  -> ERROR: {e}\n");
                 break;
             }
-            _ => {}
         }
     }
 
@@ -71,9 +70,9 @@ fn main() {
     // Rewind back to the beginning
     while debugger.history_len() > 0 {
         let pc_before = debugger.inspect_pc();
-        debugger.step_backward().unwrap();
+        let result = debugger.step_backward().unwrap();
         let pc_after = debugger.inspect_pc();
-        println!("Rewound: PC {:04x} -> {:04x}", pc_before, pc_after);
+        println!("{result}: PC {:04x} -> {:04x}", pc_before, pc_after);
     }
 
     println!("\n=== State restored to beginning ===");
@@ -87,9 +86,7 @@ fn main() {
     loop {
         match debugger.step_forward() {
             Ok(StepResult::Halted { .. }) => break,
-            Ok(StepResult::Executed { opcode, .. }) => {
-                println!("Executed: {:?}", opcode);
-            }
+            Ok(result) => println!("{result}"),
             _ => break,
         }
     }
@@ -0,0 +1,175 @@
+//! Static analysis over raw bytecode
+
+use std::fmt;
+
+use crate::bytecode::decode_instruction;
+use crate::executor::Opcode;
+use crate::vm::{Vm, MAX_STACK_SIZE};
+
+/// Stack problems detectable without executing the bytecode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StackError {
+    /// Instruction at `offset` needs more stack items than can be present
+    Underflow {
+        offset: usize,
+        required: usize,
+        available: usize,
+    },
+    /// Instruction at `offset` grows the stack to at least `height` items,
+    /// past `MAX_STACK_SIZE`
+    Overflow {
+        offset: usize,
+        height: usize,
+    },
+}
+
+impl fmt::Display for StackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Underflow { offset, required, available } => {
+                write!(f, "stack underflow at {offset:#06x}: need {required}, have {available}")
+            }
+            Self::Overflow { offset, height } => {
+                write!(f, "stack too deep at {offset:#06x}: {height} items exceed {MAX_STACK_SIZE}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for StackError {}
+
+/// Verify the stack never underflows or overflows along any straight-line
+/// path.
+///
+/// Execution starts with an empty stack at offset 0, and the height is
+/// tracked through fallthrough edges (including a not-taken JUMPI). A
+/// JUMPDEST may be entered from a jump with an unknown height, so underflow
+/// checking stops there until the next block whose height is known; the
+/// same applies to dead code after a terminator. Overflow is still caught
+/// there when the growth since the unknown entry alone exceeds
+/// `MAX_STACK_SIZE`. Undecodable bytes halt execution and are treated as
+/// terminators.
+pub fn check_stack_balance(bytecode: &[u8]) -> Result<(), StackError> {
+    let mut height: Option<usize> = Some(0);
+    // Net growth since the height became unknown
+    let mut growth: isize = 0;
+    let mut offset = 0;
+
+    while offset < bytecode.len() {
+        let Some(insn) = decode_instruction(bytecode, offset) else {
+            height = None;
+            growth = 0;
+            offset += 1;
+            continue;
+        };
+        let opcode = insn.opcode;
+
+        if opcode == Opcode::JumpDest {
+            height = None;
+            growth = 0;
+        }
+
+        let required = opcode.stack_inputs();
+        let reached = match height {
+            Some(h) => {
+                if h < required {
+                    return Err(StackError::Underflow { offset, required, available: h });
+                }
+                let h = h - required + opcode.stack_outputs();
+                height = Some(h);
+                h as isize
+            }
+            None => {
+                growth += opcode.stack_outputs() as isize - required as isize;
+                growth
+            }
+        };
+        if reached > MAX_STACK_SIZE as isize {
+            return Err(StackError::Overflow { offset, height: reached as usize });
+        }
+
+        if opcode.is_terminator() {
+            height = None;
+            growth = 0;
+        }
+
+        offset += 1 + opcode.immediate_size();
+    }
+
+    Ok(())
+}
+
+/// Offsets of every JUMPDEST that isn't PUSH immediate data
+pub fn jump_destinations(bytecode: &[u8]) -> Vec<usize> {
+    Vm::analyze_jump_dests(bytecode)
+        .iter()
+        .enumerate()
+        .filter_map(|(offset, &valid)| valid.then_some(offset))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_underflow_flagged() {
+        assert_eq!(
+            check_stack_balance(&[0x01, 0x00]),
+            Err(StackError::Underflow { offset: 0, required: 2, available: 0 })
+        );
+        // PUSH1 1, ADD
+        assert_eq!(
+            check_stack_balance(&[0x60, 0x01, 0x01]),
+            Err(StackError::Underflow { offset: 2, required: 2, available: 1 })
+        );
+    }
+
+    #[test]
+    fn test_balanced_block_passes() {
+        // PUSH1 10, PUSH1 20, ADD, PUSH1 0, MSTORE, STOP
+        let bytecode = [0x60, 0x0A, 0x60, 0x14, 0x01, 0x60, 0x00, 0x52, 0x00];
+        assert_eq!(check_stack_balance(&bytecode), Ok(()));
+    }
+
+    #[test]
+    fn test_jump_targets_are_conservative() {
+        // PUSH1 4, JUMP, STOP, JUMPDEST, ADD: the ADD runs with an unknown height
+        let bytecode = [0x60, 0x04, 0x56, 0x00, 0x5B, 0x01, 0x00];
+        assert_eq!(check_stack_balance(&bytecode), Ok(()));
+
+        // A not-taken JUMPI falls through with a known (empty) height
+        let bytecode = [0x60, 0x00, 0x60, 0x07, 0x57, 0x01, 0x00, 0x5B];
+        assert!(matches!(
+            check_stack_balance(&bytecode),
+            Err(StackError::Underflow { offset: 5, .. })
+        ));
+    }
+
+    #[test]
+    fn test_jump_destinations() {
+        // PUSH1 10, PUSH1 20, ADD, PUSH1 0, MSTORE, STOP
+        let demo = [0x60, 0x0A, 0x60, 0x14, 0x01, 0x60, 0x00, 0x52, 0x00];
+        assert!(jump_destinations(&demo).is_empty());
+
+        // JUMPDEST, PUSH1 0x5B (data, not a destination), JUMPDEST, STOP
+        let bytecode = [0x5B, 0x60, 0x5B, 0x5B, 0x00];
+        assert_eq!(jump_destinations(&bytecode), vec![0, 3]);
+        assert_eq!(Vm::new(bytecode.to_vec(), 0, Default::default()).jump_destinations(), vec![0, 3]);
+    }
+
+    #[test]
+    fn test_stack_too_deep_flagged() {
+        let pushes: Vec<u8> = [0x60, 0x01].repeat(MAX_STACK_SIZE + 1);
+        assert_eq!(
+            check_stack_balance(&pushes),
+            Err(StackError::Overflow { offset: 2 * MAX_STACK_SIZE, height: MAX_STACK_SIZE + 1 })
+        );
+        assert_eq!(check_stack_balance(&pushes[2..]), Ok(()));
+
+        // Behind a JUMPDEST the entry height is unknown, but the growth alone is too much
+        let mut block = vec![0x5B];
+        block.extend_from_slice(&pushes);
+        assert!(matches!(check_stack_balance(&block), Err(StackError::Overflow { offset: 2049, .. })));
+    }
+}
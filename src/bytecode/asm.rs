@@ -0,0 +1,138 @@
+//! Assembly of mnemonic source into bytecode
+
+use std::fmt;
+
+use crate::bytecode::format_mnemonic;
+use crate::core::VmError;
+use crate::executor::Opcode;
+
+/// Errors from `assemble`, with 1-based source line numbers
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AsmError {
+    /// Not the name of any opcode
+    UnknownMnemonic { line: usize, token: String },
+    /// A PUSH without a value
+    MissingImmediate { line: usize },
+    /// A PUSH value that isn't a number or doesn't fit the PUSH width
+    InvalidImmediate { line: usize, token: String },
+    /// Text after an instruction that takes no operand
+    UnexpectedOperand { line: usize, token: String },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownMnemonic { line, token } => write!(f, "line {line}: unknown mnemonic {token:?}"),
+            Self::MissingImmediate { line } => write!(f, "line {line}: PUSH needs a value"),
+            Self::InvalidImmediate { line, token } => {
+                write!(f, "line {line}: {token:?} is not a value that fits the PUSH")
+            }
+            Self::UnexpectedOperand { line, token } => write!(f, "line {line}: unexpected operand {token:?}"),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+impl From<AsmError> for VmError {
+    fn from(err: AsmError) -> Self {
+        let line = match &err {
+            AsmError::UnknownMnemonic { line, .. }
+            | AsmError::MissingImmediate { line }
+            | AsmError::InvalidImmediate { line, .. }
+            | AsmError::UnexpectedOperand { line, .. } => *line,
+        };
+        VmError::InvalidAssembly { line, reason: err.to_string() }
+    }
+}
+
+/// Assemble one instruction per line, in the mnemonics the disassembler
+/// prints. PUSH1..PUSH32 take a hex (`0x..`) or decimal value, left-padded
+/// to the PUSH width. Mnemonics are case-insensitive; blank lines and
+/// anything after `;` or `//` are ignored.
+pub fn assemble(src: &str) -> Result<Vec<u8>, AsmError> {
+    let mut code = Vec::new();
+    for (index, raw) in src.lines().enumerate() {
+        let line = index + 1;
+        let text = raw.split(';').next().unwrap_or("");
+        let text = text.split("//").next().unwrap_or("");
+        let mut tokens = text.split_whitespace();
+        let Some(mnemonic) = tokens.next() else {
+            continue;
+        };
+        let opcode = opcode_named(mnemonic)
+            .ok_or_else(|| AsmError::UnknownMnemonic { line, token: mnemonic.to_string() })?;
+        code.push(opcode as u8);
+
+        let width = opcode.immediate_size();
+        if width > 0 {
+            let token = tokens.next().ok_or(AsmError::MissingImmediate { line })?;
+            let bytes = parse_immediate(token, width)
+                .ok_or_else(|| AsmError::InvalidImmediate { line, token: token.to_string() })?;
+            code.extend_from_slice(&bytes);
+        }
+        if let Some(extra) = tokens.next() {
+            return Err(AsmError::UnexpectedOperand { line, token: extra.to_string() });
+        }
+    }
+    Ok(code)
+}
+
+fn opcode_named(mnemonic: &str) -> Option<Opcode> {
+    let upper = mnemonic.to_ascii_uppercase();
+    (0..=u8::MAX)
+        .filter_map(Opcode::from_u8)
+        .find(|&opcode| format_mnemonic(opcode, &None) == upper)
+}
+
+/// `token` as `width` big-endian bytes
+fn parse_immediate(token: &str, width: usize) -> Option<Vec<u8>> {
+    let digits = match token.strip_prefix("0x").or_else(|| token.strip_prefix("0X")) {
+        Some(hex) => {
+            if hex.is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            let hex = hex.trim_start_matches('0');
+            if hex.len() > width * 2 {
+                return None;
+            }
+            let padded = format!("{hex:0>width$}", width = width * 2);
+            (0..width)
+                .map(|i| u8::from_str_radix(&padded[i * 2..i * 2 + 2], 16).ok())
+                .collect::<Option<Vec<u8>>>()?
+        }
+        None => {
+            let value: u128 = token.parse().ok()?;
+            let bytes = value.to_be_bytes();
+            let significant = bytes.iter().position(|&b| b != 0).map_or(0, |first| 16 - first);
+            if significant > width {
+                return None;
+            }
+            let mut out = vec![0u8; width];
+            let take = width.min(16);
+            out[width - take..].copy_from_slice(&bytes[16 - take..]);
+            out
+        }
+    };
+    Some(digits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::disassemble;
+
+    #[test]
+    fn test_assemble() {
+        let src = "push1 0x2a ; answer\nPUSH2 300\n\n  CALLDATASIZE // size\nPUSH32 0x01\nSTOP";
+        let code = assemble(src).unwrap();
+        let mnemonics: Vec<String> = disassemble(&code).into_iter().map(|insn| insn.mnemonic).collect();
+        assert_eq!(mnemonics[..3], ["PUSH1 0x2a", "PUSH2 0x012c", "CALLDATASIZE"]);
+        assert_eq!(code.len(), 2 + 3 + 1 + 33 + 1);
+
+        assert_eq!(assemble("PUSH1 0x100"), Err(AsmError::InvalidImmediate { line: 1, token: "0x100".into() }));
+        assert_eq!(assemble("ADD\nFOO"), Err(AsmError::UnknownMnemonic { line: 2, token: "FOO".into() }));
+        assert_eq!(assemble("PUSH1"), Err(AsmError::MissingImmediate { line: 1 }));
+        assert_eq!(assemble("ADD 1"), Err(AsmError::UnexpectedOperand { line: 1, token: "1".into() }));
+    }
+}
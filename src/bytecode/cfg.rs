@@ -0,0 +1,305 @@
+//! Control-flow graph construction and Graphviz export
+
+use std::fmt::Write;
+
+use crate::bytecode::{decode_instruction, DecodedInstruction};
+use crate::executor::Opcode;
+
+/// A maximal straight-line run of instructions with a single entry
+#[derive(Clone, Debug)]
+pub struct BasicBlock {
+    /// Offset of the first instruction
+    pub start: usize,
+    /// Offset just past the last instruction
+    pub end: usize,
+    pub instructions: Vec<DecodedInstruction>,
+}
+
+impl BasicBlock {
+    /// Last instruction of the block
+    pub fn last(&self) -> &DecodedInstruction {
+        self.instructions.last().expect("basic blocks are never empty")
+    }
+}
+
+/// How control passes between two blocks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Falls through into the next block (including a not-taken JUMPI)
+    Fallthrough,
+    /// Unconditional JUMP
+    Jump,
+    /// Taken branch of a JUMPI
+    Conditional,
+}
+
+/// A control-flow edge between block start offsets.
+///
+/// `to` is `None` for a jump whose target isn't a constant pushed right
+/// before it, or whose constant target isn't a JUMPDEST.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Edge {
+    pub from: usize,
+    pub to: Option<usize>,
+    pub kind: EdgeKind,
+}
+
+/// Basic blocks of a contract and the edges between them
+#[derive(Clone, Debug, Default)]
+pub struct ControlFlowGraph {
+    pub blocks: Vec<BasicBlock>,
+    pub edges: Vec<Edge>,
+}
+
+impl ControlFlowGraph {
+    /// Block starting at `start`
+    pub fn block_at(&self, start: usize) -> Option<&BasicBlock> {
+        self.blocks.iter().find(|block| block.start == start)
+    }
+
+    /// Edges leaving the block starting at `start`
+    pub fn successors(&self, start: usize) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(move |edge| edge.from == start)
+    }
+}
+
+/// Split `bytecode` into basic blocks and connect them.
+///
+/// Blocks begin at offset 0, at every JUMPDEST and after every jump or
+/// terminator. An undecodable byte halts execution, so it ends the block
+/// before it and is not part of any block.
+pub fn build_cfg(bytecode: &[u8]) -> ControlFlowGraph {
+    let mut blocks: Vec<BasicBlock> = Vec::new();
+    let mut current: Vec<DecodedInstruction> = Vec::new();
+    let mut offset = 0;
+
+    let flush = |current: &mut Vec<DecodedInstruction>, blocks: &mut Vec<BasicBlock>, end: usize| {
+        if !current.is_empty() {
+            let start = current[0].offset;
+            blocks.push(BasicBlock { start, end, instructions: std::mem::take(current) });
+        }
+    };
+
+    while offset < bytecode.len() {
+        let Some(insn) = decode_instruction(bytecode, offset) else {
+            flush(&mut current, &mut blocks, offset);
+            offset += 1;
+            continue;
+        };
+        let opcode = insn.opcode;
+        let next = offset + 1 + opcode.immediate_size();
+
+        if opcode == Opcode::JumpDest {
+            flush(&mut current, &mut blocks, offset);
+        }
+        current.push(insn);
+        if opcode == Opcode::JumpI || opcode.is_terminator() {
+            flush(&mut current, &mut blocks, next);
+        }
+        offset = next;
+    }
+    flush(&mut current, &mut blocks, offset);
+
+    let is_jumpdest = |target: usize| {
+        blocks
+            .iter()
+            .any(|block| block.start == target && block.instructions[0].opcode == Opcode::JumpDest)
+    };
+
+    let mut edges = Vec::new();
+    for (i, block) in blocks.iter().enumerate() {
+        let last = block.last();
+        let kind = match last.opcode {
+            Opcode::Jump => Some(EdgeKind::Jump),
+            Opcode::JumpI => Some(EdgeKind::Conditional),
+            _ => None,
+        };
+
+        if let Some(kind) = kind {
+            let to = static_jump_target(block).filter(|&target| is_jumpdest(target));
+            edges.push(Edge { from: block.start, to, kind });
+        }
+
+        let falls_through = !last.opcode.is_terminator();
+        if falls_through
+            && let Some(next) = blocks.get(i + 1)
+            && next.start == block.end
+        {
+            edges.push(Edge { from: block.start, to: Some(next.start), kind: EdgeKind::Fallthrough });
+        }
+    }
+
+    ControlFlowGraph { blocks, edges }
+}
+
+/// Whether a reachable path through `bytecode` runs off the end of the code
+/// without a terminator; see `unterminated_path`
+pub fn has_unterminated_path(bytecode: &[u8]) -> bool {
+    unterminated_path(bytecode).is_some()
+}
+
+/// Offset of the last instruction of a reachable block that runs off the
+/// end of the code without a terminator. The VM halts there as on STOP,
+/// but it usually means a missing RETURN or REVERT. A dynamic jump is
+/// assumed to reach every JUMPDEST.
+pub fn unterminated_path(bytecode: &[u8]) -> Option<usize> {
+    let cfg = build_cfg(bytecode);
+    let mut reached: Vec<usize> = cfg.blocks.first().map(|block| block.start).into_iter().collect();
+    let mut pending = reached.clone();
+    while let Some(start) = pending.pop() {
+        for edge in cfg.successors(start) {
+            let targets: Vec<usize> = match edge.to {
+                Some(to) => vec![to],
+                None => cfg
+                    .blocks
+                    .iter()
+                    .filter(|block| block.instructions[0].opcode == Opcode::JumpDest)
+                    .map(|block| block.start)
+                    .collect(),
+            };
+            for target in targets {
+                if !reached.contains(&target) {
+                    reached.push(target);
+                    pending.push(target);
+                }
+            }
+        }
+    }
+
+    cfg.blocks
+        .iter()
+        .filter(|block| reached.contains(&block.start))
+        .find(|block| block.end >= bytecode.len() && !block.last().opcode.is_terminator())
+        .map(|block| block.last().offset)
+}
+
+/// Target of a block-ending jump when it's a constant pushed just before it
+fn static_jump_target(block: &BasicBlock) -> Option<usize> {
+    let [.., push, _] = block.instructions.as_slice() else {
+        return None;
+    };
+    if !push.opcode.is_push() {
+        return None;
+    }
+    let immediate = push.immediate.as_deref()?;
+    if immediate.len() > std::mem::size_of::<usize>() {
+        return None;
+    }
+    Some(immediate.iter().fold(0usize, |acc, &b| (acc << 8) | b as usize))
+}
+
+/// Render the control-flow graph of `bytecode` as a Graphviz DOT digraph.
+///
+/// Each basic block is a node labeled with its disassembly. Dynamic jumps
+/// point at a shared "?" node with dashed edges.
+pub fn cfg_to_dot(bytecode: &[u8]) -> String {
+    let cfg = build_cfg(bytecode);
+    let mut dot = String::new();
+
+    dot.push_str("digraph cfg {\n");
+    dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+
+    for block in &cfg.blocks {
+        let mut label = String::new();
+        for insn in &block.instructions {
+            let _ = write!(label, "{:04x}: {}\\l", insn.offset, insn.mnemonic);
+        }
+        let _ = writeln!(dot, "    b{} [label=\"{}\"];", block.start, label);
+    }
+
+    let mut has_dynamic = false;
+    for edge in &cfg.edges {
+        let label = match edge.kind {
+            EdgeKind::Fallthrough => "fallthrough",
+            EdgeKind::Jump => "jump",
+            EdgeKind::Conditional => "true",
+        };
+        match edge.to {
+            Some(to) => {
+                let _ = writeln!(dot, "    b{} -> b{} [label=\"{}\"];", edge.from, to, label);
+            }
+            None => {
+                has_dynamic = true;
+                let _ = writeln!(dot, "    b{} -> dynamic [label=\"{}\", style=dashed];", edge.from, label);
+            }
+        }
+    }
+
+    if has_dynamic {
+        dot.push_str("    dynamic [label=\"?\", shape=circle];\n");
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// PUSH1 0, CALLDATALOAD, PUSH1 9, JUMPI, PUSH1 1, STOP, (pad) JUMPDEST,
+    /// PUSH1 2, CALLDATALOAD, JUMP
+    const BRANCHING: &[u8] = &[
+        0x60, 0x00, 0x35, 0x60, 0x09, 0x57, 0x60, 0x01, 0x00, 0x5B, 0x60, 0x02, 0x35, 0x56,
+    ];
+
+    /// Minimal structural check of the DOT grammar subset we emit
+    fn assert_valid_dot(dot: &str) {
+        let lines: Vec<&str> = dot.lines().collect();
+        assert_eq!(lines.first(), Some(&"digraph cfg {"));
+        assert_eq!(lines.last(), Some(&"}"));
+        for line in &lines[1..lines.len() - 1] {
+            let stmt = line.trim();
+            assert!(stmt.ends_with("];"), "unterminated statement: {stmt}");
+            let (head, attrs) = stmt.split_once(" [").expect("statement without attributes");
+            let ids: Vec<&str> = head.split(" -> ").collect();
+            assert!(ids.len() <= 2, "bad edge: {stmt}");
+            for id in ids {
+                assert!(id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'), "bad id: {id}");
+            }
+            assert_eq!(attrs.matches('"').count() % 2, 0, "unbalanced quotes: {stmt}");
+        }
+    }
+
+    #[test]
+    fn test_blocks_and_edges() {
+        let cfg = build_cfg(BRANCHING);
+        let starts: Vec<usize> = cfg.blocks.iter().map(|b| b.start).collect();
+        assert_eq!(starts, vec![0, 6, 9]);
+
+        let from_entry: Vec<&Edge> = cfg.successors(0).collect();
+        assert_eq!(from_entry.len(), 2);
+        assert!(from_entry.contains(&&Edge { from: 0, to: Some(9), kind: EdgeKind::Conditional }));
+        assert!(from_entry.contains(&&Edge { from: 0, to: Some(6), kind: EdgeKind::Fallthrough }));
+
+        assert_eq!(cfg.successors(6).count(), 0);
+        let dynamic: Vec<&Edge> = cfg.successors(9).collect();
+        assert_eq!(dynamic, vec![&Edge { from: 9, to: None, kind: EdgeKind::Jump }]);
+    }
+
+    #[test]
+    fn test_unterminated_path() {
+        // PUSH1 1, PUSH1 2, ADD
+        assert_eq!(unterminated_path(&[0x60, 0x01, 0x60, 0x02, 0x01]), Some(4));
+        assert!(has_unterminated_path(&[0x60, 0x01, 0x60, 0x02, 0x01]));
+        assert!(!has_unterminated_path(&[0x60, 0x01, 0x60, 0x02, 0x01, 0x00]));
+        assert!(!has_unterminated_path(BRANCHING));
+
+        // PUSH1 4, JUMP, ADD, JUMPDEST, STOP: the ADD is unreachable
+        assert!(!has_unterminated_path(&[0x60, 0x04, 0x56, 0x01, 0x5B, 0x00]));
+        // PUSH1 0, CALLDATALOAD, PUSH1 6, JUMPI, JUMPDEST: both branches fall off
+        assert_eq!(unterminated_path(&[0x60, 0x00, 0x35, 0x60, 0x06, 0x57, 0x5B]), Some(6));
+    }
+
+    #[test]
+    fn test_cfg_to_dot() {
+        let dot = cfg_to_dot(BRANCHING);
+        assert_valid_dot(&dot);
+
+        for start in [0, 6, 9] {
+            assert!(dot.contains(&format!("    b{start} [label=")), "missing block {start}:\n{dot}");
+        }
+        assert!(dot.contains("0005: JUMPI\\l"));
+        assert!(dot.contains("b9 -> dynamic [label=\"jump\", style=dashed];"));
+        assert!(dot.contains("dynamic [label=\"?\""));
+    }
+}
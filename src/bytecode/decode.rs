@@ -1,5 +1,6 @@
 //! Bytecode decoding and disassembly
 
+use crate::core::U256;
 use crate::executor::Opcode;
 
 /// Decoded instruction with metadata
@@ -11,6 +12,23 @@ pub struct DecodedInstruction {
     pub mnemonic: String,
 }
 
+impl DecodedInstruction {
+    /// Value a PUSH places on the stack, `None` for other opcodes.
+    ///
+    /// An immediate cut short by the end of the code is zero-padded on the
+    /// right, as execution reads it.
+    pub fn immediate_u256(&self) -> Option<U256> {
+        if self.opcode == Opcode::Push0 {
+            return Some(U256::ZERO);
+        }
+        let immediate = self.immediate.as_deref()?;
+        let mut bytes = [0u8; 32];
+        let start = 32 - self.opcode.immediate_size();
+        bytes[start..start + immediate.len()].copy_from_slice(immediate);
+        Some(U256::from_be_bytes(bytes))
+    }
+}
+
 /// Decode a single instruction at offset
 pub fn decode_instruction(bytecode: &[u8], offset: usize) -> Option<DecodedInstruction> {
     if offset >= bytecode.len() {
@@ -40,7 +58,7 @@ pub fn decode_instruction(bytecode: &[u8], offset: usize) -> Option<DecodedInstr
 }
 
 /// Format opcode as mnemonic string
-fn format_mnemonic(opcode: Opcode, immediate: &Option<Vec<u8>>) -> String {
+pub(crate) fn format_mnemonic(opcode: Opcode, immediate: &Option<Vec<u8>>) -> String {
     let byte = opcode as u8;
     
     // Handle PUSH opcodes
@@ -99,6 +117,31 @@ fn format_mnemonic(opcode: Opcode, immediate: &Option<Vec<u8>>) -> String {
         Opcode::Shr => "SHR",
         Opcode::Sar => "SAR",
         Opcode::Keccak256 => "KECCAK256",
+        Opcode::Address => "ADDRESS",
+        Opcode::Balance => "BALANCE",
+        Opcode::Origin => "ORIGIN",
+        Opcode::Caller => "CALLER",
+        Opcode::CallValue => "CALLVALUE",
+        Opcode::CallDataLoad => "CALLDATALOAD",
+        Opcode::CallDataSize => "CALLDATASIZE",
+        Opcode::CallDataCopy => "CALLDATACOPY",
+        Opcode::CodeSize => "CODESIZE",
+        Opcode::CodeCopy => "CODECOPY",
+        Opcode::GasPrice => "GASPRICE",
+        Opcode::ExtCodeSize => "EXTCODESIZE",
+        Opcode::ExtCodeCopy => "EXTCODECOPY",
+        Opcode::ReturnDataSize => "RETURNDATASIZE",
+        Opcode::ReturnDataCopy => "RETURNDATACOPY",
+        Opcode::ExtCodeHash => "EXTCODEHASH",
+        Opcode::BlockHash => "BLOCKHASH",
+        Opcode::Coinbase => "COINBASE",
+        Opcode::Timestamp => "TIMESTAMP",
+        Opcode::Number => "NUMBER",
+        Opcode::Difficulty => "DIFFICULTY",
+        Opcode::GasLimit => "GASLIMIT",
+        Opcode::ChainId => "CHAINID",
+        Opcode::SelfBalance => "SELFBALANCE",
+        Opcode::BaseFee => "BASEFEE",
         Opcode::Pop => "POP",
         Opcode::MLoad => "MLOAD",
         Opcode::MStore => "MSTORE",
@@ -111,6 +154,7 @@ fn format_mnemonic(opcode: Opcode, immediate: &Option<Vec<u8>>) -> String {
         Opcode::MSize => "MSIZE",
         Opcode::Gas => "GAS",
         Opcode::JumpDest => "JUMPDEST",
+        Opcode::Push0 => "PUSH0",
         Opcode::Return => "RETURN",
         Opcode::Revert => "REVERT",
         Opcode::Invalid => "INVALID",
@@ -145,6 +189,24 @@ pub fn disassemble(bytecode: &[u8]) -> Vec<DecodedInstruction> {
     instructions
 }
 
+/// Position of the instruction starting at `pc` among those `disassemble`
+/// returns; `None` when `pc` falls inside a PUSH immediate, on a byte that
+/// isn't an opcode or past the end
+pub fn pc_to_instruction_index(bytecode: &[u8], pc: usize) -> Option<usize> {
+    let mut offset = 0;
+    let mut index = 0;
+    while offset < pc.min(bytecode.len()) {
+        match Opcode::from_u8(bytecode[offset]) {
+            Some(opcode) => {
+                offset += 1 + opcode.immediate_size();
+                index += 1;
+            }
+            None => offset += 1,
+        }
+    }
+    (offset == pc && decode_instruction(bytecode, pc).is_some()).then_some(index)
+}
+
 /// Print disassembly to string
 pub fn disassemble_to_string(bytecode: &[u8]) -> String {
     let instructions = disassemble(bytecode);
@@ -172,4 +234,23 @@ mod tests {
         assert_eq!(instructions[2].mnemonic, "MSTORE");
         assert_eq!(instructions[3].mnemonic, "STOP");
     }
+
+    #[test]
+    fn test_immediate_u256() {
+        // PUSH2 0x0102, PUSH0, ADD, PUSH2 0x03 cut short
+        let instructions = disassemble(&[0x61, 0x01, 0x02, 0x5F, 0x01, 0x61, 0x03]);
+        assert_eq!(instructions[0].immediate_u256(), Some(U256::from(0x0102u64)));
+        assert_eq!(instructions[1].immediate_u256(), Some(U256::ZERO));
+        assert_eq!(instructions[2].immediate_u256(), None);
+        assert_eq!(instructions[3].immediate_u256(), Some(U256::from(0x0300u64)));
+    }
+
+    #[test]
+    fn test_pc_to_instruction_index() {
+        // PUSH2 0x0102, 0xEF (not an opcode), ADD, STOP
+        let bytecode = vec![0x61, 0x01, 0x02, 0xEF, 0x01, 0x00];
+        let indices: Vec<_> = (0..8).map(|pc| pc_to_instruction_index(&bytecode, pc)).collect();
+        assert_eq!(indices, [Some(0), None, None, None, Some(1), Some(2), None, None]);
+        assert_eq!(disassemble(&bytecode)[2].offset, 5);
+    }
 }
@@ -1,5 +1,12 @@
 //! Bytecode parsing and utilities
 
 mod decode;
+mod asm;
+mod analysis;
+mod cfg;
 
-pub use decode::{decode_instruction, disassemble};
+pub use asm::{assemble, AsmError};
+pub use decode::{decode_instruction, disassemble, disassemble_to_string, pc_to_instruction_index, DecodedInstruction};
+pub(crate) use decode::format_mnemonic;
+pub use analysis::{check_stack_balance, jump_destinations, StackError};
+pub use cfg::{build_cfg, cfg_to_dot, has_unterminated_path, unterminated_path, BasicBlock, ControlFlowGraph, Edge, EdgeKind};
@@ -27,6 +27,10 @@ pub enum VmError {
     InvalidOpcode {
         opcode: u8,
     },
+    /// Valid opcode the interpreter doesn't implement yet
+    UnimplementedOpcode {
+        opcode: u8,
+    },
     /// Memory access out of bounds
     OutOfBoundsMemory {
         offset: usize,
@@ -44,6 +48,38 @@ pub enum VmError {
     CheckpointNotFound {
         index: usize,
     },
+    /// Code access outside the bytecode
+    CodeOutOfBounds {
+        offset: usize,
+        size: usize,
+    },
+    /// RETURNDATACOPY read past the end of the return data
+    ReturnDataOutOfBounds {
+        offset: usize,
+        size: usize,
+        available: usize,
+    },
+    /// Source passed to `run_asm` doesn't assemble
+    InvalidAssembly {
+        line: usize,
+        reason: String,
+    },
+    /// Initial gas above the block gas limit, with the limit enforced
+    GasAboveBlockLimit {
+        gas: u64,
+        limit: u64,
+    },
+    /// DIV by zero while the VM is configured to trap on it
+    DivisionByZero,
+    /// Rewinding instruction `at` didn't restore the state hash recorded
+    /// after the instruction before it
+    ReversibilityViolation {
+        at: usize,
+    },
+    /// Operation needs an empty execution history
+    HistoryNotEmpty {
+        recorded: usize,
+    },
     /// Execution halted
     Halted {
         reason: HaltReason,
@@ -65,6 +101,8 @@ pub enum HaltReason {
     InvalidOpcode(u8),
     /// Invalid jump
     InvalidJump,
+    /// Any other error, such as a stack underflow
+    Error(Box<VmError>),
 }
 
 impl fmt::Display for VmError {
@@ -85,6 +123,9 @@ impl fmt::Display for VmError {
             Self::InvalidOpcode { opcode } => {
                 write!(f, "invalid opcode: {opcode:#04x}")
             }
+            Self::UnimplementedOpcode { opcode } => {
+                write!(f, "opcode {opcode:#04x} is not implemented")
+            }
             Self::OutOfBoundsMemory { offset, size } => {
                 write!(f, "memory access out of bounds: offset={offset}, size={size}")
             }
@@ -100,13 +141,46 @@ impl fmt::Display for VmError {
             Self::CheckpointNotFound { index } => {
                 write!(f, "checkpoint not found at index {index}")
             }
+            Self::CodeOutOfBounds { offset, size } => {
+                write!(f, "code access out of bounds: offset={offset}, size={size}")
+            }
+            Self::ReturnDataOutOfBounds { offset, size, available } => {
+                write!(f, "return data access out of bounds: offset={offset}, size={size}, available={available}")
+            }
+            Self::InvalidAssembly { reason, .. } => {
+                write!(f, "invalid assembly: {reason}")
+            }
+            Self::DivisionByZero => write!(f, "division by zero"),
+            Self::GasAboveBlockLimit { gas, limit } => {
+                write!(f, "gas {gas} exceeds the block gas limit {limit}")
+            }
+            Self::ReversibilityViolation { at } => {
+                write!(f, "rewinding instruction {at} did not restore the previous state")
+            }
+            Self::HistoryNotEmpty { recorded } => {
+                write!(f, "{recorded} instructions already recorded; reset first")
+            }
             Self::Halted { reason } => {
-                write!(f, "execution halted: {reason:?}")
+                write!(f, "execution halted: {reason}")
             }
         }
     }
 }
 
+impl fmt::Display for HaltReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stop => write!(f, "stop"),
+            Self::Return(data) => write!(f, "return ({} bytes)", data.len()),
+            Self::Revert(data) => write!(f, "revert ({} bytes)", data.len()),
+            Self::OutOfGas => write!(f, "out of gas"),
+            Self::InvalidOpcode(opcode) => write!(f, "invalid opcode {opcode:#04x}"),
+            Self::InvalidJump => write!(f, "invalid jump"),
+            Self::Error(err) => write!(f, "{err}"),
+        }
+    }
+}
+
 impl std::error::Error for VmError {}
 
 /// Result type alias for VM operations
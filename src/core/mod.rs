@@ -2,6 +2,7 @@
 
 mod types;
 mod error;
+pub mod hash;
 
 pub use types::*;
 pub use error::*;
@@ -1,5 +1,7 @@
 //! Primitive types for the TTBD virtual machine
 
+use std::fmt;
+
 /// 256-bit unsigned integer for stack/storage values.
 /// 
 /// Stored as 4 x u64 in little-endian limb order (limb 0 is least significant).
@@ -25,6 +27,23 @@ impl U256 {
         Self(limbs)
     }
 
+    /// Parse up to 64 hex digits, with or without a `0x` prefix
+    pub fn from_hex(text: &str) -> Option<Self> {
+        let hex = text.strip_prefix("0x").unwrap_or(text);
+        if hex.is_empty() || hex.len() > 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let end = hex.len().saturating_sub(i * 16);
+            let start = end.saturating_sub(16);
+            if start < end {
+                *limb = u64::from_str_radix(&hex[start..end], 16).ok()?;
+            }
+        }
+        Some(Self(limbs))
+    }
+
     /// Convert to big-endian bytes
     pub fn to_be_bytes(&self) -> [u8; 32] {
         let mut bytes = [0u8; 32];
@@ -45,28 +64,137 @@ impl U256 {
     pub fn wrapping_add(self, rhs: Self) -> Self {
         let mut result = [0u64; 4];
         let mut carry = 0u64;
-        for i in 0..4 {
+        for (i, limb) in result.iter_mut().enumerate() {
             let (sum1, c1) = self.0[i].overflowing_add(rhs.0[i]);
             let (sum2, c2) = sum1.overflowing_add(carry);
-            result[i] = sum2;
+            *limb = sum2;
             carry = (c1 as u64) + (c2 as u64);
         }
         Self(result)
     }
 
+    /// Addition returning the wrapped sum and whether it carried out
+    pub fn overflowing_add(self, rhs: Self) -> (Self, bool) {
+        let sum = self.wrapping_add(rhs);
+        (sum, sum < self)
+    }
+
+    /// `(self + rhs) % modulus` over the full 257-bit sum; zero when
+    /// `modulus` is zero, as ADDMOD defines
+    pub fn add_mod(self, rhs: Self, modulus: Self) -> Self {
+        if modulus.is_zero() {
+            return Self::ZERO;
+        }
+        let (sum, carry) = self.overflowing_add(rhs);
+        Self::wide_rem(Self::from(carry as u64), sum, modulus)
+    }
+
     /// Wrapping subtraction
     pub fn wrapping_sub(self, rhs: Self) -> Self {
         let mut result = [0u64; 4];
         let mut borrow = 0u64;
-        for i in 0..4 {
+        for (i, limb) in result.iter_mut().enumerate() {
             let (diff1, b1) = self.0[i].overflowing_sub(rhs.0[i]);
             let (diff2, b2) = diff1.overflowing_sub(borrow);
-            result[i] = diff2;
+            *limb = diff2;
             borrow = (b1 as u64) + (b2 as u64);
         }
         Self(result)
     }
 
+    /// Full 512-bit product as (high, low) halves
+    pub fn full_mul(self, rhs: Self) -> (Self, Self) {
+        let mut limbs = [0u64; 8];
+        for i in 0..4 {
+            let mut carry = 0u128;
+            for j in 0..4 {
+                let acc = limbs[i + j] as u128 + self.0[i] as u128 * rhs.0[j] as u128 + carry;
+                limbs[i + j] = acc as u64;
+                carry = acc >> 64;
+            }
+            limbs[i + 4] = carry as u64;
+        }
+        (
+            Self([limbs[4], limbs[5], limbs[6], limbs[7]]),
+            Self([limbs[0], limbs[1], limbs[2], limbs[3]]),
+        )
+    }
+
+    /// Wrapping multiplication
+    pub fn wrapping_mul(self, rhs: Self) -> Self {
+        self.full_mul(rhs).1
+    }
+
+    /// `(self * rhs) % modulus` without truncating the product; zero when
+    /// `modulus` is zero, as MULMOD defines
+    pub fn mul_mod(self, rhs: Self, modulus: Self) -> Self {
+        if modulus.is_zero() {
+            return Self::ZERO;
+        }
+        let (high, low) = self.full_mul(rhs);
+        Self::wide_rem(high, low, modulus)
+    }
+
+    /// Quotient and remainder, or `None` when `rhs` is zero
+    pub fn checked_div_rem(self, rhs: Self) -> Option<(Self, Self)> {
+        if rhs.is_zero() {
+            return None;
+        }
+        let mut quotient = Self::ZERO;
+        let mut rem = Self::ZERO;
+        for bit in (0..256).rev() {
+            rem = Self([
+                rem.0[0] << 1 | (self.0[bit / 64] >> (bit % 64)) & 1,
+                rem.0[1] << 1 | rem.0[0] >> 63,
+                rem.0[2] << 1 | rem.0[1] >> 63,
+                rem.0[3] << 1 | rem.0[2] >> 63,
+            ]);
+            if rem >= rhs {
+                rem = rem.wrapping_sub(rhs);
+                quotient.0[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+        Some((quotient, rem))
+    }
+
+    /// Remainder of the 512-bit value `high:low` by a nonzero `modulus`,
+    /// by binary long division
+    pub(crate) fn wide_rem(high: Self, low: Self, modulus: Self) -> Self {
+        let mut rem = Self::ZERO;
+        for limb in high.0.iter().rev().chain(low.0.iter().rev()) {
+            for bit in (0..64).rev() {
+                // Shift the next bit in; a bit shifted out of the top means
+                // the true remainder is at least 2^256 > modulus
+                let overflow = rem.0[3] >> 63 == 1;
+                rem = Self([
+                    rem.0[0] << 1 | (limb >> bit) & 1,
+                    rem.0[1] << 1 | rem.0[0] >> 63,
+                    rem.0[2] << 1 | rem.0[1] >> 63,
+                    rem.0[3] << 1 | rem.0[2] >> 63,
+                ]);
+                if overflow || rem >= modulus {
+                    rem = rem.wrapping_sub(modulus);
+                }
+            }
+        }
+        rem
+    }
+
+    /// Whether the two's-complement sign bit is set
+    #[inline]
+    pub fn is_negative(&self) -> bool {
+        self.0[3] >> 63 == 1
+    }
+
+    /// Compare as two's-complement signed integers
+    pub fn signed_cmp(&self, other: &Self) -> std::cmp::Ordering {
+        match (self.is_negative(), other.is_negative()) {
+            (true, false) => std::cmp::Ordering::Less,
+            (false, true) => std::cmp::Ordering::Greater,
+            _ => self.cmp(other),
+        }
+    }
+
     /// Convert to usize (truncating)
     #[inline]
     pub fn as_usize(&self) -> usize {
@@ -80,6 +208,31 @@ impl U256 {
     }
 }
 
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Hex without leading zeros; `{:#x}` adds the `0x` prefix
+impl fmt::LowerHex for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self.to_be_bytes();
+        let first = bytes.iter().position(|&b| b != 0).unwrap_or(31);
+        let mut digits = format!("{:x}", bytes[first]);
+        for byte in &bytes[first + 1..] {
+            digits.push_str(&format!("{byte:02x}"));
+        }
+        f.pad_integral(true, "0x", &digits)
+    }
+}
+
 impl From<u64> for U256 {
     fn from(v: u64) -> Self {
         Self([v, 0, 0, 0])
@@ -105,6 +258,19 @@ impl Address {
         addr[20 - len..].copy_from_slice(&slice[..len]);
         Self(addr)
     }
+
+    /// Take the low 20 bytes of a stack word, as CALL does
+    pub fn from_word(word: U256) -> Self {
+        Self::from_slice(&word.to_be_bytes()[12..])
+    }
+}
+
+/// All 40 hex digits; `{:#x}` adds the `0x` prefix
+impl fmt::LowerHex for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits: String = self.0.iter().map(|b| format!("{b:02x}")).collect();
+        f.pad_integral(true, "0x", &digits)
+    }
 }
 
 /// Block context providing deterministic environmental inputs.
@@ -163,6 +329,82 @@ mod tests {
         assert_eq!(c.as_u64(), 200);
     }
 
+    #[test]
+    fn test_u256_ordering() {
+        let small = U256([u64::MAX, 0, 0, 0]);
+        let large = U256([0, 1, 0, 0]);
+        assert!(small < large);
+        assert!(U256::MAX > large);
+    }
+
+    /// `(a * b) % m` for a modulus that fits in 64 bits, reducing each
+    /// operand limb by limb in u128
+    fn reference_mul_mod(a: U256, b: U256, m: u64) -> U256 {
+        let reduce = |x: U256| x.0.iter().rev().fold(0u128, |acc, &limb| ((acc << 64) | limb as u128) % m as u128);
+        U256::from((reduce(a) * reduce(b) % m as u128) as u64)
+    }
+
+    #[test]
+    fn test_mul_mod_uses_full_product() {
+        let big = U256([0x0123_4567_89AB_CDEF, u64::MAX, 42, 1 << 63]);
+        for (a, b, m) in [(U256::MAX, U256::MAX, 7), (big, U256::MAX, 1_000_000_007), (big, big, u64::MAX)] {
+            assert_eq!(a.mul_mod(b, U256::from(m)), reference_mul_mod(a, b, m));
+        }
+        assert_eq!(U256::MAX.mul_mod(U256::MAX, U256::from(7u64)), U256::ONE);
+
+        // 2^255 * 4 = 2^257, and 2^256 leaves 1 modulo 2^256 - 1
+        let top = U256([0, 0, 0, 1 << 63]);
+        assert_eq!(top.mul_mod(U256::from(4u64), U256::MAX), U256::from(2u64));
+        assert_eq!(U256::MAX.full_mul(U256::MAX), (U256::MAX.wrapping_sub(U256::ONE), U256::ONE));
+        assert_eq!(U256::MAX.mul_mod(U256::MAX, U256::ZERO), U256::ZERO);
+    }
+
+    #[test]
+    fn test_checked_div_rem() {
+        let big = U256([5, 0, 0, 1 << 63]);
+        let divisor = U256([0, 1, 0, 0]);
+        assert_eq!(big.checked_div_rem(divisor), Some((U256([0, 0, 1 << 63, 0]), U256::from(5u64))));
+        assert_eq!(U256::MAX.checked_div_rem(U256::MAX), Some((U256::ONE, U256::ZERO)));
+        assert_eq!(U256::from(7u64).checked_div_rem(U256::from(2u64)), Some((U256::from(3u64), U256::ONE)));
+        assert_eq!(U256::ONE.checked_div_rem(U256::ZERO), None);
+    }
+
+    #[test]
+    fn test_add_mod_keeps_the_carry() {
+        // 2 * (2^256 - 1) = 2^257 - 2, and 2^256 leaves 1 modulo 5
+        assert_eq!(U256::MAX.overflowing_add(U256::MAX), (U256::MAX.wrapping_sub(U256::ONE), true));
+        assert_eq!(U256::MAX.add_mod(U256::MAX, U256::from(5u64)), U256::ZERO);
+        let reference = |a: U256, b: U256, m: u64| {
+            let reduce = |x: U256| x.0.iter().rev().fold(0u128, |acc, &limb| ((acc << 64) | limb as u128) % m as u128);
+            U256::from(((reduce(a) + reduce(b)) % m as u128) as u64)
+        };
+        let big = U256([7, 0, 0, 1 << 63]);
+        for (a, b, m) in [(U256::MAX, U256::MAX, 5), (big, big, 1_000_000_007), (U256::from(10u64), U256::from(20u64), 7)] {
+            assert_eq!(a.add_mod(b, U256::from(m)), reference(a, b, m));
+        }
+        assert_eq!(U256::from(10u64).add_mod(U256::from(20u64), U256::from(7u64)), U256::from(2u64));
+        assert_eq!(U256::MAX.add_mod(U256::ONE, U256::MAX), U256::ONE);
+        assert_eq!(U256::ONE.add_mod(U256::ONE, U256::ZERO), U256::ZERO);
+    }
+
+    #[test]
+    fn test_from_hex_round_trips() {
+        for value in [U256::ZERO, U256::from(0x2Au64), U256([1, 2, 3, 4]), U256::MAX] {
+            assert_eq!(U256::from_hex(&format!("{value:#x}")), Some(value));
+        }
+        assert_eq!(U256::from_hex("ff"), Some(U256::from(0xFFu64)));
+        assert_eq!(U256::from_hex("0x"), None);
+        assert_eq!(U256::from_hex("0xzz"), None);
+        assert_eq!(U256::from_hex(&"1".repeat(65)), None);
+    }
+
+    #[test]
+    fn test_hex_formatting() {
+        assert_eq!(format!("{:#x}", U256::ZERO), "0x0");
+        assert_eq!(format!("{:x}", U256([0, 1, 0, 0])), "10000000000000000");
+        assert_eq!(format!("{:#x}", Address::from_slice(&[0xCC])), format!("0x{}cc", "0".repeat(38)));
+    }
+
     #[test]
     fn test_u256_bytes_roundtrip() {
         let original = U256([0x1234_5678_9abc_def0, 0xfedcba9876543210, 0, 0]);
@@ -0,0 +1,87 @@
+//! Decoding of statically-typed ABI values
+
+use crate::core::{Address, U256};
+
+/// Static ABI types, each occupying one 32-byte word
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AbiType {
+    Uint256,
+    Address,
+    Bool,
+    Bytes32,
+}
+
+/// A decoded ABI value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AbiValue {
+    Uint256(U256),
+    Address(Address),
+    Bool(bool),
+    Bytes32([u8; 32]),
+}
+
+impl AbiValue {
+    /// Decode one word as `ty`
+    pub fn decode(ty: AbiType, word: [u8; 32]) -> Self {
+        match ty {
+            AbiType::Uint256 => Self::Uint256(U256::from_be_bytes(word)),
+            AbiType::Address => Self::Address(Address::from_slice(&word[12..])),
+            AbiType::Bool => Self::Bool(word.iter().any(|&b| b != 0)),
+            AbiType::Bytes32 => Self::Bytes32(word),
+        }
+    }
+}
+
+/// Every whole word of `data` as a `uint256`; a trailing partial word is ignored
+pub fn decode_uint256_array(data: &[u8]) -> Vec<U256> {
+    data.chunks_exact(32)
+        .map(|chunk| U256::from_be_bytes(chunk.try_into().expect("chunks are 32 bytes")))
+        .collect()
+}
+
+/// Address in word `word` of `data`; bytes past the end read as zero
+pub fn decode_address(data: &[u8], word: usize) -> Address {
+    let mut bytes = [0u8; 32];
+    let start = word.saturating_mul(32);
+    if let Some(tail) = data.get(start..) {
+        let len = tail.len().min(32);
+        bytes[..len].copy_from_slice(&tail[..len]);
+    }
+    Address::from_slice(&bytes[12..])
+}
+
+/// Decode consecutive words of `data` as `types`, stopping at the first
+/// type whose word is missing
+pub fn decode_static(data: &[u8], types: &[AbiType]) -> Vec<AbiValue> {
+    types
+        .iter()
+        .zip(data.chunks_exact(32))
+        .map(|(&ty, chunk)| AbiValue::decode(ty, chunk.try_into().expect("chunks are 32 bytes")))
+        .collect()
+}
+
+/// ABI word at byte `offset` of `args`
+pub(super) fn word_at(args: &[u8], offset: usize) -> Option<U256> {
+    let word: [u8; 32] = args.get(offset..offset.checked_add(32)?)?.try_into().ok()?;
+    Some(U256::from_be_bytes(word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_static_words() {
+        let mut data = U256::from(7u64).to_be_bytes().to_vec();
+        data.extend_from_slice(&U256::from(0xCAFEu64).to_be_bytes());
+        data.push(0xFF);
+
+        assert_eq!(decode_uint256_array(&data), vec![U256::from(7u64), U256::from(0xCAFEu64)]);
+        assert_eq!(decode_address(&data, 1), Address::from_slice(&[0xCA, 0xFE]));
+        assert_eq!(decode_address(&data, 5), Address::ZERO);
+        assert_eq!(
+            decode_static(&data, &[AbiType::Bool, AbiType::Address, AbiType::Uint256]),
+            vec![AbiValue::Bool(true), AbiValue::Address(Address::from_slice(&[0xCA, 0xFE]))]
+        );
+    }
+}
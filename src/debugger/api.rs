@@ -1,8 +1,16 @@
 //! Time-travel debugger API
 
-use crate::core::{U256, VmResult, HaltReason};
-use crate::vm::Vm;
-use crate::executor::{StepResult, Opcode};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::bytecode::{decode_instruction, DecodedInstruction};
+use crate::core::{U256, VmError, VmResult, HaltReason};
+use crate::vm::{LogRecord, Memory, Storage, Vm};
+use crate::executor::{StepResult, ExecutionResult, Opcode, OpcodeCategory};
+use crate::debugger::{decode_static, AbiType, AbiValue, RevertInfo};
+use crate::journal::{InstructionJournal, JournalEntry};
 
 /// Unique identifier for a breakpoint
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -13,10 +21,19 @@ pub struct BreakpointId(pub usize);
 pub enum Breakpoint {
     Address(usize),
     Opcode(u8),
+    /// Any opcode of the category, e.g. every storage access
+    Category(OpcodeCategory),
+    /// Any instruction whose `peek_gas_cost` is at least this much
+    ExpensiveOp(u64),
     StorageAccess(U256),
     GasBelow(u64),
     MemoryAccess { start: usize, end: usize },
+    /// Edge-triggered: fires once when the position reaches `n`,
+    /// and re-arms when a rewind takes it back below `n`.
     AfterInstructions(usize),
+    /// Fires when the last instruction set return data equal to this value,
+    /// e.g. a subcall returning it
+    ReturnDataEquals(Vec<u8>),
 }
 
 /// Reason execution stopped
@@ -26,14 +43,59 @@ pub enum StopReason {
     Halt(HaltReason),
     UserStop,
     ReachedBeginning,
+    /// The state hash matched the requested one
+    StateHashReached,
+    /// The step cap ran out first
+    StepLimit,
+    /// Execution came back to the same JUMPDEST in the same state without
+    /// touching storage or logs in between
+    LoopDetected { pc: usize },
+    /// Rewinding undid an instruction with the requested opcode
+    OpcodeReached { pc: usize },
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Breakpoint(id) => write!(f, "breakpoint #{}", id.0),
+            Self::Halt(reason) => write!(f, "halted: {reason}"),
+            Self::UserStop => write!(f, "stopped by user"),
+            Self::ReachedBeginning => write!(f, "reached the beginning"),
+            Self::StateHashReached => write!(f, "reached the target state hash"),
+            Self::StepLimit => write!(f, "step limit reached"),
+            Self::LoopDetected { pc } => write!(f, "loop detected at {pc:#06x}"),
+            Self::OpcodeReached { pc } => write!(f, "reached opcode at {pc:#06x}"),
+        }
+    }
 }
 
+/// What a step hook wants `run_forward` to do next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlDirective {
+    Continue,
+    Stop,
+}
+
+/// Called by `run_forward` after each instruction it executes
+pub type StepHook = Box<dyn FnMut(&Vm) -> ControlDirective + Send>;
+
 /// Time-travel debugger wrapping a VM
 pub struct TimeTravel {
     vm: Vm,
     breakpoints: Vec<(BreakpointId, Breakpoint)>,
     next_breakpoint_id: usize,
-    instruction_count: usize,
+    /// Edge-triggered breakpoints that have fired and not yet re-armed
+    tripped: HashSet<BreakpointId>,
+    /// Terminal result, cached when execution halts
+    result: Option<ExecutionResult>,
+    /// Steps within which a repeated loop state stops `run_forward`
+    loop_window: Option<usize>,
+    /// Position each loop state was last seen at during this run
+    loop_states: HashMap<u64, usize>,
+    /// Position a run last stopped at on a breakpoint; the next run leaves
+    /// it before checking breakpoints again
+    breakpoint_stop: Option<usize>,
+    step_hook: Option<StepHook>,
 }
 
 impl TimeTravel {
@@ -42,58 +104,143 @@ impl TimeTravel {
             vm,
             breakpoints: Vec::new(),
             next_breakpoint_id: 0,
-            instruction_count: 0,
+            tripped: HashSet::new(),
+            result: None,
+            loop_window: None,
+            loop_states: HashMap::new(),
+            breakpoint_stop: None,
+            step_hook: None,
         }
     }
 
     pub fn step_forward(&mut self) -> VmResult<StepResult> {
+        self.result = None;
         let result = self.vm.step_forward()?;
-        if matches!(result, StepResult::Executed { .. }) {
-            self.instruction_count += 1;
+        if let StepResult::Halted { reason } = &result {
+            self.record_halt(reason.clone());
         }
         Ok(result)
     }
 
     pub fn step_backward(&mut self) -> VmResult<StepResult> {
+        self.result = None;
         let result = self.vm.step_backward()?;
         if matches!(result, StepResult::Rewound { .. }) {
-            self.instruction_count = self.instruction_count.saturating_sub(1);
+            self.rearm_breakpoints();
         }
         Ok(result)
     }
 
     pub fn rewind(&mut self, n: usize) -> VmResult<usize> {
+        self.result = None;
         let rewound = self.vm.rewind(n)?;
-        self.instruction_count = self.instruction_count.saturating_sub(rewound);
+        self.rearm_breakpoints();
         Ok(rewound)
     }
 
+    /// Run until a breakpoint, a halt, a detected loop or the step hook
+    /// asking to stop. Resuming from a
+    /// breakpoint stop executes the current instruction first, so the same
+    /// breakpoint can't stop the run again in place.
     pub fn run_forward(&mut self) -> VmResult<StopReason> {
+        self.loop_states.clear();
+        let mut resuming = self.resuming_from_breakpoint();
         loop {
-            if let Some(bp_id) = self.check_breakpoints() {
+            if !std::mem::take(&mut resuming)
+                && let Some(bp_id) = self.check_breakpoints()
+            {
+                self.breakpoint_stop = Some(self.position());
                 return Ok(StopReason::Breakpoint(bp_id));
             }
-            match self.vm.step_forward()? {
-                StepResult::Halted { reason } => return Ok(StopReason::Halt(reason)),
-                StepResult::Executed { .. } => self.instruction_count += 1,
-                _ => {}
+            if let Some(pc) = self.check_loop() {
+                return Ok(StopReason::LoopDetected { pc });
+            }
+            self.result = None;
+            if let StepResult::Halted { reason } = self.vm.step_forward()? {
+                self.record_halt(reason.clone());
+                return Ok(StopReason::Halt(reason));
+            }
+            if let Some(hook) = &mut self.step_hook
+                && hook(&self.vm) == ControlDirective::Stop
+            {
+                return Ok(StopReason::UserStop);
             }
         }
     }
 
+    /// Rewind until a breakpoint or the beginning, first leaving the
+    /// position of a breakpoint stop like `run_forward`
     pub fn run_backward(&mut self) -> VmResult<StopReason> {
+        self.result = None;
+        let mut resuming = self.resuming_from_breakpoint();
         loop {
             if self.vm.journal().is_empty() {
                 return Ok(StopReason::ReachedBeginning);
             }
-            if let Some(bp_id) = self.check_breakpoints() {
+            if !std::mem::take(&mut resuming)
+                && let Some(bp_id) = self.check_breakpoints()
+            {
+                self.breakpoint_stop = Some(self.position());
                 return Ok(StopReason::Breakpoint(bp_id));
             }
-            match self.vm.step_backward()? {
-                StepResult::Rewound { .. } => {
-                    self.instruction_count = self.instruction_count.saturating_sub(1);
-                }
-                _ => {}
+            if let StepResult::Rewound { .. } = self.vm.step_backward()? {
+                self.rearm_breakpoints();
+            }
+        }
+    }
+
+    /// Rewind until an instruction with opcode `op` has been undone, leaving
+    /// the VM just before it, or until the history runs out
+    pub fn rewind_to_opcode(&mut self, op: Opcode) -> VmResult<StopReason> {
+        self.result = None;
+        while !self.vm.journal().is_empty() {
+            let StepResult::Rewound { opcode, pc, .. } = self.vm.step_backward()? else {
+                continue;
+            };
+            self.rearm_breakpoints();
+            if opcode == op {
+                return Ok(StopReason::OpcodeReached { pc });
+            }
+        }
+        Ok(StopReason::ReachedBeginning)
+    }
+
+    /// Step forward until the state hash equals `target`, execution halts or
+    /// `max_steps` instructions have run. Stops at once if the current state
+    /// already matches.
+    pub fn run_to_state_hash(&mut self, target: [u8; 32], max_steps: usize) -> VmResult<StopReason> {
+        for _ in 0..max_steps {
+            if self.state_hash() == target {
+                return Ok(StopReason::StateHashReached);
+            }
+            if let StepResult::Halted { reason } = self.step_forward()? {
+                return Ok(StopReason::Halt(reason));
+            }
+        }
+        if self.state_hash() == target {
+            return Ok(StopReason::StateHashReached);
+        }
+        Ok(StopReason::StepLimit)
+    }
+
+    /// Step until an instruction does something beyond moving the pc and
+    /// charging gas, so JUMPDEST and the like are passed over, or until
+    /// execution halts. Returns how many instructions ran.
+    pub fn step_forward_significant(&mut self) -> VmResult<usize> {
+        let mut stepped = 0;
+        loop {
+            let result = self.step_forward()?;
+            stepped += 1;
+            if matches!(result, StepResult::Halted { .. }) {
+                return Ok(stepped);
+            }
+            let significant = self.last_instruction_entries().is_some_and(|entries| {
+                entries
+                    .iter()
+                    .any(|entry| !matches!(entry, JournalEntry::PcChange { .. } | JournalEntry::GasChange { .. }))
+            });
+            if significant {
+                return Ok(stepped);
             }
         }
     }
@@ -110,27 +257,165 @@ impl TimeTravel {
         Ok(stepped)
     }
 
+    /// Whether execution has halted at the current position
+    pub fn is_finished(&self) -> bool {
+        self.result.is_some()
+    }
+
+    /// Whether there is no history to rewind into
+    pub fn at_start(&self) -> bool {
+        self.vm.journal().is_empty()
+    }
+
+    /// Whether execution has halted, or the root call has run off the end
+    /// of its code
+    pub fn at_end(&self) -> bool {
+        self.is_finished() || (self.call_depth() == 0 && self.inspect_pc() >= self.vm.bytecode().len())
+    }
+
+    pub fn can_step_forward(&self) -> bool {
+        !self.at_end()
+    }
+
+    pub fn can_step_backward(&self) -> bool {
+        !self.at_start()
+    }
+
+    /// Final result of the execution, if it halted at the current position
+    pub fn result(&self) -> Option<ExecutionResult> {
+        self.result.clone()
+    }
+
+    /// Bytes produced by the RETURN or REVERT execution halted on; empty
+    /// before a halt and for any other halt
+    pub fn return_data(&self) -> &[u8] {
+        match &self.result {
+            Some(ExecutionResult::Success { return_data, .. } | ExecutionResult::Revert { return_data, .. }) => {
+                return_data
+            }
+            _ => &[],
+        }
+    }
+
+    /// Rerun the whole execution from the start with root storage `slot`
+    /// initially holding `value`, returning how it ends. The replay runs on
+    /// a copy, so this session is left untouched. Fails with
+    /// `JournalExhausted` if the history no longer reaches the start.
+    pub fn replay_with_storage_override(&self, slot: U256, value: U256) -> VmResult<ExecutionResult> {
+        let mut vm = self.vm.clone();
+        vm.rewind_to(0)?;
+        let mut slots = vm.state().storage.snapshot();
+        slots.insert(slot, value);
+        vm.state_mut().storage = Storage::with_state(slots);
+        vm.run()
+    }
+
+    /// Decode the revert reason when execution halted in a REVERT
+    pub fn explain_revert(&self) -> Option<RevertInfo> {
+        match &self.result {
+            Some(ExecutionResult::Revert { return_data, .. }) => Some(RevertInfo::decode(return_data)),
+            _ => None,
+        }
+    }
+
+    /// Decode the data of a successful halt as consecutive static values,
+    /// stopping where the data runs out; empty unless execution returned
+    pub fn decode_return(&self, types: &[AbiType]) -> Vec<AbiValue> {
+        match &self.result {
+            Some(ExecutionResult::Success { return_data, .. }) => decode_static(return_data, types),
+            _ => Vec::new(),
+        }
+    }
+
+    fn record_halt(&mut self, reason: HaltReason) {
+        self.result = Some(ExecutionResult::from_halt(reason, self.vm.gas_used()));
+    }
+
     // ==================== Inspection ====================
 
+    /// Whole stack, bottom first (the top of the stack is the last element)
     pub fn inspect_stack(&self) -> &[U256] {
         self.vm.state().stack.as_slice()
     }
 
+    /// Up to `n` of the topmost stack items, top first, without allocating
+    pub fn stack_top(&self, n: usize) -> impl ExactSizeIterator<Item = &U256> {
+        let stack = self.inspect_stack();
+        stack[stack.len().saturating_sub(n)..].iter().rev()
+    }
+
     pub fn inspect_memory(&self, offset: usize, len: usize) -> Vec<u8> {
-        // Create a mutable copy for reading
-        let mut result = vec![0u8; len];
+        // Read without modifying - access internal state
         let mem = &self.vm.state().memory;
-        for i in 0..len {
-            // Read without modifying - access internal state
-            result[i] = mem.peek_byte(offset + i);
+        (0..len).map(|i| mem.peek_byte(offset + i)).collect()
+    }
+
+    /// The word an MLOAD at `offset` would push, read without expanding
+    /// memory; bytes past the end read as zero
+    pub fn inspect_memory_word(&self, offset: usize) -> U256 {
+        let mem = &self.vm.state().memory;
+        let mut word = [0u8; 32];
+        for (i, byte) in word.iter_mut().enumerate() {
+            *byte = offset.checked_add(i).map_or(0, |at| mem.peek_byte(at));
         }
-        result
+        U256::from_be_bytes(word)
     }
 
     pub fn inspect_storage(&self, key: &U256) -> U256 {
         self.vm.state().storage.get(key)
     }
 
+    /// Root storage slots written in the recorded history with their current
+    /// values, ordered by slot; preloaded slots never written are left out
+    pub fn touched_storage(&self) -> Vec<(U256, U256)> {
+        let root = self.vm.address();
+        let slots: BTreeSet<U256> = self
+            .history()
+            .flat_map(|insn| &insn.entries)
+            .filter_map(|entry| match entry {
+                JournalEntry::StorageWrite { address, key, .. } if *address == root => Some(*key),
+                _ => None,
+            })
+            .collect();
+        slots.into_iter().map(|slot| (slot, self.inspect_storage(&slot))).collect()
+    }
+
+    /// Value of a root storage slot before the current transaction wrote it
+    pub fn storage_original(&self, slot: &U256) -> U256 {
+        self.vm.state().storage.get_original(slot)
+    }
+
+    /// Whether a root storage slot differs from its original value
+    pub fn storage_is_dirty(&self, slot: &U256) -> bool {
+        self.inspect_storage(slot) != self.storage_original(slot)
+    }
+
+    /// Logs emitted up to the current position, oldest first
+    pub fn inspect_logs(&self) -> &[LogRecord] {
+        &self.vm.state().logs
+    }
+
+    /// Logs as a JSON array in emission order, each with its index, e.g.
+    /// `{"index":0,"address":"0x…","topics":["0x…"],"data":"0x…"}`.
+    /// Topics are written as full 32-byte words.
+    pub fn logs_json(&self) -> String {
+        let entries: Vec<String> = self
+            .inspect_logs()
+            .iter()
+            .enumerate()
+            .map(|(index, log)| {
+                let topics: Vec<String> = log.topics.iter().map(|topic| format!("\"{topic:#066x}\"")).collect();
+                let data: String = log.data.iter().map(|byte| format!("{byte:02x}")).collect();
+                format!(
+                    r#"{{"index":{index},"address":"{:#x}","topics":[{}],"data":"0x{data}"}}"#,
+                    log.address,
+                    topics.join(","),
+                )
+            })
+            .collect();
+        format!("[{}]", entries.join(","))
+    }
+
     pub fn inspect_pc(&self) -> usize {
         self.vm.state().pc
     }
@@ -139,6 +424,22 @@ impl TimeTravel {
         self.vm.state().gas
     }
 
+    /// Gas consumed since the start of execution, including gas forwarded
+    /// to calls that are still running
+    pub fn gas_used(&self) -> u64 {
+        self.vm.gas_used()
+    }
+
+    /// Call data of the active call frame
+    pub fn inspect_calldata(&self) -> &[u8] {
+        self.vm.active_calldata()
+    }
+
+    /// Value sent to the active call frame
+    pub fn inspect_call_value(&self) -> U256 {
+        self.vm.active_call_value()
+    }
+
     pub fn current_opcode(&self) -> Option<Opcode> {
         let pc = self.vm.state().pc;
         let bytecode = self.vm.bytecode();
@@ -149,12 +450,105 @@ impl TimeTravel {
         }
     }
 
+    /// The instruction at the current pc, decoded; `None` past the end of
+    /// the code or on a byte that isn't an opcode
+    pub fn current_instruction(&self) -> Option<DecodedInstruction> {
+        decode_instruction(self.vm.bytecode(), self.vm.state().pc)
+    }
+
+    /// Gas the next instruction will cost, without executing it: the base
+    /// cost plus per-byte LOG data gas, per-word copy gas and memory
+    /// expansion for every opcode that touches memory, CALL's argument and
+    /// return regions included. Gas a CALL forwards isn't counted; INVALID
+    /// and undecodable bytes cost all remaining gas.
+    pub fn peek_gas_cost(&self) -> VmResult<u64> {
+        let state = self.vm.state();
+        let Some(&byte) = self.vm.bytecode().get(state.pc) else {
+            return Ok(0);
+        };
+        let Some(opcode) = Opcode::from_u8(byte) else {
+            return Ok(if self.vm.gas_metering() { state.gas } else { 0 });
+        };
+        let required = opcode.stack_inputs();
+        if state.stack.len() < required {
+            return Err(VmError::StackUnderflow { required, available: state.stack.len() });
+        }
+        if opcode == Opcode::Invalid && self.vm.gas_metering() {
+            return Ok(state.gas);
+        }
+        if !self.vm.charges_gas(opcode) {
+            return Ok(0);
+        }
+
+        let stack = |depth| state.stack.peek(depth);
+        let copy_gas = |dest: U256, size: U256| -> VmResult<u64> {
+            let words = Memory::checked_range(dest, size)?.1.div_ceil(32) as u64;
+            Ok(words.saturating_mul(3).saturating_add(self.expansion_cost(dest, size)?))
+        };
+        let dynamic = match opcode {
+            Opcode::MLoad | Opcode::MStore => self.expansion_cost(stack(0)?, U256::from(32u64))?,
+            Opcode::MStore8 => self.expansion_cost(stack(0)?, U256::ONE)?,
+            Opcode::CallDataCopy | Opcode::CodeCopy | Opcode::ReturnDataCopy => copy_gas(stack(0)?, stack(2)?)?,
+            Opcode::ExtCodeCopy => copy_gas(stack(1)?, stack(3)?)?,
+            Opcode::Return | Opcode::Revert => self.expansion_cost(stack(0)?, stack(1)?)?,
+            Opcode::Call | Opcode::CallCode => {
+                self.call_expansion_cost([(stack(3)?, stack(4)?), (stack(5)?, stack(6)?)])?
+            }
+            Opcode::DelegateCall => self.call_expansion_cost([(stack(2)?, stack(3)?), (stack(4)?, stack(5)?)])?,
+            op if op.is_log() => {
+                let size = Memory::checked_range(stack(0)?, stack(1)?)?.1;
+                (size as u64).saturating_mul(8).saturating_add(self.expansion_cost(stack(0)?, stack(1)?)?)
+            }
+            _ => 0,
+        };
+        let access = self.vm.access_surcharge(opcode)?;
+        Ok(self.vm.gas_schedule().cost(opcode).saturating_add(access).saturating_add(dynamic))
+    }
+
+    /// Gas to grow memory over `offset..offset + size` from its current size
+    fn expansion_cost(&self, offset: U256, size: U256) -> VmResult<u64> {
+        let (start, len) = Memory::checked_range(offset, size)?;
+        if len == 0 {
+            return Ok(0);
+        }
+        Ok(Memory::expansion_cost(self.vm.state().memory.size(), start + len))
+    }
+
+    /// Gas to grow memory over both the argument and return regions of a call
+    fn call_expansion_cost(&self, regions: [(U256, U256); 2]) -> VmResult<u64> {
+        let mut end = 0;
+        for (offset, size) in regions {
+            let (start, len) = Memory::checked_range(offset, size)?;
+            if len > 0 {
+                end = end.max(start + len);
+            }
+        }
+        Ok(Memory::expansion_cost(self.vm.state().memory.size(), end))
+    }
+
+    /// Recorded instructions, oldest first
+    pub fn history(&self) -> impl Iterator<Item = &InstructionJournal> {
+        self.vm.journal().iter()
+    }
+
+    /// State changes of the most recently recorded instruction
+    pub fn last_instruction_entries(&self) -> Option<&[JournalEntry]> {
+        self.vm.journal().iter().last().map(|insn| insn.entries.as_slice())
+    }
+
     pub fn history_len(&self) -> usize {
         self.vm.journal().len()
     }
 
+    /// Index of the current position in the recorded history: the number
+    /// of instructions executed to get here, halting ones included
+    pub fn position(&self) -> usize {
+        self.vm.journal().end_index()
+    }
+
+    #[deprecated(note = "use `position`, which also counts the halting instruction")]
     pub fn instruction_count(&self) -> usize {
-        self.instruction_count
+        self.position()
     }
 
     pub fn memory_size(&self) -> usize {
@@ -174,9 +568,15 @@ impl TimeTravel {
         id
     }
 
+    /// Add several breakpoints, returning their ids in the same order
+    pub fn add_breakpoints(&mut self, bps: Vec<Breakpoint>) -> Vec<BreakpointId> {
+        bps.into_iter().map(|bp| self.add_breakpoint(bp)).collect()
+    }
+
     pub fn remove_breakpoint(&mut self, id: BreakpointId) -> bool {
         let len_before = self.breakpoints.len();
         self.breakpoints.retain(|(bp_id, _)| *bp_id != id);
+        self.tripped.remove(&id);
         self.breakpoints.len() < len_before
     }
 
@@ -186,27 +586,159 @@ impl TimeTravel {
 
     pub fn clear_breakpoints(&mut self) {
         self.breakpoints.clear();
+        self.tripped.clear();
+    }
+
+    /// Copy of the breakpoint list, for saving and later `restore_breakpoints`
+    pub fn breakpoints_snapshot(&self) -> Vec<(BreakpointId, Breakpoint)> {
+        self.breakpoints.clone()
+    }
+
+    /// Replace the breakpoint list with a saved one, keeping its ids.
+    /// Breakpoints added afterwards get ids past every restored one.
+    pub fn restore_breakpoints(&mut self, saved: Vec<(BreakpointId, Breakpoint)>) {
+        let next = saved.iter().map(|(id, _)| id.0 + 1).max().unwrap_or(0);
+        self.next_breakpoint_id = self.next_breakpoint_id.max(next);
+        self.breakpoints = saved;
+        self.tripped.clear();
+        self.rearm_breakpoints();
+    }
+
+    fn check_breakpoints(&mut self) -> Option<BreakpointId> {
+        let (id, bp) = self.breakpoints.iter().find(|(id, bp)| self.breakpoint_matches(*id, bp))?;
+        if matches!(bp, Breakpoint::AfterInstructions(_)) {
+            self.tripped.insert(*id);
+        }
+        Some(*id)
     }
 
-    fn check_breakpoints(&self) -> Option<BreakpointId> {
+    /// Every breakpoint that would stop a run at the current position, in
+    /// the order they were added. Nothing is tripped by asking.
+    pub fn matching_breakpoints(&self) -> Vec<BreakpointId> {
+        self.breakpoints
+            .iter()
+            .filter(|(id, bp)| self.breakpoint_matches(*id, bp))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    fn breakpoint_matches(&self, id: BreakpointId, bp: &Breakpoint) -> bool {
         let pc = self.vm.state().pc;
-        let gas = self.vm.state().gas;
-
-        for (id, bp) in &self.breakpoints {
-            let matches = match bp {
-                Breakpoint::Address(addr) => pc == *addr,
-                Breakpoint::Opcode(op) => self.vm.bytecode().get(pc).copied() == Some(*op),
-                Breakpoint::GasBelow(threshold) => gas < *threshold,
-                Breakpoint::AfterInstructions(n) => self.instruction_count >= *n,
-                Breakpoint::StorageAccess(_) | Breakpoint::MemoryAccess { .. } => false,
-            };
-            if matches {
-                return Some(*id);
+        match bp {
+            Breakpoint::Address(addr) => pc == *addr,
+            Breakpoint::Opcode(op) => self.vm.bytecode().get(pc).copied() == Some(*op),
+            Breakpoint::Category(category) => self
+                .vm
+                .bytecode()
+                .get(pc)
+                .and_then(|&byte| Opcode::from_u8(byte))
+                .is_some_and(|op| op.category() == *category),
+            Breakpoint::GasBelow(threshold) => self.vm.state().gas < *threshold,
+            Breakpoint::ExpensiveOp(threshold) => self.peek_gas_cost().is_ok_and(|cost| cost >= *threshold),
+            Breakpoint::AfterInstructions(n) => self.position() == *n && !self.tripped.contains(&id),
+            Breakpoint::ReturnDataEquals(data) => {
+                self.vm.state().return_data == *data && self.last_set_return_data()
             }
+            Breakpoint::StorageAccess(_) | Breakpoint::MemoryAccess { .. } => false,
+        }
+    }
+
+    /// Stop `run_forward` when a JUMPDEST is reached again within `window`
+    /// steps with the same stack and memory and no storage write or log in
+    /// between. Off by default; `None` turns it off again.
+    pub fn set_loop_detection(&mut self, window: Option<usize>) {
+        self.loop_window = window;
+        self.loop_states.clear();
+    }
+
+    /// Call `hook` with the VM after every instruction `run_forward`
+    /// executes; returning `Stop` ends the run with `StopReason::UserStop`
+    pub fn set_step_hook(&mut self, hook: impl FnMut(&Vm) -> ControlDirective + Send + 'static) {
+        self.step_hook = Some(Box::new(hook));
+    }
+
+    /// Remove the step hook set by `set_step_hook`
+    pub fn clear_step_hook(&mut self) {
+        self.step_hook = None;
+    }
+
+    /// Pc of a detected zero-progress loop at the current position
+    fn check_loop(&mut self) -> Option<usize> {
+        let window = self.loop_window?;
+        let made_progress = self.vm.journal().peek().is_some_and(|insn| {
+            insn.entries.iter().any(|entry| {
+                matches!(entry, JournalEntry::StorageWrite { .. } | JournalEntry::LogEmitted { .. })
+            })
+        });
+        if made_progress {
+            self.loop_states.clear();
+        }
+        if self.current_opcode() != Some(Opcode::JumpDest) {
+            return None;
+        }
+
+        let position = self.position();
+        let key = self.loop_key();
+        if let Some(seen) = self.loop_states.insert(key, position)
+            && position.saturating_sub(seen) <= window
+        {
+            return Some(self.inspect_pc());
+        }
+        if self.loop_states.len() > window {
+            self.loop_states.retain(|_, seen| position.saturating_sub(*seen) <= window);
         }
         None
     }
 
+    /// Hash of everything but gas that a loop iteration could change
+    /// without storage or logs
+    fn loop_key(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        let state = self.vm.state();
+        state.pc.hash(&mut hasher);
+        state.call_depth.hash(&mut hasher);
+        self.vm.active_address().hash(&mut hasher);
+        state.stack.as_slice().hash(&mut hasher);
+        state.memory.size().hash(&mut hasher);
+        state.memory.digest().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Whether a run is starting where the last one stopped on a breakpoint
+    fn resuming_from_breakpoint(&mut self) -> bool {
+        self.breakpoint_stop.take() == Some(self.position())
+    }
+
+    /// Whether the most recent instruction returned from a call: either a
+    /// frame exited, or a CALL was answered without entering one (a
+    /// precompile). Entering a frame also clears the buffer, which must not
+    /// count as return data arriving.
+    fn last_set_return_data(&self) -> bool {
+        self.vm.journal().peek().is_some_and(|insn| {
+            let has = |pred: fn(&JournalEntry) -> bool| insn.entries.iter().any(pred);
+            let is_call = matches!(
+                Opcode::from_u8(insn.opcode),
+                Some(Opcode::Call | Opcode::CallCode | Opcode::DelegateCall | Opcode::StaticCall)
+            );
+            has(|entry| matches!(entry, JournalEntry::CallExit { .. }))
+                || (is_call
+                    && !has(|entry| matches!(entry, JournalEntry::CallEnter { .. }))
+                    && has(|entry| matches!(entry, JournalEntry::ReturnDataSet { .. })))
+        })
+    }
+
+    /// Re-arm edge-triggered breakpoints whose threshold is ahead of the
+    /// current instruction count again.
+    fn rearm_breakpoints(&mut self) {
+        let count = self.position();
+        let breakpoints = &self.breakpoints;
+        self.tripped.retain(|id| {
+            breakpoints.iter().any(|(bp_id, bp)| {
+                bp_id == id && matches!(bp, Breakpoint::AfterInstructions(n) if count >= *n)
+            })
+        });
+    }
+
     // ==================== Utilities ====================
 
     pub fn vm(&self) -> &Vm {
@@ -217,13 +749,24 @@ impl TimeTravel {
         &mut self.vm
     }
 
+    /// Reset the VM to the start of execution with `gas`. Breakpoints are
+    /// kept, and edge-triggered ones re-arm.
     pub fn reset(&mut self, gas: u64) {
         self.vm.reset(gas);
-        self.instruction_count = 0;
+        self.result = None;
+        self.breakpoint_stop = None;
+        self.rearm_breakpoints();
+    }
+
+    /// Reset the session and install new root call data, reusing the
+    /// VM's bytecode analysis
+    pub fn reset_with_calldata(&mut self, gas: u64, calldata: Vec<u8>) {
+        self.reset(gas);
+        self.vm.set_calldata(calldata);
     }
 
     pub fn state_hash(&self) -> [u8; 32] {
-        self.vm.compute_state_hash()
+        self.vm.state_hash()
     }
 }
 
@@ -249,3 +792,717 @@ impl Debugger for TimeTravel {
     fn add_breakpoint(&mut self, bp: Breakpoint) -> BreakpointId { TimeTravel::add_breakpoint(self, bp) }
     fn remove_breakpoint(&mut self, id: BreakpointId) -> bool { TimeTravel::remove_breakpoint(self, id) }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Address, BlockContext};
+    use crate::vm::Storage;
+
+    fn demo_debugger() -> TimeTravel {
+        // PUSH1 10, PUSH1 20, ADD, PUSH1 0, MSTORE, STOP
+        let bytecode = vec![0x60, 0x0A, 0x60, 0x14, 0x01, 0x60, 0x00, 0x52, 0x00];
+        TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()))
+    }
+
+    #[test]
+    fn test_after_instructions_fires_once() {
+        let mut dbg = demo_debugger();
+        let id = dbg.add_breakpoint(Breakpoint::AfterInstructions(2));
+
+        match dbg.run_forward().unwrap() {
+            StopReason::Breakpoint(hit) => assert_eq!(hit, id),
+            other => panic!("expected breakpoint, got {other:?}"),
+        }
+        assert_eq!(dbg.position(), 2);
+
+        // Resuming must not re-trigger at the same count
+        assert!(matches!(dbg.run_forward().unwrap(), StopReason::Halt(HaltReason::Stop)));
+    }
+
+    #[test]
+    fn test_after_instructions_rearms_on_rewind() {
+        let mut dbg = demo_debugger();
+        let id = dbg.add_breakpoint(Breakpoint::AfterInstructions(2));
+
+        assert!(matches!(dbg.run_forward().unwrap(), StopReason::Breakpoint(_)));
+        dbg.run_forward().unwrap();
+
+        dbg.rewind(dbg.history_len()).unwrap();
+        match dbg.run_forward().unwrap() {
+            StopReason::Breakpoint(hit) => assert_eq!(hit, id),
+            other => panic!("expected breakpoint, got {other:?}"),
+        }
+        assert_eq!(dbg.position(), 2);
+    }
+
+    #[test]
+    fn test_touched_storage_dedupes_slots() {
+        // SSTORE 1 -> slot 7, SSTORE 2 -> slot 7, STOP; slot 9 is preloaded
+        let bytecode = vec![0x60, 0x01, 0x60, 0x07, 0x55, 0x60, 0x02, 0x60, 0x07, 0x55, 0x00];
+        let mut vm = Vm::new(bytecode, 100_000, BlockContext::default());
+        vm.state_mut().storage = Storage::with_state([(U256::from(9u64), U256::ONE)].into());
+        let mut dbg = TimeTravel::new(vm);
+        dbg.run_forward().unwrap();
+
+        assert_eq!(dbg.touched_storage(), vec![(U256::from(7u64), U256::from(2u64))]);
+    }
+
+    #[test]
+    fn test_storage_original_and_dirty() {
+        // PUSH1 9, PUSH1 0, SSTORE, STOP
+        let mut vm = Vm::new(vec![0x60, 0x09, 0x60, 0x00, 0x55, 0x00], 100_000, BlockContext::default());
+        vm.state_mut().storage = Storage::with_state([(U256::ZERO, U256::from(5u64))].into());
+        let mut dbg = TimeTravel::new(vm);
+        assert!(!dbg.storage_is_dirty(&U256::ZERO));
+
+        dbg.run_forward().unwrap();
+        assert_eq!(dbg.inspect_storage(&U256::ZERO), U256::from(9u64));
+        assert_eq!(dbg.storage_original(&U256::ZERO), U256::from(5u64));
+        assert!(dbg.storage_is_dirty(&U256::ZERO));
+        assert!(!dbg.storage_is_dirty(&U256::ONE));
+    }
+
+    #[test]
+    fn test_position_tracks_history() {
+        let mut dbg = demo_debugger();
+        dbg.step_n(5).unwrap();
+        dbg.rewind(2).unwrap();
+        assert_eq!(dbg.position(), 3);
+        assert_eq!(dbg.position(), dbg.history_len());
+
+        // The halting STOP counts too
+        dbg.run_forward().unwrap();
+        assert_eq!(dbg.position(), 6);
+    }
+
+    #[test]
+    fn test_run_forward_resumes_past_breakpoint() {
+        // Count down from 3: JUMPDEST at 2, then PUSH1 1, SWAP1, SUB, DUP1, PUSH1 2, JUMPI
+        let bytecode = vec![0x60, 0x03, 0x5B, 0x60, 0x01, 0x90, 0x03, 0x80, 0x60, 0x02, 0x57, 0x00];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()));
+        let id = dbg.add_breakpoint(Breakpoint::Address(2));
+
+        for counter in (1..=3u64).rev() {
+            match dbg.run_forward().unwrap() {
+                StopReason::Breakpoint(hit) => assert_eq!(hit, id),
+                stop => panic!("expected breakpoint, got {stop:?}"),
+            }
+            assert_eq!(dbg.inspect_pc(), 2);
+            assert_eq!(dbg.inspect_stack(), &[U256::from(counter)]);
+        }
+        assert!(matches!(dbg.run_forward().unwrap(), StopReason::Halt(HaltReason::Stop)));
+
+        // Backward through the same breakpoint, one iteration at a time
+        for counter in 1..=3u64 {
+            assert!(matches!(dbg.run_backward().unwrap(), StopReason::Breakpoint(_)));
+            assert_eq!(dbg.inspect_stack(), &[U256::from(counter)]);
+        }
+        assert!(matches!(dbg.run_backward().unwrap(), StopReason::ReachedBeginning));
+    }
+
+    #[test]
+    fn test_step_hook_stops_run() {
+        // PUSH1 1, PUSH1 2, ADD, PUSH1 4, MUL, STOP
+        let bytecode = vec![0x60, 0x01, 0x60, 0x02, 0x01, 0x60, 0x04, 0x02, 0x00];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()));
+        dbg.set_step_hook(|vm| match vm.state().stack.peek(0) {
+            Ok(top) if top == U256::from(3u64) => ControlDirective::Stop,
+            _ => ControlDirective::Continue,
+        });
+        assert!(matches!(dbg.run_forward().unwrap(), StopReason::UserStop));
+        assert_eq!(dbg.position(), 3);
+        assert_eq!(dbg.current_opcode(), Some(Opcode::Push1));
+
+        dbg.clear_step_hook();
+        assert!(matches!(dbg.run_forward().unwrap(), StopReason::Halt(HaltReason::Stop)));
+    }
+
+    #[test]
+    fn test_loop_detection() {
+        // JUMPDEST, PUSH1 0, JUMP
+        let spin = vec![0x5B, 0x60, 0x00, 0x56];
+        let mut dbg = TimeTravel::new(Vm::new(spin, 1_000_000, BlockContext::default()));
+        dbg.set_loop_detection(Some(16));
+        assert!(matches!(dbg.run_forward().unwrap(), StopReason::LoopDetected { pc: 0 }));
+        assert_eq!(dbg.position(), 3);
+
+        // JUMPDEST, slot 0 += 1, PUSH1 0, JUMP: same stack every time, but
+        // storage moves on, so it runs until gas is gone
+        let counter = vec![
+            0x5B, 0x60, 0x00, 0x54, 0x60, 0x01, 0x01, 0x60, 0x00, 0x55, 0x60, 0x00, 0x56,
+        ];
+        let mut dbg = TimeTravel::new(Vm::new(counter, 100_000, BlockContext::default()));
+        dbg.set_loop_detection(Some(16));
+        assert!(matches!(dbg.run_forward(), Err(VmError::OutOfGas { .. })));
+        assert!(dbg.inspect_storage(&U256::ZERO) > U256::ONE);
+    }
+
+    #[test]
+    fn test_run_to_state_hash() {
+        let mut dbg = demo_debugger();
+        dbg.step_n(3).unwrap();
+        let target = dbg.state_hash();
+        dbg.reset(100_000);
+
+        assert!(matches!(dbg.run_to_state_hash(target, 2).unwrap(), StopReason::StepLimit));
+        assert_eq!(dbg.position(), 2);
+
+        dbg.reset(100_000);
+        assert!(matches!(dbg.run_to_state_hash(target, 100).unwrap(), StopReason::StateHashReached));
+        assert_eq!(dbg.position(), 3);
+        assert_eq!(dbg.inspect_stack(), &[U256::from(30u64)]);
+
+        assert!(matches!(dbg.run_to_state_hash([0xAB; 32], 100).unwrap(), StopReason::Halt(HaltReason::Stop)));
+    }
+
+    #[test]
+    fn test_decode_two_word_return() {
+        // MSTORE 5 at 0 and 0x2A at 32, then RETURN both words
+        let bytecode = vec![
+            0x60, 0x05, 0x60, 0x00, 0x52, 0x60, 0x2A, 0x60, 0x20, 0x52,
+            0x60, 0x40, 0x60, 0x00, 0xF3,
+        ];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()));
+        assert!(dbg.decode_return(&[AbiType::Uint256]).is_empty());
+
+        dbg.run_forward().unwrap();
+        assert_eq!(
+            dbg.decode_return(&[AbiType::Uint256, AbiType::Uint256]),
+            vec![AbiValue::Uint256(U256::from(5u64)), AbiValue::Uint256(U256::from(0x2Au64))]
+        );
+    }
+
+    #[test]
+    fn test_return_data_after_halt() {
+        // MSTORE 0x2A at 0, RETURN 32 bytes from 0
+        let bytecode = vec![0x60, 0x2A, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xF3];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()));
+        assert!(dbg.return_data().is_empty());
+
+        dbg.run_forward().unwrap();
+        assert_eq!(dbg.return_data(), U256::from(0x2Au64).to_be_bytes());
+        dbg.step_backward().unwrap();
+        assert!(dbg.return_data().is_empty());
+    }
+
+    #[test]
+    fn test_replay_with_storage_override() {
+        // PUSH1 0, SLOAD, PUSH1 7, JUMPI, STOP, JUMPDEST, PUSH1 0, PUSH1 0, REVERT
+        let bytecode = vec![0x60, 0x00, 0x54, 0x60, 0x07, 0x57, 0x00, 0x5B, 0x60, 0x00, 0x60, 0x00, 0xFD];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()));
+        assert!(matches!(dbg.run_forward().unwrap(), StopReason::Halt(HaltReason::Stop)));
+        let position = dbg.position();
+
+        let replay = dbg.replay_with_storage_override(U256::ZERO, U256::ONE).unwrap();
+        assert!(matches!(replay, ExecutionResult::Revert { .. }), "{replay:?}");
+        let replay = dbg.replay_with_storage_override(U256::ZERO, U256::ZERO).unwrap();
+        assert!(matches!(replay, ExecutionResult::Success { .. }), "{replay:?}");
+
+        assert_eq!(dbg.position(), position);
+        assert!(matches!(dbg.result(), Some(ExecutionResult::Success { .. })));
+        assert_eq!(dbg.vm().state().storage.get(&U256::ZERO), U256::ZERO);
+    }
+
+    #[test]
+    fn test_result_after_halt() {
+        let mut dbg = demo_debugger();
+        assert!(dbg.result().is_none());
+
+        assert!(matches!(dbg.run_forward().unwrap(), StopReason::Halt(HaltReason::Stop)));
+        assert!(dbg.is_finished());
+        match dbg.result() {
+            Some(ExecutionResult::Success { return_data, gas_used }) => {
+                assert!(return_data.is_empty());
+                assert_eq!(gas_used, 100_000 - dbg.inspect_gas());
+                // Five 3-gas instructions plus 3 to grow memory to one word
+                assert_eq!(gas_used, 18);
+            }
+            other => panic!("expected success, got {other:?}"),
+        }
+
+        dbg.step_backward().unwrap();
+        assert!(dbg.result().is_none());
+        assert!(!dbg.is_finished());
+    }
+
+    #[test]
+    fn test_breakpoints_survive_reset() {
+        let mut dbg = demo_debugger();
+        let ids = dbg.add_breakpoints(vec![
+            Breakpoint::Address(4),
+            Breakpoint::Opcode(0x52),
+            Breakpoint::AfterInstructions(5),
+        ]);
+        assert_eq!(ids.len(), 3);
+        dbg.run_forward().unwrap();
+
+        dbg.reset(100_000);
+        assert_eq!(dbg.list_breakpoints().len(), 3);
+        for expected in [ids[0], ids[1], ids[2]] {
+            match dbg.run_forward().unwrap() {
+                StopReason::Breakpoint(hit) => assert_eq!(hit, expected),
+                other => panic!("expected breakpoint, got {other:?}"),
+            }
+            dbg.step_forward().unwrap();
+        }
+
+        let saved = dbg.breakpoints_snapshot();
+        dbg.clear_breakpoints();
+        dbg.restore_breakpoints(saved);
+        assert_eq!(dbg.list_breakpoints().iter().map(|(id, _)| *id).collect::<Vec<_>>(), ids);
+        assert!(!ids.contains(&dbg.add_breakpoint(Breakpoint::GasBelow(1))));
+    }
+
+    #[test]
+    fn test_stack_top_is_top_first() {
+        let mut dbg = demo_debugger();
+        dbg.step_n(2).unwrap();
+        dbg.vm_mut().state_mut().stack.push(U256::from(30u64)).unwrap();
+
+        let top: Vec<U256> = dbg.stack_top(2).copied().collect();
+        assert_eq!(top, vec![U256::from(30u64), U256::from(20u64)]);
+        assert_eq!(dbg.stack_top(10).len(), 3);
+        assert_eq!(dbg.stack_top(0).len(), 0);
+    }
+
+    #[test]
+    fn test_step_backward_reports_undone_instruction() {
+        let mut dbg = demo_debugger();
+        dbg.step_n(3).unwrap();
+        match dbg.step_backward().unwrap() {
+            StepResult::Rewound { opcode, pc, .. } => {
+                assert_eq!(opcode, Opcode::Add);
+                assert_eq!(pc, 4);
+            }
+            other => panic!("expected rewind, got {other:?}"),
+        }
+        assert_eq!(dbg.inspect_pc(), 4);
+    }
+
+    #[test]
+    fn test_history_bounds() {
+        let mut dbg = demo_debugger();
+        assert!(dbg.at_start() && !dbg.can_step_backward());
+        assert!(!dbg.at_end() && dbg.can_step_forward());
+
+        dbg.run_forward().unwrap();
+        assert!(dbg.at_end() && !dbg.can_step_forward());
+        assert!(!dbg.at_start() && dbg.can_step_backward());
+
+        dbg.rewind(dbg.history_len()).unwrap();
+        assert!(dbg.at_start());
+        assert!(!dbg.at_end());
+
+        // Running off the end of code is an implicit STOP
+        let mut dbg = TimeTravel::new(Vm::new(vec![0x60, 0x01], 100_000, BlockContext::default()));
+        dbg.step_forward().unwrap();
+        assert!(dbg.at_end());
+    }
+
+    #[test]
+    fn test_gas_used_while_stepping() {
+        let mut dbg = demo_debugger();
+        assert_eq!(dbg.gas_used(), 0);
+        dbg.step_forward().unwrap();
+        assert_eq!(dbg.gas_used(), 3);
+        dbg.step_forward().unwrap();
+        assert_eq!(dbg.gas_used(), 6);
+        dbg.step_backward().unwrap();
+        assert_eq!(dbg.gas_used(), 3);
+    }
+
+    #[test]
+    fn test_reset_with_calldata() {
+        // PUSH1 0, CALLDATALOAD, PUSH1 0, MSTORE, PUSH1 32, PUSH1 0, RETURN, JUMPDEST
+        let bytecode = vec![
+            0x60, 0x00, 0x35, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xF3, 0x5B,
+        ];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()));
+        let jump_dests = dbg.vm().jump_dests.as_ptr();
+
+        dbg.reset_with_calldata(100_000, vec![0xAA; 4]);
+        dbg.run_forward().unwrap();
+        let first = dbg.result().unwrap();
+
+        dbg.reset_with_calldata(100_000, vec![0xBB; 40]);
+        dbg.run_forward().unwrap();
+        let second = dbg.result().unwrap();
+
+        match (first, second) {
+            (
+                ExecutionResult::Success { return_data: a, .. },
+                ExecutionResult::Success { return_data: b, .. },
+            ) => {
+                assert_eq!(&a[..4], &[0xAA; 4]);
+                assert_eq!(&a[4..], &[0u8; 28], "short calldata is zero-padded");
+                assert_eq!(b, vec![0xBB; 32]);
+            }
+            other => panic!("expected two successes, got {other:?}"),
+        }
+        assert_eq!(dbg.vm().jump_dests.as_ptr(), jump_dests, "jump analysis is reused");
+        assert!(dbg.vm().is_valid_jump(11));
+    }
+
+    /// Bytecode that reverts with `data`, stored one word at a time
+    fn revert_with(data: &[u8]) -> Vec<u8> {
+        let mut bytecode = Vec::new();
+        for (i, chunk) in data.chunks(32).enumerate() {
+            let mut word = [0u8; 32];
+            word[..chunk.len()].copy_from_slice(chunk);
+            bytecode.push(0x7F);
+            bytecode.extend_from_slice(&word);
+            bytecode.extend_from_slice(&[0x60, (i * 32) as u8, 0x52]);
+        }
+        bytecode.extend_from_slice(&[0x60, data.len() as u8, 0x60, 0x00, 0xFD]);
+        bytecode
+    }
+
+    #[test]
+    fn test_explain_error_string_revert() {
+        let message = b"insufficient balance";
+        let mut data = vec![0x08, 0xc3, 0x79, 0xa0];
+        data.extend_from_slice(&U256::from(0x20u64).to_be_bytes());
+        data.extend_from_slice(&U256::from(message.len()).to_be_bytes());
+        let mut padded = [0u8; 32];
+        padded[..message.len()].copy_from_slice(message);
+        data.extend_from_slice(&padded);
+
+        let mut dbg = TimeTravel::new(Vm::new(revert_with(&data), 100_000, BlockContext::default()));
+        assert!(dbg.explain_revert().is_none());
+
+        assert!(matches!(dbg.run_forward().unwrap(), StopReason::Halt(HaltReason::Revert(_))));
+        let info = dbg.explain_revert().unwrap();
+        assert_eq!(info, RevertInfo::Error("insufficient balance".to_string()));
+        assert_eq!(info.to_string(), "Error(\"insufficient balance\")");
+
+        dbg.step_backward().unwrap();
+        assert!(dbg.explain_revert().is_none());
+    }
+
+    #[test]
+    fn test_display_stops() {
+        let mut dbg = TimeTravel::new(Vm::new(revert_with(&[0xAB; 12]), 100_000, BlockContext::default()));
+        let id = dbg.add_breakpoint(Breakpoint::AfterInstructions(1));
+        let stop = dbg.run_forward().unwrap();
+        assert_eq!(stop.to_string(), format!("breakpoint #{}", id.0));
+        assert_eq!(dbg.step_forward().unwrap().to_string(), "executed PUSH1 0x00 (3 gas)");
+
+        let stop = dbg.run_forward().unwrap();
+        assert!(matches!(stop, StopReason::Halt(HaltReason::Revert(_))), "{stop:?}");
+        assert_eq!(stop.to_string(), "halted: revert (12 bytes)");
+        assert_eq!(dbg.step_backward().unwrap().to_string(), format!("rewound REVERT at {:#06x}", dbg.inspect_pc()));
+    }
+
+    #[test]
+    fn test_return_data_breakpoint() {
+        // Root CALLs itself with one byte of input; the callee returns
+        // 0xDEADBEEF and the caller STOPs
+        let bytecode = vec![
+            0x36, 0x60, 0x13, 0x57,
+            0x60, 0x04, 0x60, 0x00, 0x60, 0x01, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00,
+            0x5A, 0xF1, 0x00,
+            0x5B, 0x63, 0xDE, 0xAD, 0xBE, 0xEF, 0x60, 0x00, 0x52,
+            0x60, 0x04, 0x60, 0x1C, 0xF3,
+        ];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 1_000_000, BlockContext::default()));
+        let other = dbg.add_breakpoint(Breakpoint::ReturnDataEquals(vec![0xDE, 0xAD]));
+        let id = dbg.add_breakpoint(Breakpoint::ReturnDataEquals(vec![0xDE, 0xAD, 0xBE, 0xEF]));
+
+        match dbg.run_forward().unwrap() {
+            StopReason::Breakpoint(hit) => assert_eq!(hit, id),
+            stop => panic!("expected breakpoint, got {stop:?}"),
+        }
+        assert_eq!(dbg.call_depth(), 0);
+        assert_eq!(dbg.inspect_pc(), 18);
+        assert_eq!(dbg.inspect_memory(0, 4), vec![0xDE, 0xAD, 0xBE, 0xEF]);
+
+        dbg.remove_breakpoint(id);
+        dbg.remove_breakpoint(other);
+        assert!(matches!(dbg.run_forward().unwrap(), StopReason::Halt(HaltReason::Stop)));
+    }
+
+    #[test]
+    fn test_empty_return_data_breakpoint_waits_for_call_exit() {
+        // Root CALLs itself with one byte of input; the callee STOPs with no
+        // return data. Entering the callee clears the buffer, which must not
+        // trip the breakpoint.
+        let bytecode = vec![
+            0x36, 0x60, 0x13, 0x57,
+            0x60, 0x00, 0x60, 0x00, 0x60, 0x01, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00,
+            0x5A, 0xF1, 0x00,
+            0x5B, 0x00,
+        ];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 1_000_000, BlockContext::default()));
+        let id = dbg.add_breakpoint(Breakpoint::ReturnDataEquals(vec![]));
+
+        match dbg.run_forward().unwrap() {
+            StopReason::Breakpoint(hit) => assert_eq!(hit, id),
+            stop => panic!("expected breakpoint, got {stop:?}"),
+        }
+        assert_eq!(dbg.call_depth(), 0);
+        assert_eq!(dbg.inspect_pc(), 18);
+
+        dbg.remove_breakpoint(id);
+        assert!(matches!(dbg.run_forward().unwrap(), StopReason::Halt(HaltReason::Stop)));
+    }
+
+    #[test]
+    fn test_inspect_root_call_inputs() {
+        let mut vm = Vm::new(vec![0x00], 100_000, BlockContext::default());
+        vm.set_calldata(vec![0xDE, 0xAD, 0xBE, 0xEF]);
+        vm.set_call_value(U256::from(1_000u64));
+        let dbg = TimeTravel::new(vm);
+
+        assert_eq!(dbg.inspect_calldata(), &[0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(dbg.inspect_call_value(), U256::from(1_000u64));
+    }
+
+    #[test]
+    fn test_last_instruction_entries_show_sstore() {
+        // PUSH1 0x2a, PUSH1 1, SSTORE, STOP
+        let bytecode = vec![0x60, 0x2A, 0x60, 0x01, 0x55, 0x00];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()));
+        assert!(dbg.last_instruction_entries().is_none());
+        dbg.step_n(3).unwrap();
+
+        let entries = dbg.last_instruction_entries().unwrap();
+        let write = entries
+            .iter()
+            .find(|entry| matches!(entry, JournalEntry::StorageWrite { .. }))
+            .expect("SSTORE journals its write");
+        let JournalEntry::StorageWrite { key, old_value, new_value, .. } = write else { unreachable!() };
+        assert_eq!((*key, *old_value, *new_value), (U256::ONE, U256::ZERO, U256::from(0x2Au64)));
+        assert_eq!(write.to_string(), "sstore[0x1] 0x0\u{2192}0x2a");
+
+        let rendered: Vec<String> = entries.iter().map(ToString::to_string).collect();
+        assert!(rendered.contains(&"pop 0x1".to_string()), "{rendered:?}");
+        assert!(rendered.contains(&"pc 0x0004\u{2192}0x0005".to_string()), "{rendered:?}");
+    }
+
+    #[test]
+    fn test_category_breakpoint() {
+        // PUSH1 0x2a, PUSH1 1, SSTORE, STOP
+        let bytecode = vec![0x60, 0x2A, 0x60, 0x01, 0x55, 0x00];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()));
+        let id = dbg.add_breakpoint(Breakpoint::Category(OpcodeCategory::Storage));
+
+        assert!(matches!(dbg.run_forward().unwrap(), StopReason::Breakpoint(hit) if hit == id));
+        assert_eq!(dbg.inspect_pc(), 4);
+    }
+
+    #[test]
+    fn test_peek_gas_cost() {
+        // PUSH1 10, PUSH1 20, ADD, PUSH1 0x40, MSTORE, STOP
+        let bytecode = vec![0x60, 0x0A, 0x60, 0x14, 0x01, 0x60, 0x40, 0x52, 0x00];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()));
+        dbg.step_n(2).unwrap();
+        assert_eq!(dbg.peek_gas_cost().unwrap(), 3);
+        assert_eq!(dbg.position(), 2, "peeking executes nothing");
+
+        dbg.step_n(2).unwrap();
+        let expected = Opcode::MStore.base_gas() + Memory::expansion_cost(0, 0x60);
+        assert_eq!(dbg.peek_gas_cost().unwrap(), expected);
+        let before = dbg.inspect_gas();
+        dbg.step_forward().unwrap();
+        assert_eq!(before - dbg.inspect_gas(), expected, "MSTORE is charged what the preview said");
+        dbg.step_n(1).unwrap();
+        assert_eq!(dbg.peek_gas_cost().unwrap(), 0, "past the end of the code");
+    }
+
+    #[test]
+    fn test_peek_gas_cost_memory_expansion() {
+        // PUSH1 1, PUSH2 1000, MSTORE, PUSH2 2000, MLOAD, STOP
+        let bytecode = vec![0x60, 0x01, 0x61, 0x03, 0xE8, 0x52, 0x61, 0x07, 0xD0, 0x51, 0x00];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()));
+        dbg.step_n(2).unwrap();
+        assert_eq!(dbg.peek_gas_cost().unwrap(), Opcode::MStore.base_gas() + Memory::expansion_cost(0, 1032));
+        dbg.step_n(2).unwrap();
+        let size = dbg.vm().state().memory.size();
+        assert_eq!(dbg.peek_gas_cost().unwrap(), Opcode::MLoad.base_gas() + Memory::expansion_cost(size, 2032));
+
+        // PUSH1 0xAA, PUSH2 1000, MSTORE8, STOP
+        let mut dbg = TimeTravel::new(Vm::new(vec![0x60, 0xAA, 0x61, 0x03, 0xE8, 0x53, 0x00], 100_000, BlockContext::default()));
+        dbg.step_n(2).unwrap();
+        assert_eq!(dbg.peek_gas_cost().unwrap(), Opcode::MStore8.base_gas() + Memory::expansion_cost(0, 1001));
+    }
+
+    #[test]
+    fn test_peek_gas_cost_matches_charge() {
+        // PUSH1 1, PUSH2 1000, MSTORE, PUSH2 2000, MLOAD, PUSH1 0xAA,
+        // PUSH2 3000, MSTORE8, PUSH1 0x20, PUSH1 0, PUSH2 4000, CODECOPY, STOP
+        let bytecode = vec![
+            0x60, 0x01, 0x61, 0x03, 0xE8, 0x52, 0x61, 0x07, 0xD0, 0x51, 0x60, 0xAA, 0x61, 0x0B, 0xB8, 0x53,
+            0x60, 0x20, 0x60, 0x00, 0x61, 0x0F, 0xA0, 0x39, 0x00,
+        ];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()));
+        while !dbg.is_finished() {
+            let preview = dbg.peek_gas_cost().unwrap();
+            let before = dbg.inspect_gas();
+            let op = dbg.current_opcode();
+            dbg.step_forward().unwrap();
+            assert_eq!(before - dbg.inspect_gas(), preview, "{op:?}");
+        }
+        assert_eq!(dbg.vm().state().memory.size(), 4032);
+    }
+
+    #[test]
+    fn test_peek_gas_cost_matches_charge_for_log_call_and_return() {
+        // LOG0 over 0x1000..0x1020, CALL an empty account with input at
+        // 0x1800 and output at 0x2000, POP, then RETURN 0x3000..0x3020
+        let bytecode = vec![
+            0x60, 0x20, 0x61, 0x10, 0x00, 0xA0,
+            0x60, 0x20, 0x61, 0x20, 0x00, 0x60, 0x20, 0x61, 0x18, 0x00,
+            0x60, 0x00, 0x60, 0x77, 0x61, 0xFF, 0xFF, 0xF1, 0x50,
+            0x60, 0x20, 0x61, 0x30, 0x00, 0xF3,
+        ];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()));
+        while !dbg.is_finished() {
+            let preview = dbg.peek_gas_cost().unwrap();
+            let before = dbg.inspect_gas();
+            let op = dbg.current_opcode();
+            dbg.step_forward().unwrap();
+            assert_eq!(before - dbg.inspect_gas(), preview, "{op:?}");
+        }
+        assert_eq!(dbg.vm().state().memory.size(), 0x3020);
+    }
+
+    #[test]
+    fn test_expensive_op_breakpoint() {
+        // PUSH1 1, PUSH1 2, ADD, PUSH1 0, SSTORE, STOP
+        let bytecode = vec![0x60, 0x01, 0x60, 0x02, 0x01, 0x60, 0x00, 0x55, 0x00];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()));
+        let id = dbg.add_breakpoint(Breakpoint::ExpensiveOp(100));
+
+        assert!(matches!(dbg.run_forward().unwrap(), StopReason::Breakpoint(hit) if hit == id));
+        assert_eq!(dbg.current_opcode(), Some(Opcode::SStore));
+        assert!(dbg.peek_gas_cost().unwrap() >= 100);
+        assert!(matches!(dbg.run_forward().unwrap(), StopReason::Halt(_)));
+    }
+
+    #[test]
+    fn test_rewind_to_last_sstore() {
+        // SSTORE(1, 0x2a), SSTORE(2, 7), then PUSH1 3, PUSH1 4, ADD, POP, STOP
+        let bytecode = vec![
+            0x60, 0x2A, 0x60, 0x01, 0x55, 0x60, 0x07, 0x60, 0x02, 0x55,
+            0x60, 0x03, 0x60, 0x04, 0x01, 0x50, 0x00,
+        ];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()));
+        dbg.run_forward().unwrap();
+
+        assert!(matches!(dbg.rewind_to_opcode(Opcode::SStore).unwrap(), StopReason::OpcodeReached { pc: 9 }));
+        assert_eq!(dbg.inspect_pc(), 9);
+        assert_eq!(dbg.inspect_storage(&U256::from(2u64)), U256::ZERO);
+        assert_eq!(dbg.inspect_storage(&U256::ONE), U256::from(0x2Au64));
+
+        assert!(matches!(dbg.rewind_to_opcode(Opcode::SStore).unwrap(), StopReason::OpcodeReached { pc: 4 }));
+        assert!(matches!(dbg.rewind_to_opcode(Opcode::SStore).unwrap(), StopReason::ReachedBeginning));
+        assert!(dbg.at_start());
+    }
+
+    #[test]
+    fn test_inspect_memory_word_matches_mload() {
+        // MSTORE(0x10, 0x1234), then MLOAD(0x18), STOP
+        let bytecode = vec![0x61, 0x12, 0x34, 0x60, 0x10, 0x52, 0x60, 0x18, 0x51, 0x00];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()));
+        dbg.step_n(3).unwrap();
+        let size = dbg.memory_size();
+        let word = dbg.inspect_memory_word(0x18);
+        assert_eq!(dbg.inspect_memory_word(0x40), U256::ZERO);
+        assert_eq!(dbg.memory_size(), size, "inspecting doesn't expand memory");
+
+        dbg.step_n(2).unwrap();
+        assert_eq!(dbg.inspect_stack(), &[word]);
+        assert_eq!(word, U256([0, 0x1234, 0, 0]));
+    }
+
+    #[test]
+    fn test_empty_and_stop_only_code_succeed() {
+        for bytecode in [vec![], vec![0x00]] {
+            let mut vm = Vm::new(bytecode.clone(), 1_000, BlockContext::default());
+            match vm.run().unwrap() {
+                ExecutionResult::Success { return_data, gas_used } => {
+                    assert!(return_data.is_empty());
+                    assert_eq!(gas_used, 0);
+                }
+                other => panic!("{bytecode:?} should succeed, got {other:?}"),
+            }
+
+            let mut dbg = TimeTravel::new(Vm::new(bytecode, 1_000, BlockContext::default()));
+            assert_eq!(dbg.peek_gas_cost().unwrap(), 0);
+            assert!(matches!(dbg.run_backward().unwrap(), StopReason::ReachedBeginning));
+            assert!(matches!(dbg.run_forward().unwrap(), StopReason::Halt(HaltReason::Stop)));
+            assert!(matches!(dbg.result(), Some(ExecutionResult::Success { .. })));
+            assert!(matches!(dbg.run_backward().unwrap(), StopReason::ReachedBeginning));
+            assert!(dbg.at_start());
+            assert_eq!(dbg.rewind(3).unwrap(), 0);
+            assert!(matches!(dbg.run_backward().unwrap(), StopReason::ReachedBeginning));
+        }
+    }
+
+    #[test]
+    fn test_matching_breakpoints_lists_overlaps() {
+        let mut dbg = demo_debugger();
+        let at_add = dbg.add_breakpoint(Breakpoint::Address(4));
+        let add = dbg.add_breakpoint(Breakpoint::Opcode(0x01));
+        let mstore = dbg.add_breakpoint(Breakpoint::Opcode(0x52));
+
+        assert!(matches!(dbg.run_forward().unwrap(), StopReason::Breakpoint(hit) if hit == at_add));
+        assert_eq!(dbg.matching_breakpoints(), vec![at_add, add]);
+        assert!(matches!(dbg.run_forward().unwrap(), StopReason::Breakpoint(hit) if hit == mstore));
+        assert_eq!(dbg.matching_breakpoints(), vec![mstore]);
+    }
+
+    #[test]
+    fn test_current_instruction() {
+        let mut dbg = demo_debugger();
+        let push = dbg.current_instruction().unwrap();
+        assert_eq!((push.opcode, push.immediate), (Opcode::Push1, Some(vec![0x0A])));
+
+        dbg.step_n(2).unwrap();
+        let add = dbg.current_instruction().unwrap();
+        assert_eq!((add.offset, add.mnemonic.as_str()), (4, "ADD"));
+
+        dbg.run_forward().unwrap();
+        assert!(dbg.current_instruction().is_none());
+    }
+
+    #[test]
+    fn test_step_forward_significant_skips_jumpdest() {
+        // PUSH1 1, PUSH1 2, JUMPDEST, JUMPDEST, ADD, STOP
+        let bytecode = vec![0x60, 0x01, 0x60, 0x02, 0x5B, 0x5B, 0x01, 0x00];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()));
+        assert_eq!(dbg.step_forward_significant().unwrap(), 1);
+        assert_eq!(dbg.step_forward_significant().unwrap(), 1);
+
+        assert_eq!(dbg.step_forward_significant().unwrap(), 3);
+        assert_eq!(dbg.inspect_pc(), 7);
+        assert_eq!(dbg.inspect_stack(), &[U256::from(3u64)]);
+        assert_eq!(dbg.step_forward_significant().unwrap(), 1);
+        assert!(dbg.is_finished());
+    }
+
+    #[test]
+    fn test_logs_json_keeps_emission_order() {
+        // LOG1(0, 1, topic 0xB0), then LOG1(0, 0, topic 0xA0), STOP
+        let bytecode = vec![
+            0x60, 0xB0, 0x60, 0x01, 0x60, 0x00, 0xA1,
+            0x60, 0xA0, 0x60, 0x00, 0x60, 0x00, 0xA1, 0x00,
+        ];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()));
+        dbg.run_forward().unwrap();
+
+        let zero = format!("{:#x}", Address::ZERO);
+        let topic = |byte: &str| format!("0x{byte:0>64}");
+        assert_eq!(
+            dbg.logs_json(),
+            format!(
+                r#"[{{"index":0,"address":"{zero}","topics":["{}"],"data":"0x00"}},{{"index":1,"address":"{zero}","topics":["{}"],"data":"0x"}}]"#,
+                topic("b0"),
+                topic("a0"),
+            )
+        );
+        dbg.rewind(2).unwrap();
+        assert_eq!(dbg.logs_json().matches("\"index\"").count(), 1);
+    }
+}
@@ -0,0 +1,74 @@
+//! Cross-checking execution against traces from other clients
+
+use crate::debugger::TimeTravel;
+use crate::executor::{StepResult, TraceStep};
+
+impl TimeTravel {
+    /// Replay the session from the start and return the index of the first
+    /// step whose pc, opcode, gas or stack differs from `external`.
+    ///
+    /// A trace that ends early, or runs on after execution halted, diverges
+    /// at the first missing step. The session itself is left untouched; if
+    /// the history no longer reaches back to the start, step 0 is reported.
+    pub fn compare_trace(&self, external: &[TraceStep]) -> Option<usize> {
+        let mut vm = self.vm().clone();
+        if vm.rewind_to(0).is_err() {
+            return Some(0);
+        }
+
+        let mut halted = false;
+        for (index, expected) in external.iter().enumerate() {
+            if halted {
+                return Some(index);
+            }
+            let state = vm.state();
+            let op = vm.bytecode().get(state.pc).copied().unwrap_or(0);
+            if state.pc != expected.pc
+                || op != expected.op
+                || state.gas != expected.gas
+                || state.stack.as_slice() != expected.stack.as_slice()
+            {
+                return Some(index);
+            }
+            halted = !matches!(vm.step_forward(), Ok(StepResult::Executed { .. }));
+        }
+        (!halted).then_some(external.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{BlockContext, U256};
+    use crate::executor::load_eip3155_trace;
+    use crate::vm::Vm;
+
+    /// EIP-3155 trace of the demo program as geth prints it
+    const DEMO_TRACE: &str = r#"{"pc":0,"op":96,"gas":"0x186a0","gasCost":"0x3","memSize":0,"stack":[],"depth":1,"refund":0,"opName":"PUSH1"}
+{"pc":2,"op":96,"gas":"0x1869d","gasCost":"0x3","memSize":0,"stack":["0xa"],"depth":1,"refund":0,"opName":"PUSH1"}
+{"pc":4,"op":1,"gas":"0x1869a","gasCost":"0x3","memSize":0,"stack":["0xa","0x14"],"depth":1,"refund":0,"opName":"ADD"}
+{"pc":5,"op":96,"gas":"0x18697","gasCost":"0x3","memSize":0,"stack":["0x1e"],"depth":1,"refund":0,"opName":"PUSH1"}
+{"pc":7,"op":1,"gas":"0x18694","gasCost":"0x3","memSize":0,"stack":["0x1e","0x0"],"depth":1,"refund":0,"opName":"ADD"}
+{"pc":8,"op":0,"gas":"0x18691","gasCost":"0x0","memSize":0,"stack":["0x1e"],"depth":1,"refund":0,"opName":"STOP"}
+{"output":"","gasUsed":"0xf"}"#;
+
+    #[test]
+    fn test_compare_demo_trace() {
+        // PUSH1 10, PUSH1 20, ADD, PUSH1 0, ADD, STOP
+        let bytecode = vec![0x60, 0x0A, 0x60, 0x14, 0x01, 0x60, 0x00, 0x01, 0x00];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()));
+        dbg.run_forward().unwrap();
+
+        let mut trace = load_eip3155_trace(DEMO_TRACE);
+        let gas: Vec<u64> = trace.iter().map(|step| step.gas).collect();
+        assert_eq!(gas, [100_000, 99_997, 99_994, 99_991, 99_988, 99_985]);
+        assert_eq!(dbg.compare_trace(&trace), None);
+        assert_eq!(dbg.compare_trace(&trace[..4]), Some(4));
+
+        trace[3].stack = vec![U256::from(31u64)];
+        assert_eq!(dbg.compare_trace(&trace), Some(3));
+        trace[3].stack = vec![U256::from(30u64)];
+        trace[5].gas += 1;
+        assert_eq!(dbg.compare_trace(&trace), Some(5));
+    }
+}
@@ -1,5 +1,14 @@
 //! Debugger API for time-travel debugging
 
+mod abi;
 mod api;
+mod crosscheck;
+mod profile;
+mod revert;
+mod session;
+mod timeline;
 
-pub use api::{TimeTravel, Breakpoint, BreakpointId, StopReason};
+pub use abi::{decode_address, decode_static, decode_uint256_array, AbiType, AbiValue};
+pub use api::{TimeTravel, Breakpoint, BreakpointId, StopReason, Debugger, ControlDirective, StepHook};
+pub use profile::FrameGas;
+pub use revert::{panic_label, RevertInfo, ERROR_SELECTOR, PANIC_SELECTOR};
@@ -0,0 +1,137 @@
+//! Gas attribution per call frame
+
+use std::fmt::Write;
+
+use crate::core::Address;
+use crate::debugger::TimeTravel;
+use crate::journal::JournalEntry;
+
+/// Gas spent inside one call frame, excluding its subcalls
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameGas {
+    /// Account the frame ran as. For DELEGATECALL and CALLCODE that is the
+    /// caller's own address, not the account the code was loaded from.
+    pub address: Address,
+    /// Call depth, 0 for the root call
+    pub depth: usize,
+    /// Index of the calling frame in the same list
+    pub parent: Option<usize>,
+    /// Gas the frame's own instructions consumed
+    pub gas: u64,
+}
+
+impl TimeTravel {
+    /// Attribute the gas of the recorded history to call frames, in the
+    /// order they were entered; the root frame comes first.
+    ///
+    /// Gas forwarded to a subcall counts toward the callee, and whatever it
+    /// hands back is not charged to the caller, so the frames sum to the
+    /// total gas used.
+    pub fn gas_by_frame(&self) -> Vec<FrameGas> {
+        let mut frames = vec![FrameGas { address: self.vm().address(), depth: 0, parent: None, gas: 0 }];
+        // Signed running totals: gas handed to and back from a callee shows
+        // up as gas changes of the caller that are cancelled separately
+        let mut spent = vec![0i128];
+        let mut active = 0;
+
+        for insn in self.history() {
+            for entry in &insn.entries {
+                match entry {
+                    JournalEntry::GasChange { old_gas, new_gas } => {
+                        spent[active] += *old_gas as i128 - *new_gas as i128;
+                    }
+                    JournalEntry::CallEnter { callee_frame } => {
+                        spent[active] -= callee_frame.gas as i128;
+                        frames.push(FrameGas {
+                            address: callee_frame.address,
+                            depth: frames[active].depth + 1,
+                            parent: Some(active),
+                            gas: 0,
+                        });
+                        spent.push(0);
+                        active = frames.len() - 1;
+                    }
+                    JournalEntry::CallExit { callee_frame, .. } => {
+                        active = frames[active].parent.unwrap_or(0);
+                        spent[active] += callee_frame.saved.gas as i128;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        for (frame, spent) in frames.iter_mut().zip(spent) {
+            frame.gas = spent.max(0) as u64;
+        }
+        frames
+    }
+
+    /// Gas per call path in the folded-stack format flamegraph tools read:
+    /// one `root;callee;... gas` line per path, identical paths merged.
+    /// Paths are built from `FrameGas::address`, so a DELEGATECALL from the
+    /// root shows up as `root;root`.
+    pub fn gas_flamegraph_text(&self) -> String {
+        let frames = self.gas_by_frame();
+        let mut lines: Vec<(String, u64)> = Vec::new();
+        for frame in &frames {
+            let mut path = vec![hex_address(&frame.address)];
+            let mut parent = frame.parent;
+            while let Some(i) = parent {
+                path.push(hex_address(&frames[i].address));
+                parent = frames[i].parent;
+            }
+            path.reverse();
+            let stack = path.join(";");
+
+            match lines.iter_mut().find(|(existing, _)| *existing == stack) {
+                Some((_, gas)) => *gas += frame.gas,
+                None => lines.push((stack, frame.gas)),
+            }
+        }
+
+        let mut out = String::new();
+        for (stack, gas) in lines {
+            let _ = writeln!(out, "{stack} {gas}");
+        }
+        out
+    }
+}
+
+fn hex_address(address: &Address) -> String {
+    format!("{address:#x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::BlockContext;
+    use crate::vm::Vm;
+
+    #[test]
+    fn test_gas_split_between_root_and_callee() {
+        // CALL 0xCC with no input or output and all gas, then STOP
+        let root = vec![
+            0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0xCC,
+            0x5A, 0xF1, 0x00,
+        ];
+        let callee = Address::from_slice(&[0xCC]);
+        let mut vm = Vm::new(root, 1_000_000, BlockContext::default());
+        // SSTORE 1 -> slot 0, STOP
+        vm.set_code(callee, vec![0x60, 0x01, 0x60, 0x00, 0x55, 0x00]);
+        let mut dbg = TimeTravel::new(vm);
+        dbg.run_forward().unwrap();
+
+        let frames = dbg.gas_by_frame();
+        assert_eq!(frames.len(), 2);
+        // Six pushes, GAS and the CALL base; two pushes and the SSTORE
+        assert_eq!(frames[0], FrameGas { address: Address::ZERO, depth: 0, parent: None, gas: 6 * 3 + 3 + 100 });
+        assert_eq!(frames[1], FrameGas { address: callee, depth: 1, parent: Some(0), gas: 2 * 3 + 100 });
+        assert_eq!(frames[0].gas + frames[1].gas, dbg.gas_used());
+
+        let folded = dbg.gas_flamegraph_text();
+        let lines: Vec<&str> = folded.lines().collect();
+        let root_hex = hex_address(&Address::ZERO);
+        assert_eq!(lines[0], format!("{root_hex} {}", frames[0].gas));
+        assert_eq!(lines[1], format!("{root_hex};{} {}", hex_address(&callee), frames[1].gas));
+    }
+}
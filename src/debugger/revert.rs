@@ -0,0 +1,124 @@
+//! Decoding of Solidity-style revert data
+
+use std::fmt;
+
+use crate::core::U256;
+use crate::debugger::abi::word_at;
+
+/// Selector of `Error(string)`
+pub const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+/// Selector of `Panic(uint256)`
+pub const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// Decoded revert reason
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RevertInfo {
+    /// Revert without data
+    Empty,
+    /// `require`/`revert` message from `Error(string)`
+    Error(String),
+    /// Compiler-inserted `Panic(uint256)` check
+    Panic { code: U256, label: &'static str },
+    /// Custom error or malformed payload, split at the selector when there is one
+    Custom { selector: Option<[u8; 4]>, data: Vec<u8> },
+}
+
+impl RevertInfo {
+    /// Decode the return data of a REVERT
+    pub fn decode(data: &[u8]) -> Self {
+        if data.is_empty() {
+            return Self::Empty;
+        }
+        let Some((selector, args)) = data.split_first_chunk::<4>() else {
+            return Self::Custom { selector: None, data: data.to_vec() };
+        };
+
+        let decoded = match *selector {
+            ERROR_SELECTOR => decode_string(args).map(Self::Error),
+            PANIC_SELECTOR => word_at(args, 0).map(|code| Self::Panic { code, label: panic_label(code) }),
+            _ => None,
+        };
+        decoded.unwrap_or_else(|| Self::Custom { selector: Some(*selector), data: args.to_vec() })
+    }
+}
+
+impl fmt::Display for RevertInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "reverted without data"),
+            Self::Error(message) => write!(f, "Error(\"{message}\")"),
+            Self::Panic { code, label } => write!(f, "Panic(0x{:02x}): {label}", code.as_u64()),
+            Self::Custom { selector: Some(selector), data } => {
+                write!(f, "custom error 0x")?;
+                for byte in selector {
+                    write!(f, "{byte:02x}")?;
+                }
+                write!(f, " ({} bytes of arguments)", data.len())
+            }
+            Self::Custom { selector: None, data } => write!(f, "{} bytes of raw revert data", data.len()),
+        }
+    }
+}
+
+/// Human-readable meaning of a Solidity panic code
+pub fn panic_label(code: U256) -> &'static str {
+    if code > U256::from(0xffu64) {
+        return "unknown panic code";
+    }
+    match code.as_u64() {
+        0x00 => "generic compiler panic",
+        0x01 => "assertion failed",
+        0x11 => "arithmetic overflow or underflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "invalid enum conversion",
+        0x22 => "corrupt storage byte array encoding",
+        0x31 => "pop on empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "out of memory",
+        0x51 => "call to uninitialized function pointer",
+        _ => "unknown panic code",
+    }
+}
+
+/// ABI word at byte `offset` of `args` that must fit in a usize
+fn usize_at(args: &[u8], offset: usize) -> Option<usize> {
+    let word = word_at(args, offset)?;
+    (word <= U256::from(args.len())).then(|| word.as_usize())
+}
+
+/// Decode a single dynamic `string` argument
+fn decode_string(args: &[u8]) -> Option<String> {
+    let head = usize_at(args, 0)?;
+    let len = usize_at(args, head)?;
+    let start = head + 32;
+    let bytes = args.get(start..start.checked_add(len)?)?;
+    String::from_utf8(bytes.to_vec()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_panic_and_malformed() {
+        let mut panic = PANIC_SELECTOR.to_vec();
+        panic.extend_from_slice(&U256::from(0x11u64).to_be_bytes());
+        assert_eq!(
+            RevertInfo::decode(&panic),
+            RevertInfo::Panic { code: U256::from(0x11u64), label: "arithmetic overflow or underflow" }
+        );
+
+        // Error(string) whose length runs past the end of the data
+        let mut truncated = ERROR_SELECTOR.to_vec();
+        truncated.extend_from_slice(&U256::from(0x20u64).to_be_bytes());
+        truncated.extend_from_slice(&U256::from(0x40u64).to_be_bytes());
+        truncated.extend_from_slice(b"short");
+        assert!(matches!(
+            RevertInfo::decode(&truncated),
+            RevertInfo::Custom { selector: Some(ERROR_SELECTOR), .. }
+        ));
+
+        assert_eq!(RevertInfo::decode(&[]), RevertInfo::Empty);
+        assert_eq!(RevertInfo::decode(&[0xAB]), RevertInfo::Custom { selector: None, data: vec![0xAB] });
+    }
+}
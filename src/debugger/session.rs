@@ -0,0 +1,359 @@
+//! Saving and restoring debugging sessions
+//!
+//! A session is stored as the inputs execution started from, the position
+//! reached and the breakpoints, one `key value...` line each. Loading
+//! rebuilds the VM and replays up to the saved position; execution is
+//! deterministic, so state and history come back exactly.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use crate::core::{Address, BlockContext, U256};
+use crate::debugger::{Breakpoint, BreakpointId, TimeTravel};
+use crate::executor::{GasSchedule, OpcodeCategory};
+use crate::journal::{HistoryMode, Journal};
+use crate::vm::{Account, DivByZero, HashMode, Storage, Vm, VmState};
+
+const HEADER: &str = "ttbd-session 1";
+
+const CATEGORIES: [OpcodeCategory; 11] = [
+    OpcodeCategory::Arithmetic,
+    OpcodeCategory::Comparison,
+    OpcodeCategory::Bitwise,
+    OpcodeCategory::Memory,
+    OpcodeCategory::Storage,
+    OpcodeCategory::Flow,
+    OpcodeCategory::Environment,
+    OpcodeCategory::Block,
+    OpcodeCategory::Stack,
+    OpcodeCategory::Log,
+    OpcodeCategory::System,
+];
+
+impl TimeTravel {
+    /// Write the session so `load_session` can resume it: the code, context,
+    /// starting state and VM settings, the current position and the
+    /// breakpoints. Fails with `InvalidInput` if the history no longer
+    /// reaches back to the start of execution. The checkpoint hook isn't
+    /// saved.
+    pub fn save_session(&self, mut writer: impl Write) -> io::Result<()> {
+        let mut start = self.vm().clone();
+        start
+            .rewind_to(0)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+
+        let w = &mut writer;
+        writeln!(w, "{HEADER}")?;
+        writeln!(w, "code {}", hex_bytes(&start.bytecode))?;
+        writeln!(w, "gas {}", start.state.gas)?;
+        let c = &start.context;
+        writeln!(
+            w,
+            "context {} {} {} {:#x} {:#x} {} {:#x}",
+            c.number, c.timestamp, c.gas_limit, c.coinbase, c.difficulty, c.chain_id, c.base_fee
+        )?;
+        writeln!(w, "address {:#x}", start.address)?;
+        writeln!(w, "calldata {}", hex_bytes(&start.calldata))?;
+        writeln!(w, "value {:#x}", start.call_value)?;
+        write!(w, "entry {}", start.entry_pc)?;
+        for word in &start.entry_stack {
+            write!(w, " {word:#x}")?;
+        }
+        writeln!(w)?;
+
+        let s = &start.gas_schedule;
+        writeln!(w, "schedule {} {} {} {}", s.push0, s.push, s.dup, s.swap)?;
+        let journal = start.journal();
+        writeln!(w, "limits {} {} {}", start.max_call_depth, journal.checkpoint_interval(), journal.max_size())?;
+        match journal.history_mode() {
+            HistoryMode::Truncate => writeln!(w, "history truncate")?,
+            HistoryMode::Ring { capacity } => writeln!(w, "history ring {capacity}")?,
+        }
+        let flags = [
+            ("metering", start.gas_metering),
+            ("strict_opcodes", start.strict_opcodes),
+            ("strict_memory", start.strict_memory),
+            ("access_costs", start.access_costs),
+            ("verify_on_rewind", start.verify_on_rewind),
+            ("hash_every_step", start.hash_mode == HashMode::EveryStep),
+            ("trap_division_by_zero", start.division_by_zero == DivByZero::Trap),
+            ("sparse_checkpoints", start.sparse_checkpoints),
+        ];
+        write!(w, "flags")?;
+        for (name, _) in flags.iter().filter(|(_, on)| *on) {
+            write!(w, " {name}")?;
+        }
+        writeln!(w)?;
+        write!(w, "free")?;
+        for byte in (0..=255u8).filter(|&byte| start.free_opcodes[byte as usize]) {
+            write!(w, " {byte}")?;
+        }
+        writeln!(w)?;
+        for address in &start.warm_addresses {
+            writeln!(w, "warm {address:#x}")?;
+        }
+        for (address, key) in &start.warm_slots {
+            writeln!(w, "warm_slot {address:#x} {key:#x}")?;
+        }
+
+        for (key, value) in sorted(start.state.storage.iter()) {
+            writeln!(w, "storage {key:#x} {value:#x}")?;
+        }
+        let mut accounts: Vec<_> = start.state.accounts.iter().collect();
+        accounts.sort_by_key(|(address, _)| address.0);
+        for (address, account) in accounts {
+            writeln!(w, "account {address:#x} {} {:#x}", hex_bytes(&account.code), account.balance)?;
+            for (key, value) in sorted(account.storage.iter()) {
+                writeln!(w, "slot {address:#x} {key:#x} {value:#x}")?;
+            }
+        }
+
+        writeln!(w, "position {}", self.position())?;
+        for (id, bp) in self.list_breakpoints() {
+            writeln!(w, "breakpoint {} {}", id.0, format_breakpoint(bp))?;
+        }
+        Ok(())
+    }
+
+    /// Read a session written by `save_session` and replay it to the saved
+    /// position. Fails with `InvalidData` on malformed input or if replay
+    /// stops short of the position.
+    pub fn load_session(reader: impl Read) -> io::Result<TimeTravel> {
+        let mut lines = BufReader::new(reader).lines();
+        if lines.next().transpose()?.as_deref() != Some(HEADER) {
+            return Err(invalid("missing session header"));
+        }
+
+        let mut vm = Vm::new(Vec::new(), 0, BlockContext::default());
+        let mut gas = 0;
+        let mut position = 0;
+        let mut breakpoints = Vec::new();
+        let mut accounts: HashMap<Address, Account> = HashMap::new();
+        let mut root_storage = HashMap::new();
+        let mut warm_addresses = Vec::new();
+        let mut warm_slots = Vec::new();
+
+        for (number, line) in lines.enumerate() {
+            let line = line?;
+            let bad = || invalid(&format!("line {}: {line}", number + 2));
+            let mut fields = line.split_whitespace();
+            let key = fields.next().ok_or_else(bad)?;
+            let fields: Vec<&str> = fields.collect();
+            let field = |i: usize| fields.get(i).copied().ok_or_else(bad);
+            let int = |i: usize| field(i)?.parse::<u64>().map_err(|_| bad());
+            let word = |i: usize| U256::from_hex(field(i)?).ok_or_else(bad);
+            let address = |i: usize| word(i).map(Address::from_word);
+
+            match key {
+                "code" => {
+                    let code = parse_bytes(field(0)?).ok_or_else(bad)?;
+                    vm = Vm::new(code, 0, BlockContext::default());
+                }
+                "gas" => gas = int(0)?,
+                "context" => {
+                    vm.context = BlockContext {
+                        number: int(0)?,
+                        timestamp: int(1)?,
+                        gas_limit: int(2)?,
+                        coinbase: address(3)?,
+                        difficulty: word(4)?,
+                        chain_id: int(5)?,
+                        base_fee: word(6)?,
+                    };
+                }
+                "address" => vm.address = address(0)?,
+                "calldata" => vm.calldata = parse_bytes(field(0)?).ok_or_else(bad)?,
+                "value" => vm.call_value = word(0)?,
+                "entry" => {
+                    vm.entry_pc = int(0)? as usize;
+                    vm.entry_stack = (1..fields.len()).map(word).collect::<io::Result<_>>()?;
+                }
+                "schedule" => {
+                    vm.gas_schedule = GasSchedule { push0: int(0)?, push: int(1)?, dup: int(2)?, swap: int(3)? };
+                }
+                "limits" => {
+                    vm.max_call_depth = int(0)? as usize;
+                    vm.journal = Journal::new(int(1)? as usize, int(2)? as usize);
+                }
+                "history" => {
+                    let mode = match field(0)? {
+                        "truncate" => HistoryMode::Truncate,
+                        "ring" => HistoryMode::Ring { capacity: int(1)? as usize },
+                        _ => return Err(bad()),
+                    };
+                    vm.journal.set_history_mode(mode);
+                }
+                "flags" => {
+                    let on = |name: &str| fields.contains(&name);
+                    vm.gas_metering = on("metering");
+                    vm.strict_opcodes = on("strict_opcodes");
+                    vm.strict_memory = on("strict_memory");
+                    vm.access_costs = on("access_costs");
+                    vm.verify_on_rewind = on("verify_on_rewind");
+                    vm.hash_mode = if on("hash_every_step") { HashMode::EveryStep } else { HashMode::OnDemand };
+                    vm.division_by_zero = if on("trap_division_by_zero") { DivByZero::Trap } else { DivByZero::Zero };
+                    vm.sparse_checkpoints = on("sparse_checkpoints");
+                }
+                "free" => {
+                    for i in 0..fields.len() {
+                        let byte = u8::try_from(int(i)?).map_err(|_| bad())?;
+                        vm.free_opcodes[byte as usize] = true;
+                    }
+                }
+                "warm" => warm_addresses.push(address(0)?),
+                "warm_slot" => warm_slots.push((address(0)?, word(1)?)),
+                "storage" => {
+                    root_storage.insert(word(0)?, word(1)?);
+                }
+                "account" => {
+                    let code = parse_bytes(field(1)?).ok_or_else(bad)?;
+                    let account = Account { balance: word(2)?, ..Account::new(code.into()) };
+                    accounts.insert(address(0)?, account);
+                }
+                "slot" => {
+                    let account = accounts.get_mut(&address(0)?).ok_or_else(bad)?;
+                    account.storage.insert(word(1)?, word(2)?);
+                }
+                "position" => position = int(0)? as usize,
+                "breakpoint" => {
+                    let id = BreakpointId(int(0)? as usize);
+                    breakpoints.push((id, parse_breakpoint(&fields[1..]).ok_or_else(bad)?));
+                }
+                _ => return Err(bad()),
+            }
+        }
+
+        vm.state = VmState::new(gas);
+        vm.initial_gas = gas;
+        vm.state.pc = vm.entry_pc;
+        vm.state.stack.restore_from(&vm.entry_stack);
+        vm.state.storage = Storage::with_state(root_storage);
+        for account in accounts.values_mut() {
+            account.storage = Storage::with_state(account.storage.snapshot());
+        }
+        vm.state.accounts = accounts;
+        vm.set_access_list(warm_addresses, warm_slots);
+
+        let mut dbg = TimeTravel::new(vm);
+        for _ in 0..position {
+            dbg.step_forward().map_err(|err| invalid(&format!("replay failed: {err}")))?;
+        }
+        if dbg.position() != position {
+            return Err(invalid("replay stopped short of the saved position"));
+        }
+        dbg.restore_breakpoints(breakpoints);
+        Ok(dbg)
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Entries ordered by key, so saved sessions are byte-for-byte stable
+fn sorted<'a, K: Ord + 'a, V: 'a>(entries: impl Iterator<Item = (&'a K, &'a V)>) -> Vec<(&'a K, &'a V)> {
+    let mut entries: Vec<_> = entries.collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    let mut text = String::from("0x");
+    for byte in bytes {
+        text.push_str(&format!("{byte:02x}"));
+    }
+    text
+}
+
+fn parse_bytes(text: &str) -> Option<Vec<u8>> {
+    let hex = text.strip_prefix("0x")?;
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn format_breakpoint(bp: &Breakpoint) -> String {
+    match bp {
+        Breakpoint::Address(pc) => format!("pc {pc}"),
+        Breakpoint::Opcode(byte) => format!("opcode {byte}"),
+        Breakpoint::Category(category) => format!("category {category:?}"),
+        Breakpoint::ExpensiveOp(gas) => format!("expensive {gas}"),
+        Breakpoint::StorageAccess(key) => format!("storage {key:#x}"),
+        Breakpoint::GasBelow(gas) => format!("gas_below {gas}"),
+        Breakpoint::MemoryAccess { start, end } => format!("memory {start} {end}"),
+        Breakpoint::AfterInstructions(n) => format!("after {n}"),
+        Breakpoint::ReturnDataEquals(data) => format!("return_data {}", hex_bytes(data)),
+    }
+}
+
+fn parse_breakpoint(fields: &[&str]) -> Option<Breakpoint> {
+    let int = |i: usize| fields.get(i)?.parse::<u64>().ok();
+    Some(match *fields.first()? {
+        "pc" => Breakpoint::Address(int(1)? as usize),
+        "opcode" => Breakpoint::Opcode(u8::try_from(int(1)?).ok()?),
+        "category" => {
+            let name = *fields.get(1)?;
+            Breakpoint::Category(CATEGORIES.into_iter().find(|c| format!("{c:?}") == name)?)
+        }
+        "expensive" => Breakpoint::ExpensiveOp(int(1)?),
+        "storage" => Breakpoint::StorageAccess(U256::from_hex(fields.get(1)?)?),
+        "gas_below" => Breakpoint::GasBelow(int(1)?),
+        "memory" => Breakpoint::MemoryAccess { start: int(1)? as usize, end: int(2)? as usize },
+        "after" => Breakpoint::AfterInstructions(int(1)? as usize),
+        "return_data" => Breakpoint::ReturnDataEquals(parse_bytes(fields.get(1)?)?),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{Opcode, StepResult};
+
+    #[test]
+    fn test_session_round_trip() {
+        // Root CALLs 0xBB, which returns SLOAD(7); the root stores it
+        let callee = Address::from_word(U256::from(0xBBu64));
+        let callee_code = vec![0x60, 0x07, 0x54, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xF3];
+        let root_code = vec![
+            0x60, 0x20, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0xBB, 0x61, 0xFF, 0xFF, 0xF1,
+            0x60, 0x00, 0x51, 0x60, 0x01, 0x55, 0x00,
+        ];
+        let vm = Vm::builder(root_code)
+            .gas(1_000_000)
+            .calldata(vec![0xAB, 0xCD])
+            .account(callee, callee_code, U256::from(5u64), HashMap::from([(U256::from(7u64), U256::from(99u64))]))
+            .build()
+            .unwrap();
+        let mut dbg = TimeTravel::new(vm);
+        dbg.add_breakpoint(Breakpoint::Category(OpcodeCategory::Storage));
+        dbg.add_breakpoint(Breakpoint::ReturnDataEquals(vec![0; 32]));
+        dbg.add_breakpoint(Breakpoint::Opcode(Opcode::SStore as u8));
+        // Stop inside the callee
+        dbg.step_n(11).unwrap();
+        assert_eq!(dbg.call_depth(), 1);
+
+        let mut saved = Vec::new();
+        dbg.save_session(&mut saved).unwrap();
+        let mut loaded = TimeTravel::load_session(saved.as_slice()).unwrap();
+        assert_eq!(loaded.position(), dbg.position());
+        assert_eq!(loaded.state_hash(), dbg.state_hash());
+        assert_eq!(format!("{:?}", loaded.list_breakpoints()), format!("{:?}", dbg.list_breakpoints()));
+
+        loop {
+            let expected = dbg.step_forward().unwrap();
+            assert_eq!(loaded.step_forward().unwrap().to_string(), expected.to_string());
+            assert_eq!(loaded.state_hash(), dbg.state_hash());
+            if matches!(expected, StepResult::Halted { .. }) {
+                break;
+            }
+        }
+        assert_eq!(loaded.inspect_storage(&U256::ONE), U256::from(99u64));
+
+        let (mut from_loaded, mut from_original) = (Vec::new(), Vec::new());
+        loaded.save_session(&mut from_loaded).unwrap();
+        dbg.save_session(&mut from_original).unwrap();
+        assert_eq!(from_loaded, from_original);
+    }
+}
@@ -0,0 +1,115 @@
+//! Plain-text timeline of a recorded session
+
+use crate::bytecode::format_mnemonic;
+use crate::debugger::TimeTravel;
+use crate::executor::Opcode;
+use crate::journal::{InstructionJournal, JournalEntry};
+
+impl TimeTravel {
+    /// Render the recorded history one instruction per line: index, pc,
+    /// mnemonic, gas left afterwards and a short summary of what changed.
+    pub fn timeline(&self) -> String {
+        let mut out = String::new();
+        for (index, insn) in self.history().enumerate() {
+            let line = format!(
+                "{index:>5}  {:04x}  {:<14}  gas {:<10}  {}",
+                insn.pc,
+                mnemonic(insn),
+                insn.gas_after,
+                describe_changes(insn),
+            );
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Mnemonic of a recorded instruction; PUSH immediates are recovered from
+/// the pushed value since the journal doesn't keep the code
+fn mnemonic(insn: &InstructionJournal) -> String {
+    let Some(opcode) = Opcode::from_u8(insn.opcode) else {
+        return format!("INVALID(0x{:02x})", insn.opcode);
+    };
+    let immediate = opcode.is_push().then(|| {
+        insn.entries.iter().find_map(|entry| match entry {
+            JournalEntry::StackPush { value } => {
+                Some(value.to_be_bytes()[32 - opcode.immediate_size()..].to_vec())
+            }
+            _ => None,
+        })
+    });
+    format_mnemonic(opcode, &immediate.flatten())
+}
+
+/// Comma-separated summary of the state changes of one instruction
+fn describe_changes(insn: &InstructionJournal) -> String {
+    let mut parts = Vec::new();
+    let pops = insn.entries.iter().filter(|entry| matches!(entry, JournalEntry::StackPop { .. })).count();
+    if pops > 0 {
+        parts.push(format!("pop {pops}"));
+    }
+
+    for entry in &insn.entries {
+        match entry {
+            JournalEntry::StackPush { value } => parts.push(format!("push {value:#x}")),
+            JournalEntry::MemoryWrite { offset, new_data, .. } => {
+                parts.push(format!("mem[{:#x}..{:#x}]", offset, offset + new_data.len()));
+            }
+            JournalEntry::StorageWrite { key, old_value, new_value, .. } => {
+                parts.push(format!("sstore {key:#x}: {old_value:#x} -> {new_value:#x}"));
+            }
+            JournalEntry::PcChange { old_pc, new_pc } => {
+                let width = Opcode::from_u8(insn.opcode).map_or(0, |op| op.immediate_size());
+                if *new_pc != old_pc + 1 + width {
+                    parts.push(format!("jump {new_pc:#06x}"));
+                }
+            }
+            JournalEntry::CallEnter { callee_frame } => {
+                parts.push(format!("enter {:#x}", callee_frame.address));
+            }
+            JournalEntry::CallExit { return_data, .. } => {
+                parts.push(format!("exit ({} bytes)", return_data.len()));
+            }
+            JournalEntry::MemoryExpansion { old_size, new_size } => {
+                parts.push(format!("msize {old_size} -> {new_size}"));
+            }
+            JournalEntry::LogEmitted { index } => parts.push(format!("log #{index}")),
+            JournalEntry::LogsDiscarded { logs } => parts.push(format!("discard {} logs", logs.len())),
+            JournalEntry::TransactionCommitted { .. } => parts.push("commit".to_string()),
+            JournalEntry::SlotWarmed { key, .. } => parts.push(format!("warm slot {key:#x}")),
+            JournalEntry::AddressWarmed { address } => parts.push(format!("warm {address:#x}")),
+            JournalEntry::StackPop { .. }
+            | JournalEntry::GasChange { .. }
+            | JournalEntry::ReturnDataSet { .. } => {}
+        }
+    }
+    parts.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::BlockContext;
+    use crate::vm::Vm;
+
+    #[test]
+    fn test_demo_timeline() {
+        // PUSH1 10, PUSH1 20, ADD, PUSH1 0, MSTORE, STOP
+        let bytecode = vec![0x60, 0x0A, 0x60, 0x14, 0x01, 0x60, 0x00, 0x52, 0x00];
+        let mut dbg = TimeTravel::new(Vm::new(bytecode, 100_000, BlockContext::default()));
+        dbg.run_forward().unwrap();
+
+        let timeline = dbg.timeline();
+        let lines: Vec<&str> = timeline.lines().collect();
+        assert_eq!(lines.len(), dbg.history_len());
+        assert_eq!(lines.len(), 6);
+        assert!(lines[0].contains("PUSH1 0x0a"), "{timeline}");
+        assert!(lines[2].contains("ADD") && lines[2].ends_with("pop 2, push 0x1e"), "{timeline}");
+        assert!(lines[4].contains("mem[0x0..0x20]"), "{timeline}");
+
+        let gas: Vec<u64> = dbg.history().map(|insn| insn.gas_after).collect();
+        assert!(gas.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert!(gas[0] < 100_000);
+    }
+}
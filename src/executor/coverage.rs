@@ -0,0 +1,51 @@
+//! Which opcodes the interpreter gives real semantics
+
+use crate::executor::Opcode;
+
+/// Whether the interpreter implements `op`. Anything else fails with
+/// `UnimplementedOpcode` in strict mode and is a no-op in lenient mode.
+pub fn is_implemented(op: Opcode) -> bool {
+    use Opcode::*;
+    op.is_push()
+        || op.is_dup()
+        || op.is_swap()
+        || op.is_log()
+        || matches!(
+            op,
+            Stop | Add | Mul | Sub | Div | AddMod | MulMod
+                | Lt | Gt | Slt | Sgt | Eq | IsZero | And | Or | Xor | Not
+                | Balance | CallDataLoad | CallDataSize | CallDataCopy | CodeCopy
+                | ExtCodeSize | ExtCodeCopy | ReturnDataCopy | ExtCodeHash | SelfBalance
+                | Pop | MLoad | MStore | MStore8 | SLoad | SStore | Jump | JumpI
+                | Pc | MSize | Gas | JumpDest | Push0
+                | Call | CallCode | DelegateCall | Return | Revert | Invalid
+        )
+}
+
+/// Every implemented opcode, in byte order
+pub fn implemented_opcodes() -> Vec<Opcode> {
+    (0..=u8::MAX).filter_map(Opcode::from_u8).filter(|&op| is_implemented(op)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{BlockContext, U256, VmError};
+    use crate::vm::Vm;
+
+    #[test]
+    fn test_registry_matches_interpreter() {
+        assert!(is_implemented(Opcode::Add));
+        assert!(!is_implemented(Opcode::Exp));
+        assert!(implemented_opcodes().contains(&Opcode::Push32));
+
+        for op in (0..=u8::MAX).filter_map(Opcode::from_u8) {
+            let mut vm = Vm::new(vec![op as u8, 0x00], 1_000_000, BlockContext::default());
+            for _ in 0..op.stack_inputs() {
+                vm.state_mut().stack.push(U256::ONE).unwrap();
+            }
+            let unimplemented = matches!(vm.step_forward(), Err(VmError::UnimplementedOpcode { .. }));
+            assert_eq!(unimplemented, !is_implemented(op), "{op:?}");
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! Configurable gas prices
+
+use crate::executor::Opcode;
+
+/// Prices of the stack opcodes; everything else uses fixed costs.
+///
+/// The default matches Shanghai: PUSH0 costs 2, the other stack opcodes 3.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GasSchedule {
+    pub push0: u64,
+    /// PUSH1 through PUSH32
+    pub push: u64,
+    pub dup: u64,
+    pub swap: u64,
+}
+
+/// EIP-2929 price of a storage slot's first access in a transaction
+pub const COLD_SLOAD_COST: u64 = 2100;
+/// EIP-2929 price of an account's first access in a transaction
+pub const COLD_ACCOUNT_ACCESS_COST: u64 = 2600;
+/// EIP-2929 price of every later access
+pub const WARM_STORAGE_READ_COST: u64 = 100;
+
+impl GasSchedule {
+    pub const SHANGHAI: Self = Self { push0: 2, push: 3, dup: 3, swap: 3 };
+
+    /// Base gas charged for executing `opcode`
+    pub fn cost(&self, opcode: Opcode) -> u64 {
+        match opcode {
+            Opcode::Push0 => self.push0,
+            op if op.is_push() => self.push,
+            op if op.is_dup() => self.dup,
+            op if op.is_swap() => self.swap,
+            op => op.fixed_gas(),
+        }
+    }
+}
+
+impl Default for GasSchedule {
+    fn default() -> Self {
+        Self::SHANGHAI
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::BlockContext;
+    use crate::executor::StepResult;
+    use crate::vm::Vm;
+
+    #[test]
+    fn test_push_costs() {
+        let schedule = GasSchedule::default();
+        assert_eq!(schedule.cost(Opcode::Push0), 2);
+        assert_eq!(schedule.cost(Opcode::Push1), 3);
+        assert_eq!(schedule.cost(Opcode::Push32), 3);
+        assert_eq!(schedule.cost(Opcode::Dup1), 3);
+        assert_eq!(schedule.cost(Opcode::Add), Opcode::Add.base_gas());
+
+        // PUSH0, PUSH1 1, ADD, STOP
+        let bytecode = vec![0x5F, 0x60, 0x01, 0x01, 0x00];
+        let mut vm = Vm::new(bytecode.clone(), 100_000, BlockContext::default());
+        assert_eq!(vm.run().unwrap().gas_used(), 2 + 3 + 3);
+        assert_eq!(vm.state().stack.as_slice(), &[crate::core::U256::ONE]);
+
+        let cheap = GasSchedule { push0: 1, push: 1, ..GasSchedule::default() };
+        let mut vm = Vm::builder(bytecode).gas(100_000).gas_schedule(cheap).build().unwrap();
+        assert_eq!(vm.run().unwrap().gas_used(), 1 + 1 + 3);
+    }
+
+    #[test]
+    fn test_arithmetic_gas_matches_yellow_paper() {
+        let expected = [
+            (Opcode::Add, 3), (Opcode::Mul, 5), (Opcode::Sub, 3), (Opcode::Div, 5),
+            (Opcode::SDiv, 5), (Opcode::Mod, 5), (Opcode::SMod, 5), (Opcode::AddMod, 8),
+            (Opcode::MulMod, 8), (Opcode::Exp, 10), (Opcode::SignExtend, 5),
+        ];
+        for (opcode, gas) in expected {
+            assert_eq!(opcode.base_gas(), gas, "{opcode:?}");
+
+            // Three zero operands keep EXP's per-byte gas out of the charge;
+            // lenient mode charges opcodes even where they aren't implemented
+            let mut vm = Vm::new(vec![0x5F, 0x5F, 0x5F, opcode as u8, 0x00], 100_000, BlockContext::default());
+            vm.set_strict_opcodes(false);
+            vm.step_forward().unwrap();
+            vm.step_forward().unwrap();
+            vm.step_forward().unwrap();
+            let StepResult::Executed { gas_used, .. } = vm.step_forward().unwrap() else {
+                panic!("{opcode:?} didn't execute");
+            };
+            assert_eq!(gas_used, gas, "{opcode:?}");
+        }
+        assert_eq!(Opcode::SelfBalance.base_gas(), 5);
+    }
+}
@@ -1,16 +1,51 @@
 //! Forward execution interpreter with journaling
 
-use crate::core::{U256, VmError, VmResult, HaltReason};
-use crate::vm::Vm;
-use crate::executor::Opcode;
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::bytecode::format_mnemonic;
+use crate::core::hash::keccak256;
+use crate::core::{Address, BlockContext, U256, VmError, VmResult, HaltReason};
+use crate::vm::{Vm, HashMode, CallFrame, DivByZero, LogRecord, Memory, SavedContext};
+use crate::executor::{Opcode, Precompile, apply_inverse, COLD_ACCOUNT_ACCESS_COST, COLD_SLOAD_COST, WARM_STORAGE_READ_COST};
 use crate::journal::{JournalEntry, InstructionJournal, Checkpoint, StateSnapshot};
 
 /// Result of a single step execution
 #[derive(Clone, Debug)]
 pub enum StepResult {
-    Executed { opcode: Opcode, gas_used: u64 },
+    /// One instruction run; `immediate` is the value a PUSH pushed
+    Executed { opcode: Opcode, gas_used: u64, immediate: Option<U256>, control_flow: ControlFlow },
     Halted { reason: HaltReason },
-    Rewound { steps: usize },
+    /// One instruction undone: the opcode at `pc`, with undecodable bytes
+    /// reported as INVALID
+    Rewound { steps: usize, opcode: Opcode, pc: usize },
+}
+
+impl fmt::Display for StepResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Executed { opcode, gas_used, immediate, .. } => {
+                let bytes = immediate.map(|value| value.to_be_bytes()[32 - opcode.immediate_size()..].to_vec());
+                write!(f, "executed {} ({gas_used} gas)", format_mnemonic(*opcode, &bytes))
+            }
+            Self::Halted { reason } => write!(f, "halted: {reason}"),
+            Self::Rewound { opcode, pc, .. } => {
+                write!(f, "rewound {} at {pc:#06x}", format_mnemonic(*opcode, &None))
+            }
+        }
+    }
+}
+
+/// How an executed instruction moved the program counter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlFlow {
+    /// Fell through to the next instruction; entering a call counts too
+    Sequential,
+    /// Jumped to the given pc
+    Jumped(usize),
+    /// A nested call halted and returned to its caller
+    Halted,
 }
 
 /// Final execution result
@@ -21,16 +56,61 @@ pub enum ExecutionResult {
     Halt { reason: HaltReason, gas_used: u64 },
 }
 
+impl ExecutionResult {
+    /// Build the final result for a halt that consumed `gas_used`
+    pub fn from_halt(reason: HaltReason, gas_used: u64) -> Self {
+        match reason {
+            HaltReason::Stop => Self::Success { return_data: Vec::new(), gas_used },
+            HaltReason::Return(data) => Self::Success { return_data: data, gas_used },
+            HaltReason::Revert(data) => Self::Revert { return_data: data, gas_used },
+            _ => Self::Halt { reason, gas_used },
+        }
+    }
+
+    /// Gas consumed by the execution
+    pub fn gas_used(&self) -> u64 {
+        match self {
+            Self::Success { gas_used, .. }
+            | Self::Revert { gas_used, .. }
+            | Self::Halt { gas_used, .. } => *gas_used,
+        }
+    }
+}
+
 impl Vm {
     /// Execute one instruction forward, journaling all state changes.
+    ///
+    /// A halt inside a nested call returns to the caller and reports
+    /// `Executed`; only a halt of the root call reports `Halted`. An
+    /// exceptional halt inside a nested call, such as running out of gas,
+    /// fails that call like a REVERT instead of returning an error.
     pub fn step_forward(&mut self) -> VmResult<StepResult> {
+        match self.step_instruction() {
+            Err(err) if !self.call_stack.is_empty() => match exceptional_halt(&err) {
+                Some(reason) => self.fail_nested_call(reason),
+                None => Err(err),
+            },
+            result => result,
+        }
+    }
+
+    fn step_instruction(&mut self) -> VmResult<StepResult> {
         if self.state.pc >= self.bytecode.len() {
-            return Ok(StepResult::Halted { reason: HaltReason::Stop });
+            if self.call_stack.is_empty() {
+                return Ok(StepResult::Halted { reason: HaltReason::Stop });
+            }
+            // Running off the end of a callee's code is an implicit STOP
+            let mut insn_journal = InstructionJournal::new(self.state.pc, Opcode::Stop as u8, self.state.gas);
+            self.invalidate_hash();
+            self.advance_pc(0, &mut insn_journal);
+            self.exit_call(HaltReason::Stop, &mut insn_journal)?;
+            return Ok(self.finish_nested_exit(insn_journal, Opcode::Stop));
         }
 
         let opcode_byte = self.bytecode[self.state.pc];
-        let opcode = Opcode::from_u8(opcode_byte)
-            .ok_or(VmError::InvalidOpcode { opcode: opcode_byte })?;
+        let Some((opcode, immediate_size)) = self.decoded[self.state.pc] else {
+            return self.halt_undecodable(opcode_byte);
+        };
 
         let stack_len = self.state.stack.len();
         let required = opcode.stack_inputs();
@@ -38,41 +118,196 @@ impl Vm {
             return Err(VmError::StackUnderflow { required, available: stack_len });
         }
 
-        let gas_cost = opcode.base_gas();
+        let gas_cost = if self.charges_gas(opcode) {
+            self.gas_schedule.cost(opcode).saturating_add(self.access_surcharge(opcode)?)
+        } else {
+            0
+        };
         if self.state.gas < gas_cost {
             return Err(VmError::OutOfGas { required: gas_cost, available: self.state.gas });
         }
 
         let mut insn_journal = InstructionJournal::new(self.state.pc, opcode_byte, self.state.gas);
+        self.invalidate_hash();
         let old_pc = self.state.pc;
-
-        let halt = self.execute_opcode(opcode, &mut insn_journal)?;
+        let old_depth = self.call_stack.len();
 
         let old_gas = self.state.gas;
         self.state.gas -= gas_cost;
         insn_journal.push(JournalEntry::GasChange { old_gas, new_gas: self.state.gas });
-        insn_journal.gas_after = self.state.gas;
+        self.mark_accessed(opcode, &mut insn_journal)?;
 
-        if self.state.pc == old_pc {
-            let new_pc = old_pc + 1 + opcode.immediate_size();
-            insn_journal.push(JournalEntry::PcChange { old_pc, new_pc });
-            self.state.pc = new_pc;
-        }
+        let mut halt = match self.execute_opcode(opcode, &mut insn_journal) {
+            Ok(halt) => halt,
+            Err(err) => {
+                // Undo partial effects so a failed instruction leaves no trace
+                for entry in insn_journal.entries.into_iter().rev() {
+                    apply_inverse(self, entry)?;
+                }
+                return Err(err);
+            }
+        };
 
-        insn_journal.state_hash = self.compute_state_hash();
-        self.journal.record(insn_journal);
+        // Jumps and calls move the pc themselves; everything else, halts
+        // included, falls through so each instruction journals one PcChange
+        if self.state.pc == old_pc && self.call_stack.len() == old_depth {
+            self.advance_pc(immediate_size as usize, &mut insn_journal);
+        }
 
-        if self.journal.should_checkpoint() {
-            let snapshot = self.create_state_snapshot();
-            let checkpoint = Checkpoint::new(self.journal.len(), snapshot);
-            self.journal.add_checkpoint(checkpoint);
+        if let Some(reason) = halt.take_if(|_| !self.call_stack.is_empty()) {
+            self.exit_call(reason, &mut insn_journal)?;
+            return Ok(self.finish_nested_exit(insn_journal, opcode));
         }
 
+        insn_journal.gas_after = self.state.gas;
+        let gas_used = insn_journal.gas_before.saturating_sub(insn_journal.gas_after);
+        let control_flow = match insn_journal.entries.iter().rev().find_map(|entry| match entry {
+            JournalEntry::PcChange { new_pc, .. } => Some(*new_pc),
+            _ => None,
+        }) {
+            Some(new_pc) if new_pc != old_pc + 1 + immediate_size as usize => ControlFlow::Jumped(new_pc),
+            _ => ControlFlow::Sequential,
+        };
+        let immediate = if opcode.is_push() || opcode == Opcode::Push0 {
+            insn_journal.entries.iter().find_map(|entry| match entry {
+                JournalEntry::StackPush { value } => Some(*value),
+                _ => None,
+            })
+        } else {
+            None
+        };
+        self.commit_instruction(insn_journal);
+
         if let Some(reason) = halt {
             return Ok(StepResult::Halted { reason });
         }
 
-        Ok(StepResult::Executed { opcode, gas_used: gas_cost })
+        Ok(StepResult::Executed { opcode, gas_used, immediate, control_flow })
+    }
+
+    /// Account, and slot for storage opcodes, that `opcode` is about to
+    /// access, read off the stack
+    fn access_target(&self, opcode: Opcode) -> VmResult<Option<(Address, Option<U256>)>> {
+        let stack = &self.state.stack;
+        Ok(match opcode {
+            Opcode::SLoad | Opcode::SStore => Some((self.active_address(), Some(stack.peek(0)?))),
+            Opcode::Balance | Opcode::ExtCodeSize | Opcode::ExtCodeHash | Opcode::ExtCodeCopy => {
+                Some((Address::from_word(stack.peek(0)?), None))
+            }
+            Opcode::Call | Opcode::CallCode | Opcode::DelegateCall | Opcode::StaticCall => {
+                Some((Address::from_word(stack.peek(1)?), None))
+            }
+            _ => None,
+        })
+    }
+
+    /// Gas `opcode` pays on top of its base cost for a cold access; always
+    /// zero unless access costs are enabled
+    pub(crate) fn access_surcharge(&self, opcode: Opcode) -> VmResult<u64> {
+        if !self.access_costs {
+            return Ok(0);
+        }
+        Ok(match self.access_target(opcode)? {
+            Some((address, Some(key))) if !self.is_warm_slot(address, key) => match opcode {
+                Opcode::SLoad => COLD_SLOAD_COST - WARM_STORAGE_READ_COST,
+                _ => COLD_SLOAD_COST,
+            },
+            Some((address, None)) if !self.is_warm_address(address) => {
+                COLD_ACCOUNT_ACCESS_COST - WARM_STORAGE_READ_COST
+            }
+            _ => 0,
+        })
+    }
+
+    /// Warm whatever `opcode` is about to access, journaling only the first
+    /// access so rewinding makes it cold again
+    fn mark_accessed(&mut self, opcode: Opcode, journal: &mut InstructionJournal) -> VmResult<()> {
+        if !self.access_costs {
+            return Ok(());
+        }
+        match self.access_target(opcode)? {
+            Some((address, Some(key))) if self.state.accessed_slots.insert((address, key)) => {
+                journal.push(JournalEntry::SlotWarmed { address, key });
+            }
+            Some((address, None)) if self.state.accessed_addresses.insert(address) => {
+                journal.push(JournalEntry::AddressWarmed { address });
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Halt on a byte that doesn't decode to an opcode, consuming all
+    /// remaining gas like the designated INVALID opcode.
+    fn halt_undecodable(&mut self, opcode_byte: u8) -> VmResult<StepResult> {
+        if !self.call_stack.is_empty() {
+            return self.fail_nested_call(HaltReason::InvalidOpcode(opcode_byte));
+        }
+        let mut insn_journal = InstructionJournal::new(self.state.pc, opcode_byte, self.state.gas);
+        self.invalidate_hash();
+
+        self.consume_all_gas(&mut insn_journal);
+        self.advance_pc(0, &mut insn_journal);
+        insn_journal.gas_after = self.state.gas;
+
+        self.commit_instruction(insn_journal);
+        Ok(StepResult::Halted { reason: HaltReason::InvalidOpcode(opcode_byte) })
+    }
+
+    /// Fail the active nested call at the current instruction: its gas is
+    /// consumed, its writes rolled back and the caller sees 0
+    fn fail_nested_call(&mut self, reason: HaltReason) -> VmResult<StepResult> {
+        let opcode_byte = self.bytecode.get(self.state.pc).copied().unwrap_or(Opcode::Stop as u8);
+        let mut insn_journal = InstructionJournal::new(self.state.pc, opcode_byte, self.state.gas);
+        self.invalidate_hash();
+
+        self.consume_all_gas(&mut insn_journal);
+        self.advance_pc(0, &mut insn_journal);
+        self.exit_call(reason, &mut insn_journal)?;
+        let opcode = Opcode::from_u8(opcode_byte).unwrap_or(Opcode::Invalid);
+        Ok(self.finish_nested_exit(insn_journal, opcode))
+    }
+
+    /// Move past the current instruction and its immediate
+    fn advance_pc(&mut self, immediate_size: usize, journal: &mut InstructionJournal) {
+        let old_pc = self.state.pc;
+        let new_pc = old_pc + 1 + immediate_size;
+        journal.push(JournalEntry::PcChange { old_pc, new_pc });
+        self.state.pc = new_pc;
+    }
+
+    /// Commit an instruction that returned from a nested call
+    fn finish_nested_exit(&mut self, mut insn_journal: InstructionJournal, opcode: Opcode) -> StepResult {
+        insn_journal.gas_after = self.state.gas;
+        self.commit_instruction(insn_journal);
+        StepResult::Executed { opcode, gas_used: 0, immediate: None, control_flow: ControlFlow::Halted }
+    }
+
+    /// Zero out remaining gas, journaling the change; a no-op without
+    /// gas metering
+    fn consume_all_gas(&mut self, journal: &mut InstructionJournal) {
+        if !self.gas_metering {
+            return;
+        }
+        let old_gas = self.state.gas;
+        self.state.gas = 0;
+        journal.push(JournalEntry::GasChange { old_gas, new_gas: 0 });
+    }
+
+    /// Hash, record and checkpoint a completed instruction
+    fn commit_instruction(&mut self, mut insn_journal: InstructionJournal) {
+        if self.hash_mode == HashMode::EveryStep {
+            insn_journal.state_hash = self.state_hash();
+        }
+        self.journal.record(insn_journal);
+
+        if self.journal.should_checkpoint() {
+            let checkpoint = self.checkpoint();
+            if let Some(hook) = &self.checkpoint_hook {
+                hook(checkpoint.instruction_index, checkpoint.state_snapshot.memory_usage());
+            }
+            self.journal.add_checkpoint(checkpoint);
+        }
     }
 
     fn execute_opcode(&mut self, opcode: Opcode, journal: &mut InstructionJournal) -> VmResult<Option<HaltReason>> {
@@ -115,17 +350,45 @@ impl Vm {
                 journal.push(JournalEntry::StackPop { value: a });
                 let b = self.state.stack.pop()?;
                 journal.push(JournalEntry::StackPop { value: b });
-                let result = U256::from(a.as_u64().wrapping_mul(b.as_u64()));
+                let result = a.wrapping_mul(b);
                 self.state.stack.push(result)?;
                 journal.push(JournalEntry::StackPush { value: result });
             }
             
+            Opcode::AddMod => {
+                let a = self.state.stack.pop()?;
+                journal.push(JournalEntry::StackPop { value: a });
+                let b = self.state.stack.pop()?;
+                journal.push(JournalEntry::StackPop { value: b });
+                let m = self.state.stack.pop()?;
+                journal.push(JournalEntry::StackPop { value: m });
+                let result = a.add_mod(b, m);
+                self.state.stack.push(result)?;
+                journal.push(JournalEntry::StackPush { value: result });
+            }
+
+            Opcode::MulMod => {
+                let a = self.state.stack.pop()?;
+                journal.push(JournalEntry::StackPop { value: a });
+                let b = self.state.stack.pop()?;
+                journal.push(JournalEntry::StackPop { value: b });
+                let m = self.state.stack.pop()?;
+                journal.push(JournalEntry::StackPop { value: m });
+                let result = a.mul_mod(b, m);
+                self.state.stack.push(result)?;
+                journal.push(JournalEntry::StackPush { value: result });
+            }
+
             Opcode::Div => {
                 let a = self.state.stack.pop()?;
                 journal.push(JournalEntry::StackPop { value: a });
                 let b = self.state.stack.pop()?;
                 journal.push(JournalEntry::StackPop { value: b });
-                let result = if b.is_zero() { U256::ZERO } else { U256::from(a.as_u64() / b.as_u64()) };
+                let result = match a.checked_div_rem(b) {
+                    Some((quotient, _)) => quotient,
+                    None if self.division_by_zero == DivByZero::Trap => return Err(VmError::DivisionByZero),
+                    None => U256::ZERO,
+                };
                 self.state.stack.push(result)?;
                 journal.push(JournalEntry::StackPush { value: result });
             }
@@ -153,7 +416,7 @@ impl Vm {
                 journal.push(JournalEntry::StackPop { value: a });
                 let b = self.state.stack.pop()?;
                 journal.push(JournalEntry::StackPop { value: b });
-                let result = if a.as_u64() < b.as_u64() { U256::ONE } else { U256::ZERO };
+                let result = if a < b { U256::ONE } else { U256::ZERO };
                 self.state.stack.push(result)?;
                 journal.push(JournalEntry::StackPush { value: result });
             }
@@ -163,7 +426,18 @@ impl Vm {
                 journal.push(JournalEntry::StackPop { value: a });
                 let b = self.state.stack.pop()?;
                 journal.push(JournalEntry::StackPop { value: b });
-                let result = if a.as_u64() > b.as_u64() { U256::ONE } else { U256::ZERO };
+                let result = if a > b { U256::ONE } else { U256::ZERO };
+                self.state.stack.push(result)?;
+                journal.push(JournalEntry::StackPush { value: result });
+            }
+
+            Opcode::Slt | Opcode::Sgt => {
+                let a = self.state.stack.pop()?;
+                journal.push(JournalEntry::StackPop { value: a });
+                let b = self.state.stack.pop()?;
+                journal.push(JournalEntry::StackPop { value: b });
+                let wanted = if opcode == Opcode::Slt { Ordering::Less } else { Ordering::Greater };
+                let result = if a.signed_cmp(&b) == wanted { U256::ONE } else { U256::ZERO };
                 self.state.stack.push(result)?;
                 journal.push(JournalEntry::StackPush { value: result });
             }
@@ -206,6 +480,49 @@ impl Vm {
                 journal.push(JournalEntry::StackPush { value: result });
             }
             
+            Opcode::CallDataLoad => {
+                let offset = self.state.stack.pop()?;
+                journal.push(JournalEntry::StackPop { value: offset });
+                let mut word = [0u8; 32];
+                let calldata = self.active_calldata();
+                if offset.0[1..].iter().all(|&limb| limb == 0) {
+                    let start = offset.as_usize().min(calldata.len());
+                    let available = &calldata[start..];
+                    let len = available.len().min(32);
+                    word[..len].copy_from_slice(&available[..len]);
+                }
+                let value = U256::from_be_bytes(word);
+                self.state.stack.push(value)?;
+                journal.push(JournalEntry::StackPush { value });
+            }
+
+            Opcode::CallDataSize => {
+                let value = U256::from(self.active_calldata().len());
+                self.state.stack.push(value)?;
+                journal.push(JournalEntry::StackPush { value });
+            }
+
+            Opcode::Balance | Opcode::ExtCodeSize | Opcode::ExtCodeHash => {
+                let word = self.state.stack.pop()?;
+                journal.push(JournalEntry::StackPop { value: word });
+                let address = Address::from_word(word);
+                let value = match opcode {
+                    Opcode::Balance => self.balance_of(address),
+                    Opcode::ExtCodeSize => U256::from(self.code_at(address).len()),
+                    // Accounts that don't exist hash to zero
+                    _ if address != self.address && !self.state.accounts.contains_key(&address) => U256::ZERO,
+                    _ => U256::from_be_bytes(keccak256(self.code_at(address))),
+                };
+                self.state.stack.push(value)?;
+                journal.push(JournalEntry::StackPush { value });
+            }
+
+            Opcode::SelfBalance => {
+                let value = self.balance_of(self.active_address());
+                self.state.stack.push(value)?;
+                journal.push(JournalEntry::StackPush { value });
+            }
+
             Opcode::Pop => {
                 let a = self.state.stack.pop()?;
                 journal.push(JournalEntry::StackPop { value: a });
@@ -214,8 +531,10 @@ impl Vm {
             Opcode::MLoad => {
                 let offset = self.state.stack.pop()?;
                 journal.push(JournalEntry::StackPop { value: offset });
+                let (offset, _) = Memory::checked_range(offset, U256::from(32u64))?;
+                self.charge_memory_expansion(opcode, offset + 32, journal)?;
                 let old_size = self.state.memory.size();
-                let value = self.state.memory.load(offset.as_usize());
+                let value = self.mem_load(offset)?;
                 let new_size = self.state.memory.size();
                 if new_size > old_size {
                     journal.push(JournalEntry::MemoryExpansion { old_size, new_size });
@@ -229,14 +548,16 @@ impl Vm {
                 journal.push(JournalEntry::StackPop { value: offset });
                 let value = self.state.stack.pop()?;
                 journal.push(JournalEntry::StackPop { value });
+                let (offset, _) = Memory::checked_range(offset, U256::from(32u64))?;
+                self.charge_memory_expansion(opcode, offset + 32, journal)?;
                 let old_size = self.state.memory.size();
-                let old_data = self.state.memory.store(offset.as_usize(), value);
+                let old_data = self.mem_store(offset, value)?;
                 let new_size = self.state.memory.size();
                 if new_size > old_size {
                     journal.push(JournalEntry::MemoryExpansion { old_size, new_size });
                 }
                 journal.push(JournalEntry::MemoryWrite {
-                    offset: offset.as_usize(),
+                    offset,
                     old_data,
                     new_data: value.to_be_bytes().to_vec(),
                 });
@@ -248,9 +569,16 @@ impl Vm {
                 let value = self.state.stack.pop()?;
                 journal.push(JournalEntry::StackPop { value });
                 let byte = (value.0[0] & 0xFF) as u8;
-                let old_byte = self.state.memory.store_byte(offset.as_usize(), byte);
+                let (offset, _) = Memory::checked_range(offset, U256::ONE)?;
+                self.charge_memory_expansion(opcode, offset + 1, journal)?;
+                let old_size = self.state.memory.size();
+                let old_byte = self.mem_store_byte(offset, byte)?;
+                let new_size = self.state.memory.size();
+                if new_size > old_size {
+                    journal.push(JournalEntry::MemoryExpansion { old_size, new_size });
+                }
                 journal.push(JournalEntry::MemoryWrite {
-                    offset: offset.as_usize(),
+                    offset,
                     old_data: vec![old_byte],
                     new_data: vec![byte],
                 });
@@ -259,7 +587,7 @@ impl Vm {
             Opcode::SLoad => {
                 let key = self.state.stack.pop()?;
                 journal.push(JournalEntry::StackPop { value: key });
-                let value = self.state.storage.get(&key);
+                let value = self.storage_at(self.active_address(), &key);
                 self.state.stack.push(value)?;
                 journal.push(JournalEntry::StackPush { value });
             }
@@ -269,8 +597,9 @@ impl Vm {
                 journal.push(JournalEntry::StackPop { value: key });
                 let value = self.state.stack.pop()?;
                 journal.push(JournalEntry::StackPop { value });
-                let old_value = self.state.storage.insert(key, value);
-                journal.push(JournalEntry::StorageWrite { key, old_value, new_value: value });
+                let address = self.active_address();
+                let old_value = self.storage_mut(address).insert(key, value);
+                journal.push(JournalEntry::StorageWrite { address, key, old_value, new_value: value });
             }
             
             Opcode::Jump => {
@@ -318,16 +647,28 @@ impl Vm {
             }
             
             Opcode::JumpDest => {}
+
+            Opcode::Push0 => {
+                self.state.stack.push(U256::ZERO)?;
+                journal.push(JournalEntry::StackPush { value: U256::ZERO });
+            }
             
             Opcode::Return => {
                 let offset = self.state.stack.pop()?;
                 journal.push(JournalEntry::StackPop { value: offset });
                 let size = self.state.stack.pop()?;
                 journal.push(JournalEntry::StackPop { value: size });
-                let mut return_data = vec![0u8; size.as_usize()];
-                for i in 0..size.as_usize() {
-                    return_data[i] = self.state.memory.load_byte(offset.as_usize() + i);
+                let (offset, size) = Memory::checked_range(offset, size)?;
+                if size > 0 {
+                    self.charge_memory_expansion(opcode, offset + size, journal)?;
                 }
+                let old_size = self.state.memory.size();
+                let return_data = self.mem_read(offset, size)?;
+                let new_size = self.state.memory.size();
+                if new_size > old_size {
+                    journal.push(JournalEntry::MemoryExpansion { old_size, new_size });
+                }
+                self.set_return_data(return_data.clone(), journal);
                 return Ok(Some(HaltReason::Return(return_data)));
             }
             
@@ -336,20 +677,49 @@ impl Vm {
                 journal.push(JournalEntry::StackPop { value: offset });
                 let size = self.state.stack.pop()?;
                 journal.push(JournalEntry::StackPop { value: size });
-                let mut return_data = vec![0u8; size.as_usize()];
-                for i in 0..size.as_usize() {
-                    return_data[i] = self.state.memory.load_byte(offset.as_usize() + i);
+                let (offset, size) = Memory::checked_range(offset, size)?;
+                if size > 0 {
+                    self.charge_memory_expansion(opcode, offset + size, journal)?;
+                }
+                let old_size = self.state.memory.size();
+                let return_data = self.mem_read(offset, size)?;
+                let new_size = self.state.memory.size();
+                if new_size > old_size {
+                    journal.push(JournalEntry::MemoryExpansion { old_size, new_size });
                 }
+                self.set_return_data(return_data.clone(), journal);
                 return Ok(Some(HaltReason::Revert(return_data)));
             }
             
-            Opcode::Invalid => return Ok(Some(HaltReason::InvalidOpcode(opcode as u8))),
+            Opcode::CallDataCopy | Opcode::CodeCopy | Opcode::ReturnDataCopy | Opcode::ExtCodeCopy => {
+                self.execute_copy(opcode, journal)?;
+            }
+
+            Opcode::Call | Opcode::CallCode | Opcode::DelegateCall => {
+                self.execute_call(opcode, journal)?;
+            }
+
+            Opcode::Log0 | Opcode::Log1 | Opcode::Log2 | Opcode::Log3 | Opcode::Log4 => {
+                self.execute_log(opcode, journal)?;
+            }
+            
+            Opcode::Invalid => {
+                self.consume_all_gas(journal);
+                return Ok(Some(HaltReason::InvalidOpcode(opcode as u8)));
+            }
             
-            _ => {} // Unimplemented opcodes - no-op
+            // Unimplemented opcodes are no-ops in lenient mode
+            _ if self.strict_opcodes => {
+                return Err(VmError::UnimplementedOpcode { opcode: opcode as u8 });
+            }
+            _ => {}
         }
         Ok(None)
     }
 
+    /// Immediate bytes past the end of the code read as zero. The pc still
+    /// skips the full immediate, so it may land past the end, where the next
+    /// step halts like STOP.
     fn execute_push(&mut self, opcode: Opcode, journal: &mut InstructionJournal) -> VmResult<Option<HaltReason>> {
         let size = opcode.immediate_size();
         let mut bytes = [0u8; 32];
@@ -386,34 +756,1139 @@ impl Vm {
         Ok(None)
     }
 
-    fn create_state_snapshot(&self) -> StateSnapshot {
+    /// CALL, CALLCODE and DELEGATECALL: dispatch precompiles, enter a
+    /// frame for targets with code, and treat other targets as code-less
+    /// accounts that succeed with empty return data. Value is not transferred.
+    ///
+    /// CALL runs the target's code against the target's storage. CALLCODE
+    /// runs it against the current account's storage, as a call from the
+    /// current account. DELEGATECALL also keeps the current account's storage
+    /// and additionally preserves the current caller and value.
+    fn execute_call(&mut self, kind: Opcode, journal: &mut InstructionJournal) -> VmResult<()> {
+        let mut args = [U256::ZERO; 7];
+        for arg in args.iter_mut().take(kind.stack_inputs()) {
+            *arg = self.state.stack.pop()?;
+            journal.push(JournalEntry::StackPop { value: *arg });
+        }
+        let [gas, target, value, args_offset, args_size, ret_offset, ret_size] = match kind {
+            Opcode::DelegateCall => {
+                let [gas, target, args_offset, args_size, ret_offset, ret_size, _] = args;
+                [gas, target, self.active_call_value(), args_offset, args_size, ret_offset, ret_size]
+            }
+            _ => args,
+        };
+        let target = Address::from_word(target);
+        let (ret_offset, ret_size) = Memory::checked_range(ret_offset, ret_size)?;
+        let (args_offset, args_size) = Memory::checked_range(args_offset, args_size)?;
+        let end = [(args_offset, args_size), (ret_offset, ret_size)]
+            .into_iter()
+            .filter(|&(_, size)| size > 0)
+            .map(|(offset, size)| offset + size)
+            .max();
+        if let Some(end) = end {
+            self.charge_memory_expansion(kind, end, journal)?;
+        }
+
+        if self.call_stack.len() >= self.max_call_depth {
+            return Err(VmError::CallDepthExceeded { max: self.max_call_depth });
+        }
+
+        let old_size = self.state.memory.size();
+        let input = self.mem_read(args_offset, args_size)?;
+        if ret_size > 0 {
+            self.mem_expand(ret_offset + ret_size)?;
+        }
+        let new_size = self.state.memory.size();
+        if new_size > old_size {
+            journal.push(JournalEntry::MemoryExpansion { old_size, new_size });
+        }
+
+        // All but one 64th of the remaining gas may be forwarded
+        let available = self.state.gas - self.state.gas / 64;
+        let forwarded = if gas > U256::from(available) { available } else { gas.as_u64() };
+
+        let code = self.shared_code_at(target);
+        if !code.is_empty() {
+            let (address, caller) = match kind {
+                Opcode::Call => (target, self.active_address()),
+                Opcode::CallCode => (self.active_address(), self.active_address()),
+                _ => (self.active_address(), self.active_caller()),
+            };
+            let mut frame = CallFrame::new(code, address, caller, value, input, forwarded, false);
+            frame.return_offset = ret_offset;
+            frame.return_size = ret_size;
+            self.enter_call(frame, journal);
+            return Ok(());
+        }
+
+        let (success, gas_spent, output) = match Precompile::from_address(&target) {
+            Some(precompile) => {
+                let cost = if self.gas_metering { precompile.gas_cost(input.len()) } else { 0 };
+                if cost > forwarded {
+                    (false, forwarded, Vec::new())
+                } else {
+                    (true, cost, precompile.execute(&input))
+                }
+            }
+            None => (true, 0, Vec::new()),
+        };
+
+        if gas_spent > 0 {
+            let old_gas = self.state.gas;
+            self.state.gas -= gas_spent;
+            journal.push(JournalEntry::GasChange { old_gas, new_gas: self.state.gas });
+        }
+        self.complete_call(success, output, ret_offset, ret_size, journal)
+    }
+
+    /// Append a log with the topics LOG0..LOG4 pop, charging 8 gas per byte
+    /// of data on top of the base cost
+    fn execute_log(&mut self, opcode: Opcode, journal: &mut InstructionJournal) -> VmResult<()> {
+        let offset = self.state.stack.pop()?;
+        journal.push(JournalEntry::StackPop { value: offset });
+        let size = self.state.stack.pop()?;
+        journal.push(JournalEntry::StackPop { value: size });
+        let topic_count = (opcode as u8 - Opcode::Log0 as u8) as usize;
+        let mut topics = Vec::with_capacity(topic_count);
+        for _ in 0..topic_count {
+            let topic = self.state.stack.pop()?;
+            journal.push(JournalEntry::StackPop { value: topic });
+            topics.push(topic);
+        }
+
+        let (offset, size) = Memory::checked_range(offset, size)?;
+        let data_gas = if self.charges_gas(opcode) { (size as u64).saturating_mul(8) } else { 0 };
+        if self.state.gas < data_gas {
+            return Err(VmError::OutOfGas { required: data_gas, available: self.state.gas });
+        }
+        if data_gas > 0 {
+            let old_gas = self.state.gas;
+            self.state.gas -= data_gas;
+            journal.push(JournalEntry::GasChange { old_gas, new_gas: self.state.gas });
+        }
+        if size > 0 {
+            self.charge_memory_expansion(opcode, offset + size, journal)?;
+        }
+
+        let old_size = self.state.memory.size();
+        let data = self.mem_read(offset, size)?;
+        let new_size = self.state.memory.size();
+        if new_size > old_size {
+            journal.push(JournalEntry::MemoryExpansion { old_size, new_size });
+        }
+
+        let index = self.state.logs.len();
+        self.state.logs.push(LogRecord { address: self.active_address(), topics, data });
+        journal.push(JournalEntry::LogEmitted { index });
+        Ok(())
+    }
+
+    /// Copy part of the call data, code, return data or another account's
+    /// code into memory, charging 3 gas per word copied plus memory
+    /// expansion on top of the base cost. Reads past the end of the source
+    /// are zero-filled, except for return data, where they fail.
+    fn execute_copy(&mut self, opcode: Opcode, journal: &mut InstructionJournal) -> VmResult<()> {
+        let account = if opcode == Opcode::ExtCodeCopy {
+            let word = self.state.stack.pop()?;
+            journal.push(JournalEntry::StackPop { value: word });
+            Some(Address::from_word(word))
+        } else {
+            None
+        };
+        let mut args = [U256::ZERO; 3];
+        for arg in &mut args {
+            *arg = self.state.stack.pop()?;
+            journal.push(JournalEntry::StackPop { value: *arg });
+        }
+        let [dest_offset, src_offset, size] = args;
+        let (dest, len) = Memory::checked_range(dest_offset, size)?;
+
+        let start = fits_usize(src_offset);
+        let available = self.state.return_data.len();
+        if opcode == Opcode::ReturnDataCopy
+            && start.and_then(|start| start.checked_add(len)).is_none_or(|end| end > available)
+        {
+            return Err(VmError::ReturnDataOutOfBounds { offset: start.unwrap_or(usize::MAX), size: len, available });
+        }
+
+        let old_size = self.state.memory.size();
+        if self.charges_gas(opcode) {
+            let words = (len as u64).div_ceil(32);
+            let expansion = if len == 0 { 0 } else { Memory::expansion_cost(old_size, dest + len) };
+            let copy_gas = words.saturating_mul(3).saturating_add(expansion);
+            if self.state.gas < copy_gas {
+                return Err(VmError::OutOfGas { required: copy_gas, available: self.state.gas });
+            }
+            if copy_gas > 0 {
+                let old_gas = self.state.gas;
+                self.state.gas -= copy_gas;
+                journal.push(JournalEntry::GasChange { old_gas, new_gas: self.state.gas });
+            }
+        }
+
+        if len == 0 {
+            return Ok(());
+        }
+        if self.strict_memory {
+            self.state.memory.try_expand(dest + len)?;
+        }
+        // Only allocate once gas and the memory ceiling have vouched for `len`
+        let source: &[u8] = match (opcode, account) {
+            (_, Some(address)) => self.code_at(address),
+            (Opcode::CallDataCopy, _) => self.active_calldata(),
+            (Opcode::ReturnDataCopy, _) => &self.state.return_data,
+            _ => &self.bytecode,
+        };
+        let mut data = vec![0u8; len];
+        let available = source.get(start.unwrap_or(usize::MAX)..).unwrap_or(&[]);
+        let copied = available.len().min(len);
+        data[..copied].copy_from_slice(&available[..copied]);
+        let old_data = self.state.memory.store_bytes(dest, &data);
+        let new_size = self.state.memory.size();
+        if new_size > old_size {
+            journal.push(JournalEntry::MemoryExpansion { old_size, new_size });
+        }
+        journal.push(JournalEntry::MemoryWrite { offset: dest, old_data, new_data: data });
+        Ok(())
+    }
+
+    /// Hand `frame.gas` to a new frame and switch to its fresh context,
+    /// setting the caller's context aside in the frame
+    fn enter_call(&mut self, mut frame: CallFrame, journal: &mut InstructionJournal) {
+        let old_gas = self.state.gas;
+        self.state.gas -= frame.gas;
+        journal.push(JournalEntry::GasChange { old_gas, new_gas: self.state.gas });
+
+        let old_pc = self.state.pc;
+        self.state.pc = old_pc + 1;
+        journal.push(JournalEntry::PcChange { old_pc, new_pc: self.state.pc });
+
+        let old_data = std::mem::take(&mut self.state.return_data);
+        journal.push(JournalEntry::ReturnDataSet { old_data, new_data: Vec::new() });
+
+        // Calling into the code already running reuses its analysis
+        let (jump_dests, decoded) = if Arc::ptr_eq(&frame.code, &self.bytecode) {
+            (self.jump_dests.clone(), self.decoded.clone())
+        } else {
+            (Vm::analyze_jump_dests(&frame.code).into(), Vm::decode_table(&frame.code).into())
+        };
+        frame.saved = SavedContext {
+            pc: 0,
+            gas: frame.gas,
+            stack: Vec::new(),
+            memory: Memory::with_ceiling(self.state.memory.ceiling()),
+            code: frame.code.clone(),
+            jump_dests,
+            decoded,
+        };
+        frame.journal_index = self.journal.end_index();
+        frame.log_index = self.state.logs.len();
+        self.swap_call_context(&mut frame);
+        journal.push(JournalEntry::CallEnter { callee_frame: frame.snapshot() });
+        self.call_stack.push(frame);
+        self.state.call_depth += 1;
+    }
+
+    /// Return from the active frame to its caller, refunding unused gas and
+    /// handing back the output of `reason`
+    fn exit_call(&mut self, reason: HaltReason, journal: &mut InstructionJournal) -> VmResult<()> {
+        let Some(mut frame) = self.call_stack.pop() else {
+            return Ok(());
+        };
+        self.state.call_depth -= 1;
+
+        let (success, output) = match reason {
+            HaltReason::Stop => (true, Vec::new()),
+            HaltReason::Return(data) => (true, data),
+            HaltReason::Revert(data) => (false, data),
+            _ => (false, Vec::new()),
+        };
+        if !success {
+            self.revert_storage_since(frame.journal_index, journal);
+            let logs = self.state.logs.split_off(frame.log_index.min(self.state.logs.len()));
+            if !logs.is_empty() {
+                journal.push(JournalEntry::LogsDiscarded { logs });
+            }
+        }
+
+        self.swap_call_context(&mut frame);
+        let leftover = frame.saved.gas;
+        let (ret_offset, ret_size) = (frame.return_offset, frame.return_size);
+        journal.push(JournalEntry::CallExit { callee_frame: Box::new(frame), return_data: output.clone() });
+
+        if leftover > 0 {
+            let old_gas = self.state.gas;
+            self.state.gas += leftover;
+            journal.push(JournalEntry::GasChange { old_gas, new_gas: self.state.gas });
+        }
+        // The return region was expanded when the call was entered
+        self.complete_call(success, output, ret_offset, ret_size, journal)
+    }
+
+    /// Roll back every storage write journaled since `index`, including those
+    /// of the instruction in progress.
+    ///
+    /// The rollback is itself journaled as ordinary storage writes, so
+    /// rewinding past a reverted call replays the callee's writes exactly.
+    fn revert_storage_since(&mut self, index: usize, journal: &mut InstructionJournal) {
+        let start = index.saturating_sub(self.journal.first_index());
+        let recorded = (start..self.journal.len()).rev().filter_map(|i| self.journal.get(i));
+        let undo: Vec<(Address, U256, U256)> = std::iter::once(&*journal)
+            .chain(recorded)
+            .flat_map(|insn| insn.entries.iter().rev())
+            .filter_map(|entry| match entry {
+                JournalEntry::StorageWrite { address, key, old_value, .. } => {
+                    Some((*address, *key, *old_value))
+                }
+                _ => None,
+            })
+            .collect();
+
+        for (address, key, value) in undo {
+            let old_value = self.storage_mut(address).insert(key, value);
+            journal.push(JournalEntry::StorageWrite { address, key, old_value, new_value: value });
+        }
+    }
+
+    /// Copy call output into the caller's return region, record it as return
+    /// data and push the success flag
+    fn complete_call(
+        &mut self,
+        success: bool,
+        output: Vec<u8>,
+        ret_offset: usize,
+        ret_size: usize,
+        journal: &mut InstructionJournal,
+    ) -> VmResult<()> {
+        let copy_len = output.len().min(ret_size);
+        if copy_len > 0 {
+            let new_data = output[..copy_len].to_vec();
+            let old_data = self.state.memory.store_bytes(ret_offset, &new_data);
+            journal.push(JournalEntry::MemoryWrite { offset: ret_offset, old_data, new_data });
+        }
+
+        self.set_return_data(output, journal);
+
+        let value = if success { U256::ONE } else { U256::ZERO };
+        self.state.stack.push(value)?;
+        journal.push(JournalEntry::StackPush { value });
+        Ok(())
+    }
+
+    /// Replace the return data, journaling the previous value
+    fn set_return_data(&mut self, data: Vec<u8>, journal: &mut InstructionJournal) {
+        let old_data = std::mem::replace(&mut self.state.return_data, data.clone());
+        journal.push(JournalEntry::ReturnDataSet { old_data, new_data: data });
+    }
+
+    /// Exchange the live pc, gas, stack, memory and code with those set
+    /// aside in `frame`. Entering and leaving a call are both this swap, so
+    /// each is its own inverse.
+    pub(crate) fn swap_call_context(&mut self, frame: &mut CallFrame) {
+        let saved = &mut frame.saved;
+        std::mem::swap(&mut self.state.pc, &mut saved.pc);
+        std::mem::swap(&mut self.state.gas, &mut saved.gas);
+        std::mem::swap(&mut self.state.memory, &mut saved.memory);
+        std::mem::swap(&mut self.bytecode, &mut saved.code);
+        std::mem::swap(&mut self.jump_dests, &mut saved.jump_dests);
+        std::mem::swap(&mut self.decoded, &mut saved.decoded);
+        let stack = self.state.stack.to_vec();
+        self.state.stack.restore_from(&saved.stack);
+        saved.stack = stack;
+    }
+
+    /// Charge the gas for growing memory to cover `end`, journaled; nothing
+    /// when memory is already that large
+    fn charge_memory_expansion(&mut self, opcode: Opcode, end: usize, journal: &mut InstructionJournal) -> VmResult<()> {
+        if !self.charges_gas(opcode) {
+            return Ok(());
+        }
+        let cost = Memory::expansion_cost(self.state.memory.size(), end);
+        if cost == 0 {
+            return Ok(());
+        }
+        if self.state.gas < cost {
+            return Err(VmError::OutOfGas { required: cost, available: self.state.gas });
+        }
+        let old_gas = self.state.gas;
+        self.state.gas -= cost;
+        journal.push(JournalEntry::GasChange { old_gas, new_gas: self.state.gas });
+        Ok(())
+    }
+
+    fn mem_expand(&mut self, min_size: usize) -> VmResult<()> {
+        if self.strict_memory {
+            self.state.memory.try_expand(min_size)
+        } else {
+            self.state.memory.expand(min_size);
+            Ok(())
+        }
+    }
+
+    fn mem_load(&mut self, offset: usize) -> VmResult<U256> {
+        if self.strict_memory {
+            self.state.memory.try_load(offset)
+        } else {
+            Ok(self.state.memory.load(offset))
+        }
+    }
+
+    fn mem_store(&mut self, offset: usize, value: U256) -> VmResult<Vec<u8>> {
+        if self.strict_memory {
+            self.state.memory.try_store(offset, value)
+        } else {
+            Ok(self.state.memory.store(offset, value))
+        }
+    }
+
+    fn mem_store_byte(&mut self, offset: usize, value: u8) -> VmResult<u8> {
+        if self.strict_memory {
+            self.state.memory.try_store_byte(offset, value)
+        } else {
+            Ok(self.state.memory.store_byte(offset, value))
+        }
+    }
+
+    fn mem_read(&mut self, offset: usize, size: usize) -> VmResult<Vec<u8>> {
+        (0..size)
+            .map(|i| {
+                if self.strict_memory {
+                    self.state.memory.try_load_byte(offset + i)
+                } else {
+                    Ok(self.state.memory.load_byte(offset + i))
+                }
+            })
+            .collect()
+    }
+
+    /// Capture the current state as a checkpoint at the current instruction index
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint::new(self.journal.len(), self.create_state_snapshot())
+    }
+
+    /// Checkpoint the current position on top of the periodic ones, e.g.
+    /// before an expensive region that will be rewound into later
+    pub fn add_manual_checkpoint(&mut self) {
+        if self.journal.checkpoints().last().is_some_and(|c| c.instruction_index == self.journal.len()) {
+            return;
+        }
+        self.journal.add_checkpoint(self.checkpoint());
+    }
+
+    /// Capture the current state of the active frame and all storage
+    pub fn create_state_snapshot(&self) -> StateSnapshot {
         StateSnapshot {
             stack: self.state.stack.to_vec(),
-            memory: self.state.memory.snapshot(),
+            memory: if self.sparse_checkpoints { Vec::new() } else { self.state.memory.snapshot() },
+            sparse_memory: self.sparse_checkpoints.then(|| self.state.memory.sparse_snapshot()),
             storage: self.state.storage.snapshot(),
+            account_storage: self
+                .state
+                .accounts
+                .iter()
+                .map(|(address, account)| (*address, account.storage.snapshot()))
+                .collect(),
             pc: self.state.pc,
             gas: self.state.gas,
             call_depth: self.state.call_depth,
             return_data: self.state.return_data.clone(),
+            logs: self.state.logs.clone(),
+            accessed_addresses: self.state.accessed_addresses.clone(),
+            accessed_slots: self.state.accessed_slots.clone(),
         }
     }
 
+    /// Gas held by the root call, counting gas forwarded to active calls as
+    /// not yet spent
+    fn root_gas(&self) -> u64 {
+        self.call_stack.first().map_or(self.state.gas, |frame| frame.saved.gas + frame.gas)
+    }
+
+    /// Gas consumed since the root call started
+    pub fn gas_used(&self) -> u64 {
+        self.initial_gas.saturating_sub(self.root_gas())
+    }
+
     pub fn run(&mut self) -> VmResult<ExecutionResult> {
-        let initial_gas = self.state.gas;
         loop {
             match self.step_forward()? {
                 StepResult::Halted { reason } => {
-                    let gas_used = initial_gas - self.state.gas;
-                    return Ok(match reason {
-                        HaltReason::Stop => ExecutionResult::Success { return_data: Vec::new(), gas_used },
-                        HaltReason::Return(data) => ExecutionResult::Success { return_data: data, gas_used },
-                        HaltReason::Revert(data) => ExecutionResult::Revert { return_data: data, gas_used },
-                        _ => ExecutionResult::Halt { reason, gas_used },
-                    });
+                    return Ok(ExecutionResult::from_halt(reason, self.gas_used()));
                 }
                 StepResult::Executed { .. } => continue,
                 StepResult::Rewound { .. } => unreachable!(),
             }
         }
     }
+
+    /// Run arbitrary `bytecode` with `gas` for fuzzing.
+    ///
+    /// This never panics, whatever the input: memory is capped at
+    /// `FUZZ_MEMORY_CEILING` bytes, and every error becomes an exceptional
+    /// halt that consumes all gas. Run time is bounded only by `gas`.
+    pub fn run_fuzz(bytecode: Vec<u8>, gas: u64) -> ExecutionResult {
+        let mut vm = Vm::new(bytecode, gas, BlockContext::default());
+        vm.state.memory.set_ceiling(FUZZ_MEMORY_CEILING);
+        vm.strict_memory = true;
+        match vm.run() {
+            Ok(result) => result,
+            Err(err) => {
+                let reason = match err {
+                    VmError::OutOfGas { .. } => HaltReason::OutOfGas,
+                    VmError::InvalidJump { .. } => HaltReason::InvalidJump,
+                    VmError::InvalidOpcode { opcode } | VmError::UnimplementedOpcode { opcode } => {
+                        HaltReason::InvalidOpcode(opcode)
+                    }
+                    err => HaltReason::Error(Box::new(err)),
+                };
+                ExecutionResult::Halt { reason, gas_used: gas }
+            }
+        }
+    }
+}
+
+/// Memory limit for `Vm::run_fuzz`
+pub const FUZZ_MEMORY_CEILING: usize = 1 << 20;
+
+/// Halt reason for an error the EVM treats as an exceptional halt of the
+/// running call; `None` for errors that are limits of the debugger itself
+fn exceptional_halt(err: &VmError) -> Option<HaltReason> {
+    match err {
+        VmError::OutOfGas { .. } => Some(HaltReason::OutOfGas),
+        VmError::InvalidJump { .. } => Some(HaltReason::InvalidJump),
+        VmError::InvalidOpcode { opcode } => Some(HaltReason::InvalidOpcode(*opcode)),
+        VmError::StackUnderflow { .. }
+        | VmError::StackOverflow { .. }
+        | VmError::WriteProtectedStorage
+        | VmError::ReturnDataOutOfBounds { .. } => Some(HaltReason::Error(Box::new(err.clone()))),
+        _ => None,
+    }
+}
+
+/// `word` as a usize, if it fits
+fn fits_usize(word: U256) -> Option<usize> {
+    if word.0[1..].iter().any(|&limb| limb != 0) {
+        return None;
+    }
+    usize::try_from(word.0[0]).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_fuzz_never_panics() {
+        // xorshift keeps the inputs reproducible without a rand dependency
+        let mut seed = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        for _ in 0..5_000 {
+            let len = (next() % 96) as usize;
+            let bytecode: Vec<u8> = (0..len)
+                .map(|_| match next() % 4 {
+                    // Lean towards huge operands so offsets and sizes overflow
+                    0 => 0x7F,
+                    1 => 0xFF,
+                    _ => next() as u8,
+                })
+                .collect();
+            let result = Vm::run_fuzz(bytecode, 50_000);
+            assert!(result.gas_used() <= 50_000);
+        }
+    }
+
+    #[test]
+    fn test_strict_memory_ceiling() {
+        // PUSH2 0x0401, MLOAD, STOP
+        let bytecode = vec![0x61, 0x04, 0x01, 0x51, 0x00];
+
+        let mut lenient = Vm::new(bytecode.clone(), 100_000, BlockContext::default());
+        lenient.state_mut().memory.set_ceiling(1024);
+        assert!(matches!(lenient.run().unwrap(), ExecutionResult::Success { .. }));
+
+        let mut strict = Vm::new(bytecode, 100_000, BlockContext::default());
+        strict.state_mut().memory.set_ceiling(1024);
+        strict.set_strict_memory(true);
+        strict.step_forward().unwrap();
+        assert_eq!(
+            strict.step_forward().unwrap_err(),
+            VmError::OutOfBoundsMemory { offset: 1025, size: 32 }
+        );
+        assert_eq!(strict.state().memory.size(), 0);
+    }
+
+    /// Run `OP(a, b)`, or `OP(a)` for ISZERO, and return the result
+    fn compare(opcode: Opcode, a: U256, b: U256) -> U256 {
+        let mut bytecode = Vec::new();
+        for operand in [b, a] {
+            bytecode.push(0x7F);
+            bytecode.extend_from_slice(&operand.to_be_bytes());
+        }
+        bytecode.extend_from_slice(&[opcode as u8, 0x00]);
+        let mut vm = Vm::new(bytecode, 100_000, BlockContext::default());
+        vm.run().unwrap();
+        vm.state().stack.peek(0).unwrap()
+    }
+
+    /// Reference ordering on big-endian bytes, signed when `signed` is set
+    fn reference_cmp(a: U256, b: U256, signed: bool) -> Ordering {
+        let (mut a, mut b) = (a.to_be_bytes(), b.to_be_bytes());
+        if signed {
+            a[0] ^= 0x80;
+            b[0] ^= 0x80;
+        }
+        a.cmp(&b)
+    }
+
+    #[test]
+    fn test_comparisons_against_reference() {
+        let sign_bit = U256([0, 0, 0, 1 << 63]);
+        let boundary = [
+            U256::ZERO,
+            U256::ONE,
+            U256::from(2u64),
+            U256::from(u64::MAX),
+            U256([0, 1, 0, 0]),
+            U256([u64::MAX, u64::MAX, u64::MAX, u64::MAX >> 1]),
+            sign_bit,
+            U256([1, 0, 0, 1 << 63]),
+            U256::MAX.wrapping_sub(U256::ONE),
+            U256::MAX,
+        ];
+        let flag = |holds: bool| if holds { U256::ONE } else { U256::ZERO };
+
+        for &a in &boundary {
+            assert_eq!(compare(Opcode::IsZero, a, U256::ZERO), flag(a == U256::ZERO), "ISZERO({a:?})");
+            for &b in &boundary {
+                let unsigned = reference_cmp(a, b, false);
+                let signed = reference_cmp(a, b, true);
+                assert_eq!(compare(Opcode::Eq, a, b), flag(a == b), "EQ({a:?}, {b:?})");
+                assert_eq!(compare(Opcode::Lt, a, b), flag(unsigned.is_lt()), "LT({a:?}, {b:?})");
+                assert_eq!(compare(Opcode::Gt, a, b), flag(unsigned.is_gt()), "GT({a:?}, {b:?})");
+                assert_eq!(compare(Opcode::Slt, a, b), flag(signed.is_lt()), "SLT({a:?}, {b:?})");
+                assert_eq!(compare(Opcode::Sgt, a, b), flag(signed.is_gt()), "SGT({a:?}, {b:?})");
+            }
+        }
+
+        // -1 < 0 signed, but MAX > 0 unsigned
+        assert_eq!(compare(Opcode::Slt, U256::MAX, U256::ZERO), U256::ONE);
+        assert_eq!(compare(Opcode::Sgt, U256::ZERO, U256::MAX), U256::ONE);
+        assert_eq!(compare(Opcode::Lt, U256::MAX, U256::ZERO), U256::ZERO);
+        assert_eq!(compare(Opcode::Slt, sign_bit, U256::MAX), U256::ONE, "MIN < -1");
+    }
+
+    #[test]
+    fn test_step_reports_push_immediate() {
+        // PUSH1 0x42, PUSH1 1, ADD, STOP
+        let mut vm = Vm::new(vec![0x60, 0x42, 0x60, 0x01, 0x01, 0x00], 100_000, BlockContext::default());
+        let immediate = |result| match result {
+            StepResult::Executed { immediate, .. } => immediate,
+            other => panic!("expected an executed step, got {other:?}"),
+        };
+        assert_eq!(immediate(vm.step_forward().unwrap()), Some(U256::from(0x42u64)));
+        assert_eq!(immediate(vm.step_forward().unwrap()), Some(U256::ONE));
+        assert_eq!(immediate(vm.step_forward().unwrap()), None);
+    }
+
+    #[test]
+    fn test_step_reports_control_flow() {
+        // PUSH1 1, PUSH1 7, JUMPI, INVALID, JUMPDEST, STOP
+        let mut vm = Vm::new(vec![0x60, 0x01, 0x60, 0x07, 0x57, 0xFE, 0xFE, 0x5B, 0x00], 100_000, BlockContext::default());
+        let control_flow = |result| match result {
+            StepResult::Executed { control_flow, .. } => control_flow,
+            other => panic!("expected an executed step, got {other:?}"),
+        };
+        assert_eq!(control_flow(vm.step_forward().unwrap()), ControlFlow::Sequential);
+        vm.step_forward().unwrap();
+        assert_eq!(control_flow(vm.step_forward().unwrap()), ControlFlow::Jumped(7));
+        assert_eq!(control_flow(vm.step_forward().unwrap()), ControlFlow::Sequential);
+    }
+
+    #[test]
+    fn test_truncated_push_zero_fills_and_halts() {
+        for (opcode, width) in [(0x60u8, 1usize), (0x7F, 32)] {
+            let mut vm = Vm::new(vec![opcode], 100_000, BlockContext::default());
+            assert!(matches!(vm.step_forward().unwrap(), StepResult::Executed { immediate: Some(U256::ZERO), .. }));
+            assert_eq!(vm.state().stack.as_slice(), [U256::ZERO]);
+            assert_eq!(vm.state().pc, 1 + width);
+            assert!(matches!(vm.step_forward().unwrap(), StepResult::Halted { reason: HaltReason::Stop }));
+            assert_eq!(vm.journal().len(), 1, "running off the end records nothing");
+
+            vm.step_backward().unwrap();
+            assert_eq!(vm.state().pc, 0);
+            assert!(vm.state().stack.is_empty());
+            assert_eq!(vm.gas_used(), 0);
+        }
+    }
+
+    #[test]
+    fn test_mstore_offset_past_usize_errors() {
+        // PUSH1 0x42, PUSH9 2^64 + 5, MSTORE, STOP
+        let bytecode = vec![0x60, 0x42, 0x68, 0x01, 0, 0, 0, 0, 0, 0, 0, 0x05, 0x52, 0x00];
+        let mut vm = Vm::new(bytecode, 100_000, BlockContext::default());
+        vm.step_forward().unwrap();
+        vm.step_forward().unwrap();
+
+        assert!(matches!(vm.step_forward(), Err(VmError::OutOfBoundsMemory { .. })));
+        assert_eq!(vm.state().memory.size(), 0, "nothing was written at offset 5");
+        assert_eq!(vm.state().stack.len(), 2);
+    }
+
+    /// Store `word` at 0, then CALL `address` with input `memory[args_offset..32]`
+    /// and a 32-byte output buffer at 32
+    fn call_bytecode(word: u32, args_offset: u8, address: u8) -> Vec<u8> {
+        let mut code = vec![0x63];
+        code.extend_from_slice(&word.to_be_bytes());
+        code.extend_from_slice(&[
+            0x60, 0x00, 0x52,       // MSTORE at 0
+            0x60, 0x20, 0x60, 0x20, // retSize, retOffset
+            0x60, 32 - args_offset, 0x60, args_offset,
+            0x60, 0x00, 0x60, address,
+            0x61, 0xFF, 0xFF,       // gas
+            0xF1, 0x00,             // CALL, STOP
+        ]);
+        code
+    }
+
+    #[test]
+    fn test_identity_precompile_echoes_input() {
+        let bytecode = call_bytecode(0xDEADBEEF, 0, 0x04);
+        let mut vm = Vm::new(bytecode.clone(), 100_000, BlockContext::default());
+        let result = vm.run().unwrap();
+
+        assert_eq!(vm.state().stack.peek(0).unwrap(), U256::ONE);
+        assert_eq!(vm.state().memory.snapshot()[32..], vm.state().memory.snapshot()[..32]);
+        assert_eq!(vm.state().return_data, U256::from(0xDEADBEEFu64).to_be_bytes());
+        // 9 pushes, MSTORE with expansion, CALL base and output expansion
+        // plus 15 + 3 per word
+        let call_expansion = Memory::expansion_cost(32, 64);
+        assert_eq!(result.gas_used(), 9 * 3 + 3 + Memory::expansion_cost(0, 32) + 100 + call_expansion + 18);
+
+        crate::testing::assert_roundtrip(&bytecode, 100_000);
+    }
+
+    #[test]
+    fn test_sha256_precompile_digest() {
+        // "abc" sits in the last three bytes of the stored word
+        let bytecode = call_bytecode(0x00616263, 29, 0x02);
+        let mut vm = Vm::new(bytecode.clone(), 100_000, BlockContext::default());
+        vm.run().unwrap();
+
+        let digest: String = vm.state().memory.snapshot()[32..64]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        assert_eq!(digest, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+
+        crate::testing::assert_roundtrip(&bytecode, 100_000);
+    }
+
+    #[test]
+    fn test_precompile_out_of_gas_fails_call() {
+        let mut bytecode = call_bytecode(0x00616263, 29, 0x02);
+        // Forward only 16 gas to SHA-256, which needs 72
+        bytecode[21] = 0x00;
+        bytecode[22] = 0x10;
+        let mut vm = Vm::new(bytecode, 100_000, BlockContext::default());
+        let result = vm.run().unwrap();
+
+        assert_eq!(vm.state().stack.peek(0).unwrap(), U256::ZERO);
+        assert!(vm.state().return_data.is_empty());
+        let call_expansion = Memory::expansion_cost(32, 64);
+        assert_eq!(result.gas_used(), 9 * 3 + 3 + Memory::expansion_cost(0, 32) + 100 + call_expansion + 16);
+    }
+
+    #[test]
+    fn test_self_call_returns_into_caller_memory() {
+        // With no call data, CALL self with one byte of input and a 32-byte
+        // return buffer at 0; with call data, RETURN the word 42
+        let bytecode = vec![
+            0x36, 0x60, 0x13, 0x57,
+            0x60, 0x20, 0x60, 0x00, 0x60, 0x01, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00,
+            0x5A, 0xF1, 0x00,
+            0x5B, 0x60, 0x2A, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xF3,
+        ];
+        let mut vm = Vm::new(bytecode.clone(), 100_000, BlockContext::default());
+        assert!(matches!(vm.run().unwrap(), ExecutionResult::Success { .. }));
+        assert_eq!(vm.state().stack.as_slice(), &[U256::ONE]);
+        assert_eq!(vm.state_mut().memory.load(0), U256::from(42u64));
+        assert_eq!(vm.state().call_depth, 0);
+
+        // Step back over STOP and the callee's RETURN into the callee's frame
+        vm.rewind(2).unwrap();
+        assert_eq!(vm.state().call_depth, 1);
+        assert_eq!(vm.state().pc, 29);
+        assert_eq!(vm.active_calldata(), &[0x00]);
+
+        crate::testing::assert_roundtrip(&bytecode, 100_000);
+    }
+
+    #[test]
+    fn test_codecopy_charges_per_word_and_expansion() {
+        // CODECOPY 64 bytes of code from 0 to memory 0, then STOP
+        let bytecode = vec![0x60, 0x40, 0x60, 0x00, 0x60, 0x00, 0x39, 0x00];
+        let mut vm = Vm::new(bytecode.clone(), 100_000, BlockContext::default());
+        for _ in 0..3 {
+            vm.step_forward().unwrap();
+        }
+        let gas_before = vm.state().gas;
+        let StepResult::Executed { gas_used, .. } = vm.step_forward().unwrap() else {
+            panic!("CODECOPY should execute");
+        };
+        assert_eq!(gas_before - vm.state().gas, gas_used);
+        assert_eq!(gas_used, Opcode::CodeCopy.base_gas() + 6 + Memory::expansion_cost(0, 64));
+
+        let mut expected = bytecode.clone();
+        expected.resize(64, 0);
+        assert_eq!(vm.state().memory.snapshot(), expected);
+
+        crate::testing::assert_roundtrip(&bytecode, 100_000);
+    }
+
+    #[test]
+    fn test_returndatacopy_past_end_fails() {
+        // RETURNDATACOPY one byte with no return data
+        let bytecode = vec![0x60, 0x01, 0x60, 0x00, 0x60, 0x00, 0x3E, 0x00];
+        let mut vm = Vm::new(bytecode, 100_000, BlockContext::default());
+        assert_eq!(
+            vm.run().unwrap_err(),
+            VmError::ReturnDataOutOfBounds { offset: 0, size: 1, available: 0 }
+        );
+        assert_eq!(vm.state().stack.len(), 3, "the failed copy left no trace");
+    }
+
+    #[test]
+    fn test_rewinding_subcall_return_restores_return_data() {
+        // Echo a word through the identity precompile, then CALL 0xCC,
+        // which RETURNs the word 42
+        let mut bytecode = call_bytecode(0xDEADBEEF, 0, 0x04);
+        bytecode.pop();
+        bytecode.extend_from_slice(&[0x60, 0x00].repeat(5));
+        bytecode.extend_from_slice(&[0x60, 0xCC, 0x61, 0xFF, 0xFF, 0xF1, 0x00]);
+        let callee = Address::from_slice(&[0xCC]);
+        let callee_code = vec![0x60, 0x2A, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xF3];
+        let mut vm = Vm::new(bytecode, 100_000, BlockContext::default());
+        vm.set_code(callee, callee_code);
+        vm.run().unwrap();
+        assert_eq!(vm.state().return_data, U256::from(42u64).to_be_bytes());
+
+        // Undo STOP and the callee's RETURN: the callee saw no return data
+        vm.rewind(2).unwrap();
+        assert_eq!(vm.state().call_depth, 1);
+        assert!(vm.state().return_data.is_empty());
+
+        // Back out of the callee and its CALL: the precompile's output is back
+        vm.rewind(6).unwrap();
+        assert_eq!(vm.state().call_depth, 0);
+        assert_eq!(vm.state().return_data, U256::from(0xDEADBEEFu64).to_be_bytes());
+
+        // Replaying records the RETURN's own assignment
+        vm.run().unwrap();
+        let exit_step = vm.journal().iter().rev().nth(1).unwrap();
+        let assignments = exit_step
+            .entries
+            .iter()
+            .filter(|entry| matches!(entry, JournalEntry::ReturnDataSet { .. }))
+            .count();
+        assert_eq!(assignments, 2, "RETURN in the callee and the caller's completion");
+    }
+
+    #[test]
+    fn test_strict_opcodes_reject_unimplemented() {
+        // PUSH1 3, PUSH1 7, MOD, STOP
+        let bytecode = vec![0x60, 0x03, 0x60, 0x07, 0x06, 0x00];
+        let mut vm = Vm::new(bytecode.clone(), 100_000, BlockContext::default());
+        assert!(vm.strict_opcodes());
+        vm.step_forward().unwrap();
+        vm.step_forward().unwrap();
+        let gas = vm.state().gas;
+        assert_eq!(vm.step_forward().unwrap_err(), VmError::UnimplementedOpcode { opcode: 0x06 });
+        assert_eq!((vm.state().pc, vm.state().gas, vm.state().stack.len()), (4, gas, 2));
+
+        let mut lenient = Vm::new(bytecode, 100_000, BlockContext::default());
+        lenient.set_strict_opcodes(false);
+        assert!(lenient.run().is_ok());
+        assert_eq!(lenient.state().stack.len(), 2, "MOD is skipped");
+    }
+
+    #[test]
+    fn test_division_by_zero_modes() {
+        // DIV(5, 0), STOP
+        let bytecode = vec![0x60, 0x00, 0x60, 0x05, 0x04, 0x00];
+        let mut vm = Vm::new(bytecode.clone(), 100_000, BlockContext::default());
+        assert_eq!(vm.division_by_zero(), DivByZero::Zero);
+        vm.run().unwrap();
+        assert_eq!(vm.state().stack.as_slice(), &[U256::ZERO]);
+
+        let mut trapping = Vm::builder(bytecode).gas(100_000).division_by_zero(DivByZero::Trap).build().unwrap();
+        trapping.step_forward().unwrap();
+        trapping.step_forward().unwrap();
+        assert_eq!(trapping.step_forward().unwrap_err(), VmError::DivisionByZero);
+        assert_eq!(trapping.state().stack.len(), 2, "the failed DIV leaves no trace");
+    }
+
+    #[test]
+    fn test_addmod_keeps_the_carry() {
+        // ADDMOD(MAX, MAX, 5), ADDMOD(10, 20, 7), STOP
+        let mut bytecode = vec![0x60, 0x05, 0x7F];
+        bytecode.extend([0xFF; 32]);
+        bytecode.extend([0x80, 0x08, 0x60, 0x07, 0x60, 0x14, 0x60, 0x0A, 0x08, 0x00]);
+        let mut vm = Vm::new(bytecode, 100_000, BlockContext::default());
+        vm.run().unwrap();
+        assert_eq!(vm.state().stack.as_slice(), &[U256::ZERO, U256::from(2u64)]);
+    }
+
+    #[test]
+    fn test_mulmod_and_mul_are_full_width() {
+        // MULMOD(MAX, MAX, 7), then MUL(MAX, MAX), STOP
+        let mut bytecode = vec![0x60, 0x07, 0x7F];
+        bytecode.extend([0xFF; 32]);
+        bytecode.extend([0x80, 0x09, 0x60, 0x00, 0x19, 0x80, 0x02, 0x00]);
+        let mut vm = Vm::new(bytecode, 100_000, BlockContext::default());
+        vm.run().unwrap();
+        assert_eq!(vm.state().stack.as_slice(), &[U256::ONE, U256::ONE]);
+
+        vm.rewind(2).unwrap();
+        assert_eq!(vm.state().stack.as_slice(), &[U256::ONE, U256::MAX, U256::MAX]);
+    }
+
+    #[test]
+    fn test_every_instruction_journals_one_pc_change() {
+        fn assert_one_pc_change(vm: &Vm) {
+            for insn in vm.journal().iter() {
+                let changes = insn.entries.iter().filter(|e| matches!(e, JournalEntry::PcChange { .. })).count();
+                assert_eq!(changes, 1, "opcode {:#04x} at {:#x}", insn.opcode, insn.pc);
+            }
+        }
+
+        // JUMPI not taken, CALL self, JUMPI taken in the callee, RETURN
+        // from the callee, STOP
+        let self_call = vec![
+            0x36, 0x60, 0x13, 0x57,
+            0x60, 0x20, 0x60, 0x00, 0x60, 0x01, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00,
+            0x5A, 0xF1, 0x00,
+            0x5B, 0x60, 0x2A, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xF3,
+        ];
+        // PUSH1 4, JUMP, INVALID, JUMPDEST, then three CALLs to callees
+        // that hit an undecodable byte, run off their code and REVERT
+        let mut jumps = vec![0x60, 0x04, 0x56, 0xFE, 0x5B];
+        for callee in [0xC1, 0xC2, 0xC3] {
+            jumps.extend_from_slice(&[0x60, 0x00].repeat(5));
+            jumps.extend_from_slice(&[0x60, callee, 0x61, 0xFF, 0xFF, 0xF1]);
+        }
+        jumps.push(0x00);
+
+        for bytecode in [self_call, jumps] {
+            let mut vm = Vm::new(bytecode.clone(), 1_000_000, BlockContext::default());
+            vm.set_code(Address::from_slice(&[0xC1]), vec![0x0C]);
+            vm.set_code(Address::from_slice(&[0xC2]), vec![0x60, 0x01]);
+            vm.set_code(Address::from_slice(&[0xC3]), vec![0x60, 0x00, 0x80, 0xFD]);
+            vm.run().unwrap();
+            assert_one_pc_change(&vm);
+
+            let end = vm.compute_state_hash();
+            let len = vm.journal().len();
+            vm.rewind(len).unwrap();
+            vm.run().unwrap();
+            assert_eq!(vm.compute_state_hash(), end, "replay after a full rewind matches");
+        }
+        crate::testing::assert_roundtrip(&[0x60, 0x04, 0x56, 0xFE, 0x5B, 0x00], 100_000);
+    }
+
+    #[test]
+    fn test_call_forwards_at_most_63_64ths_of_gas() {
+        // CALL 0xBB (code: STOP) asking for 2^256 - 1 gas
+        let mut bytecode = [0x60, 0x00].repeat(5);
+        bytecode.extend_from_slice(&[0x60, 0xBB, 0x7F]);
+        bytecode.extend_from_slice(&[0xFF; 32]);
+        bytecode.extend_from_slice(&[0xF1, 0x00]);
+        let mut vm = Vm::new(bytecode, 100_000, BlockContext::default());
+        vm.set_code(Address::from_slice(&[0xBB]), vec![0x00]);
+
+        for _ in 0..7 {
+            vm.step_forward().unwrap();
+        }
+        let before_call = vm.state().gas;
+        vm.step_forward().unwrap();
+        let remaining = before_call - Opcode::Call.base_gas();
+        assert_eq!(vm.state().call_depth, 1);
+        assert_eq!(vm.state().gas, remaining - remaining / 64);
+        assert_eq!(vm.call_stack[0].saved.gas, remaining / 64);
+
+        // The callee's STOP refunds everything it was given
+        vm.step_forward().unwrap();
+        assert_eq!(vm.state().call_depth, 0);
+        assert_eq!(vm.state().gas, remaining);
+
+        vm.step_backward().unwrap();
+        assert_eq!(vm.state().gas, remaining - remaining / 64);
+        vm.step_backward().unwrap();
+        assert_eq!(vm.state().gas, before_call);
+    }
+
+    #[test]
+    fn test_failed_call_discards_its_logs() {
+        // Root CALLs itself with one byte of input; the callee emits an
+        // empty LOG0 and ends with `exit` (RETURN or REVERT of empty data)
+        for (exit, kept) in [(0xF3, 1), (0xFD, 0)] {
+            let bytecode = vec![
+                0x36, 0x60, 0x13, 0x57,
+                0x60, 0x00, 0x60, 0x00, 0x60, 0x01, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00,
+                0x5A, 0xF1, 0x00,
+                0x5B, 0x60, 0x00, 0x60, 0x00, 0xA0,
+                0x60, 0x00, 0x60, 0x00, exit,
+            ];
+            let mut vm = Vm::new(bytecode.clone(), 1_000_000, BlockContext::default());
+            vm.run().unwrap();
+            assert_eq!(vm.state().logs.len(), kept);
+
+            // Back over STOP and the callee's exit: the log is live again
+            vm.rewind(2).unwrap();
+            assert_eq!(vm.state().logs.len(), 1);
+            assert_eq!(vm.state().logs[0].address, Address::ZERO);
+            crate::testing::assert_roundtrip(&bytecode, 1_000_000);
+        }
+    }
+
+    #[test]
+    fn test_recursion_stops_at_max_call_depth() {
+        // CALL self with all gas, forever
+        let bytecode = vec![
+            0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00,
+            0x5A, 0xF1, 0x00,
+        ];
+        let mut vm = Vm::builder(bytecode).gas(1_000_000).max_call_depth(4).build().unwrap();
+        let initial = vm.state_hash();
+
+        assert_eq!(vm.run().unwrap_err(), VmError::CallDepthExceeded { max: 4 });
+        assert_eq!(vm.state().call_depth, 4);
+        assert_eq!(vm.state().stack.len(), 7, "failed CALL leaves its arguments");
+
+        vm.rewind(vm.journal().len()).unwrap();
+        assert_eq!(vm.state().call_depth, 0);
+        assert_eq!(vm.state_hash(), initial);
+    }
+
+    /// Root contract at 0xAA that calls 0xBB with `kind`; 0xBB's code stores
+    /// 0x42 in slot 0
+    fn storage_context_vm(kind: u8) -> Vm {
+        let mut bytecode = vec![0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00];
+        if kind != 0xF4 {
+            bytecode.extend_from_slice(&[0x60, 0x00]); // value
+        }
+        bytecode.extend_from_slice(&[0x60, 0xBB, 0x5A, kind, 0x00]);
+
+        let mut vm = Vm::builder(bytecode).gas(1_000_000).address(Address::from_slice(&[0xAA])).build().unwrap();
+        vm.set_code(Address::from_slice(&[0xBB]), vec![0x60, 0x42, 0x60, 0x00, 0x55, 0x00]);
+        vm
+    }
+
+    #[test]
+    fn test_call_kinds_pick_storage_context() {
+        let root = Address::from_slice(&[0xAA]);
+        let target = Address::from_slice(&[0xBB]);
+        let slot = U256::ZERO;
+        let stored = U256::from(0x42u64);
+
+        for (kind, root_value, target_value, frame_caller) in [
+            (0xF1, U256::ZERO, stored, root),
+            (0xF2, stored, U256::ZERO, root),
+            (0xF4, stored, U256::ZERO, Address::ZERO),
+        ] {
+            let mut vm = storage_context_vm(kind);
+            let initial = vm.state_hash();
+            while vm.state().call_depth == 0 {
+                vm.step_forward().unwrap();
+            }
+            let frame = vm.call_stack.last().unwrap();
+            assert_eq!(frame.caller, frame_caller, "caller for {kind:#04x}");
+            assert_eq!(vm.bytecode(), vm.code_at(target));
+
+            vm.run().unwrap();
+            assert_eq!(vm.storage_at(root, &slot), root_value, "root storage for {kind:#04x}");
+            assert_eq!(vm.storage_at(target, &slot), target_value, "target storage for {kind:#04x}");
+
+            vm.rewind(vm.journal().len()).unwrap();
+            assert_eq!(vm.storage_at(root, &slot), U256::ZERO);
+            assert_eq!(vm.storage_at(target, &slot), U256::ZERO);
+            assert_eq!(vm.state_hash(), initial);
+        }
+    }
+
+    #[test]
+    fn test_exceptional_halt_fails_only_the_subcall() {
+        let target = Address::from_slice(&[0xBB]);
+        // Each callee stores 0x42 in slot 0 first, then halts exceptionally
+        let callees: [&[u8]; 3] = [
+            // JUMPDEST, PUSH1 5, JUMP until out of gas
+            &[0x60, 0x42, 0x60, 0x00, 0x55, 0x5B, 0x60, 0x05, 0x56],
+            // PUSH1 3, JUMP into the middle of a PUSH
+            &[0x60, 0x42, 0x60, 0x00, 0x55, 0x60, 0x03, 0x56],
+            // ADD on an empty stack
+            &[0x60, 0x42, 0x60, 0x00, 0x55, 0x01],
+        ];
+        for callee in callees {
+            let mut vm = storage_context_vm(0xF1);
+            vm.set_code(target, callee.to_vec());
+            let initial = vm.state_hash();
+
+            let result = vm.run().unwrap();
+            assert!(matches!(result, ExecutionResult::Success { .. }), "{callee:02x?}: {result:?}");
+            assert_eq!(vm.state().stack.peek(0).unwrap(), U256::ZERO, "CALL pushes 0");
+            assert_eq!(vm.storage_at(target, &U256::ZERO), U256::ZERO, "callee write rolled back");
+            assert!(result.gas_used() > 1_000_000 * 63 / 64, "forwarded gas consumed");
+
+            vm.rewind(vm.journal().len()).unwrap();
+            assert_eq!(vm.state_hash(), initial);
+        }
+    }
+
+    #[test]
+    fn test_return_charges_memory_expansion() {
+        // PUSH4 16 MiB, PUSH1 0, RETURN, then the same with a 2^56 byte size
+        for size in [vec![0x63, 0x01, 0x00, 0x00, 0x00], vec![0x67, 0x01, 0, 0, 0, 0, 0, 0, 0]] {
+            let mut bytecode = size;
+            bytecode.extend_from_slice(&[0x60, 0x00, 0xF3]);
+            let mut vm = Vm::new(bytecode, 100, BlockContext::default());
+            assert!(matches!(vm.run(), Err(VmError::OutOfGas { .. })));
+            assert_eq!(vm.state().memory.size(), 0);
+        }
+    }
+
+    #[test]
+    fn test_invalid_consumes_all_gas() {
+        // PUSH1 1, INVALID
+        let mut vm = Vm::new(vec![0x60, 0x01, 0xFE], 100_000, BlockContext::default());
+        vm.step_forward().unwrap();
+        let gas_before = vm.state().gas;
+
+        let result = vm.step_forward().unwrap();
+        assert!(matches!(result, StepResult::Halted { reason: HaltReason::InvalidOpcode(0xFE) }));
+        assert_eq!(vm.state().gas, 0);
+
+        vm.step_backward().unwrap();
+        assert_eq!(vm.state().gas, gas_before);
+        assert_eq!(vm.state().pc, 2);
+    }
+
+    #[test]
+    fn test_undecodable_byte_halts_like_invalid() {
+        let mut vm = Vm::new(vec![0x60, 0x01, 0x0C], 100_000, BlockContext::default());
+        match vm.run().unwrap() {
+            ExecutionResult::Halt { reason, gas_used } => {
+                assert_eq!(reason, HaltReason::InvalidOpcode(0x0C));
+                assert_eq!(gas_used, 100_000);
+            }
+            other => panic!("expected halt, got {other:?}"),
+        }
+
+        vm.step_backward().unwrap();
+        assert_eq!(vm.state().gas, 100_000 - 3);
+        assert_eq!(vm.state().pc, 2);
+    }
 }
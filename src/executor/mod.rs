@@ -3,7 +3,15 @@
 mod opcodes;
 mod interpreter;
 mod reverse;
+mod precompiles;
+mod gas;
+mod trace;
+mod coverage;
 
-pub use opcodes::Opcode;
-pub use interpreter::{StepResult, ExecutionResult};
+pub use opcodes::{Opcode, OpcodeCategory};
+pub use interpreter::{StepResult, ControlFlow, ExecutionResult, FUZZ_MEMORY_CEILING};
 pub use reverse::apply_inverse;
+pub use precompiles::Precompile;
+pub use gas::{GasSchedule, COLD_ACCOUNT_ACCESS_COST, COLD_SLOAD_COST, WARM_STORAGE_READ_COST};
+pub use trace::{load_eip3155_trace, TraceStep};
+pub use coverage::{implemented_opcodes, is_implemented};
@@ -1,5 +1,7 @@
 //! Opcode definitions and metadata
 
+use crate::executor::GasSchedule;
+
 /// VM opcodes with forward and reverse semantics.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u8)]
@@ -79,6 +81,7 @@ pub enum Opcode {
     MSize = 0x59,
     Gas = 0x5A,
     JumpDest = 0x5B,
+    Push0 = 0x5F,
 
     // ============ Push (0x60 - 0x7F) ============
     Push1 = 0x60,
@@ -170,50 +173,70 @@ pub enum Opcode {
     SelfDestruct = 0xFF,
 }
 
+/// Broad groups of opcodes, for filtering and grouped statistics
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OpcodeCategory {
+    Arithmetic,
+    Comparison,
+    Bitwise,
+    Memory,
+    Storage,
+    /// STOP, jumps, JUMPDEST and PC
+    Flow,
+    Environment,
+    Block,
+    /// POP, PUSH, DUP and SWAP
+    Stack,
+    Log,
+    /// Calls, creates and the ways out of a frame other than STOP
+    System,
+}
+
 impl Opcode {
     /// Check if this is a PUSH opcode
     #[inline]
     pub fn is_push(&self) -> bool {
         let b = *self as u8;
-        b >= 0x60 && b <= 0x7F
+        (0x60..=0x7F).contains(&b)
     }
 
     /// Check if this is a DUP opcode
     #[inline]
     pub fn is_dup(&self) -> bool {
         let b = *self as u8;
-        b >= 0x80 && b <= 0x8F
+        (0x80..=0x8F).contains(&b)
     }
 
     /// Check if this is a SWAP opcode
     #[inline]
     pub fn is_swap(&self) -> bool {
         let b = *self as u8;
-        b >= 0x90 && b <= 0x9F
+        (0x90..=0x9F).contains(&b)
     }
 
     /// Check if this is a LOG opcode
     #[inline]
     pub fn is_log(&self) -> bool {
         let b = *self as u8;
-        b >= 0xA0 && b <= 0xA4
+        (0xA0..=0xA4).contains(&b)
     }
 
     /// Parse opcode from byte
     pub fn from_u8(byte: u8) -> Option<Self> {
         // All valid opcodes can be transmuted safely from their byte representation
         match byte {
-            0x00..=0x0B => Some(unsafe { std::mem::transmute(byte) }),
-            0x10..=0x1D => Some(unsafe { std::mem::transmute(byte) }),
+            0x00..=0x0B => Some(unsafe { std::mem::transmute::<u8, Self>(byte) }),
+            0x10..=0x1D => Some(unsafe { std::mem::transmute::<u8, Self>(byte) }),
             0x20 => Some(Self::Keccak256),
-            0x30..=0x3F => Some(unsafe { std::mem::transmute(byte) }),
-            0x40..=0x48 => Some(unsafe { std::mem::transmute(byte) }),
-            0x50..=0x5B => Some(unsafe { std::mem::transmute(byte) }),
-            0x60..=0x7F => Some(unsafe { std::mem::transmute(byte) }),
-            0x80..=0x8F => Some(unsafe { std::mem::transmute(byte) }),
-            0x90..=0x9F => Some(unsafe { std::mem::transmute(byte) }),
-            0xA0..=0xA4 => Some(unsafe { std::mem::transmute(byte) }),
-            0xF0..=0xF5 => Some(unsafe { std::mem::transmute(byte) }),
+            0x30..=0x3F => Some(unsafe { std::mem::transmute::<u8, Self>(byte) }),
+            0x40..=0x48 => Some(unsafe { std::mem::transmute::<u8, Self>(byte) }),
+            0x50..=0x5B => Some(unsafe { std::mem::transmute::<u8, Self>(byte) }),
+            0x5F => Some(Self::Push0),
+            0x60..=0x7F => Some(unsafe { std::mem::transmute::<u8, Self>(byte) }),
+            0x80..=0x8F => Some(unsafe { std::mem::transmute::<u8, Self>(byte) }),
+            0x90..=0x9F => Some(unsafe { std::mem::transmute::<u8, Self>(byte) }),
+            0xA0..=0xA4 => Some(unsafe { std::mem::transmute::<u8, Self>(byte) }),
+            0xF0..=0xF5 => Some(unsafe { std::mem::transmute::<u8, Self>(byte) }),
             0xFA => Some(Self::StaticCall),
             0xFD => Some(Self::Revert),
             0xFE => Some(Self::Invalid),
@@ -244,7 +267,7 @@ impl Opcode {
             | Self::ReturnDataSize | Self::Coinbase | Self::Timestamp
             | Self::Number | Self::Difficulty | Self::GasLimit 
             | Self::ChainId | Self::SelfBalance | Self::BaseFee
-            | Self::Pc | Self::MSize | Self::Gas => 0,
+            | Self::Pc | Self::MSize | Self::Gas | Self::Push0 => 0,
             Self::IsZero | Self::Not | Self::Pop | Self::MLoad | Self::SLoad
             | Self::Jump | Self::Balance | Self::ExtCodeSize | Self::ExtCodeHash
             | Self::BlockHash | Self::CallDataLoad => 1,
@@ -254,13 +277,13 @@ impl Opcode {
             | Self::And | Self::Or | Self::Xor | Self::Byte
             | Self::Shl | Self::Shr | Self::Sar
             | Self::MStore | Self::MStore8 | Self::SStore | Self::JumpI
-            | Self::Return | Self::Revert => 2,
+            | Self::Return | Self::Revert | Self::Keccak256 | Self::Log0 => 2,
             Self::AddMod | Self::MulMod | Self::CallDataCopy | Self::CodeCopy
-            | Self::ReturnDataCopy | Self::Keccak256 | Self::Log0 => 3,
-            Self::ExtCodeCopy | Self::Log1 | Self::Create => 4,
-            Self::Log2 | Self::Create2 => 5,
-            Self::Log3 | Self::Call | Self::CallCode | Self::DelegateCall => 6,
-            Self::Log4 | Self::StaticCall => 7,
+            | Self::ReturnDataCopy | Self::Log1 | Self::Create => 3,
+            Self::ExtCodeCopy | Self::Log2 | Self::Create2 => 4,
+            Self::Log3 => 5,
+            Self::Log4 | Self::DelegateCall | Self::StaticCall => 6,
+            Self::Call | Self::CallCode => 7,
             Self::SelfDestruct => 1,
             _ => 0, // PUSH/DUP/SWAP handled above
         }
@@ -288,12 +311,27 @@ impl Opcode {
         }
     }
 
-    /// Base gas cost
+    /// Net change in stack height (outputs − inputs)
+    pub fn stack_delta(&self) -> i32 {
+        self.stack_outputs() as i32 - self.stack_inputs() as i32
+    }
+
+    /// Whether execution cannot fall through to the next instruction
+    pub fn is_terminator(&self) -> bool {
+        matches!(
+            self,
+            Self::Stop | Self::Jump | Self::Return | Self::Revert
+            | Self::Invalid | Self::SelfDestruct
+        )
+    }
+
+    /// Gas cost under the default schedule
     pub fn base_gas(&self) -> u64 {
-        if self.is_push() || self.is_dup() || self.is_swap() {
-            return 3;
-        }
+        GasSchedule::default().cost(*self)
+    }
 
+    /// Gas cost of opcodes whose price `GasSchedule` doesn't configure
+    pub(crate) fn fixed_gas(&self) -> u64 {
         match self {
             Self::Stop | Self::Invalid | Self::Return | Self::Revert => 0,
             Self::JumpDest => 1,
@@ -312,7 +350,8 @@ impl Opcode {
             | Self::CallDataSize | Self::CodeSize | Self::GasPrice
             | Self::Coinbase | Self::Timestamp | Self::Number
             | Self::Difficulty | Self::GasLimit | Self::ChainId
-            | Self::SelfBalance | Self::BaseFee | Self::ReturnDataSize => 2,
+            | Self::BaseFee | Self::ReturnDataSize => 2,
+            Self::SelfBalance => 5,
             Self::CallDataLoad | Self::MLoad | Self::MStore | Self::MStore8 => 3,
             Self::SLoad => 100,
             Self::SStore => 100,
@@ -335,6 +374,23 @@ impl Opcode {
         }
     }
 
+    /// Group this opcode belongs to; KECCAK256 counts as arithmetic
+    pub fn category(&self) -> OpcodeCategory {
+        match *self as u8 {
+            0x00 | 0x56..=0x58 | 0x5B => OpcodeCategory::Flow,
+            0x01..=0x0B | 0x20 => OpcodeCategory::Arithmetic,
+            0x10..=0x15 => OpcodeCategory::Comparison,
+            0x16..=0x1D => OpcodeCategory::Bitwise,
+            0x30..=0x3F | 0x5A => OpcodeCategory::Environment,
+            0x40..=0x48 => OpcodeCategory::Block,
+            0x51..=0x53 | 0x59 => OpcodeCategory::Memory,
+            0x54 | 0x55 => OpcodeCategory::Storage,
+            0x50 | 0x5F..=0x9F => OpcodeCategory::Stack,
+            0xA0..=0xA4 => OpcodeCategory::Log,
+            _ => OpcodeCategory::System,
+        }
+    }
+
     /// Size of immediate data following opcode
     pub fn immediate_size(&self) -> usize {
         if self.is_push() {
@@ -344,3 +400,32 @@ impl Opcode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stack_delta() {
+        assert_eq!(Opcode::Add.stack_delta(), -1);
+        assert_eq!(Opcode::Push1.stack_delta(), 1);
+        assert_eq!(Opcode::Dup3.stack_delta(), 1);
+        assert_eq!(Opcode::Swap2.stack_delta(), 0);
+        assert_eq!(Opcode::SStore.stack_delta(), -2);
+        assert_eq!(Opcode::Call.stack_delta(), -6);
+        assert_eq!(Opcode::StaticCall.stack_delta(), -5);
+        assert_eq!(Opcode::Log2.stack_delta(), -4);
+    }
+
+    #[test]
+    fn test_category() {
+        assert_eq!(Opcode::Add.category(), OpcodeCategory::Arithmetic);
+        assert_eq!(Opcode::SStore.category(), OpcodeCategory::Storage);
+        assert_eq!(Opcode::Jump.category(), OpcodeCategory::Flow);
+        assert_eq!(Opcode::IsZero.category(), OpcodeCategory::Comparison);
+        assert_eq!(Opcode::Push0.category(), OpcodeCategory::Stack);
+        assert_eq!(Opcode::Swap16.category(), OpcodeCategory::Stack);
+        assert_eq!(Opcode::MSize.category(), OpcodeCategory::Memory);
+        assert_eq!(Opcode::Revert.category(), OpcodeCategory::System);
+    }
+}
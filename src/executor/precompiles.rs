@@ -0,0 +1,372 @@
+//! Built-in contracts reachable by CALL at fixed low addresses
+
+use crate::core::{Address, U256};
+use crate::core::hash::{keccak256, sha256};
+
+/// Precompiled contracts, numbered by their address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Precompile {
+    EcRecover = 0x01,
+    Sha256 = 0x02,
+    Identity = 0x04,
+}
+
+impl Precompile {
+    /// Look up the precompile deployed at `address`
+    pub fn from_address(address: &Address) -> Option<Self> {
+        if address.0[..19].iter().any(|&b| b != 0) {
+            return None;
+        }
+        match address.0[19] {
+            0x01 => Some(Self::EcRecover),
+            0x02 => Some(Self::Sha256),
+            0x04 => Some(Self::Identity),
+            _ => None,
+        }
+    }
+
+    /// Address the precompile is deployed at
+    pub fn address(&self) -> Address {
+        Address::from_slice(&[*self as u8])
+    }
+
+    /// Gas charged for an input of `input_len` bytes
+    pub fn gas_cost(&self, input_len: usize) -> u64 {
+        let words = input_len.div_ceil(32) as u64;
+        match self {
+            Self::EcRecover => 3000,
+            Self::Sha256 => 60 + 12 * words,
+            Self::Identity => 15 + 3 * words,
+        }
+    }
+
+    /// Compute the output for `input`.
+    ///
+    /// ECRECOVER returns empty output for a signature it can't recover.
+    pub fn execute(&self, input: &[u8]) -> Vec<u8> {
+        match self {
+            Self::EcRecover => ecrecover(input)
+                .map(|address| {
+                    let mut word = [0u8; 32];
+                    word[12..].copy_from_slice(&address.0);
+                    word.to_vec()
+                })
+                .unwrap_or_default(),
+            Self::Sha256 => sha256(input).to_vec(),
+            Self::Identity => input.to_vec(),
+        }
+    }
+}
+
+// ============ secp256k1 ============
+
+/// Field prime p = 2^256 - 2^32 - 977
+const P: U256 = U256([0xFFFFFFFEFFFFFC2F, u64::MAX, u64::MAX, u64::MAX]);
+
+/// 2^256 mod p
+const P_FOLD: u64 = 0x1000003D1;
+
+/// Group order n
+const N: U256 = U256([0xBFD25E8CD0364141, 0xBAAEDCE6AF48A03B, 0xFFFFFFFFFFFFFFFE, u64::MAX]);
+
+/// Generator point
+const G: Point = Point {
+    x: U256([0x59F2815B16F81798, 0x029BFCDB2DCE28D9, 0x55A06295CE870B07, 0x79BE667EF9DCBBAC]),
+    y: U256([0x9C47D08FFB10D4B8, 0xFD17B448A6855419, 0x5DA4FBFC0E1108A8, 0x483ADA7726A3C465]),
+    z: U256::ONE,
+};
+
+/// Recover the signer address from a 128-byte `hash || v || r || s` input
+fn ecrecover(input: &[u8]) -> Option<Address> {
+    let mut padded = [0u8; 128];
+    let len = input.len().min(128);
+    padded[..len].copy_from_slice(&input[..len]);
+    let word = |i: usize| U256::from_be_bytes(padded[i * 32..(i + 1) * 32].try_into().unwrap());
+    let (hash, v, r, s) = (word(0), word(1), word(2), word(3));
+
+    let valid_scalar = |k: U256| !k.is_zero() && k < N;
+    if !valid_scalar(r) || !valid_scalar(s) {
+        return None;
+    }
+    let odd_y = match v {
+        v if v == U256::from(27u64) => false,
+        v if v == U256::from(28u64) => true,
+        _ => return None,
+    };
+
+    // Lift r to the curve point R with the requested y parity
+    let rhs = fp_add(fp_mul(fp_mul(r, r), r), U256::from(7u64));
+    let mut y = fp_pow(rhs, P.wrapping_add(U256::ONE).shr1().shr1());
+    if fp_mul(y, y) != rhs {
+        return None;
+    }
+    if (y.0[0] & 1 == 1) != odd_y {
+        y = P.wrapping_sub(y);
+    }
+    let big_r = Point { x: r, y, z: U256::ONE };
+
+    // Q = r^-1 * (s * R - z * G)
+    let z = if hash >= N { hash.wrapping_sub(N) } else { hash };
+    let r_inv = fn_pow(r, N.wrapping_sub(U256::from(2u64)));
+    let u1 = fn_mul(sub_mod(U256::ZERO, z, N), r_inv);
+    let u2 = fn_mul(s, r_inv);
+    let (qx, qy) = Point::multi_mul(u1, &G, u2, &big_r).to_affine()?;
+
+    let mut pubkey = [0u8; 64];
+    pubkey[..32].copy_from_slice(&qx.to_be_bytes());
+    pubkey[32..].copy_from_slice(&qy.to_be_bytes());
+    Some(Address::from_slice(&keccak256(&pubkey)[12..]))
+}
+
+/// Curve point in Jacobian coordinates; `z == 0` is the point at infinity
+#[derive(Clone, Copy, Debug)]
+struct Point {
+    x: U256,
+    y: U256,
+    z: U256,
+}
+
+impl Point {
+    const INFINITY: Self = Self { x: U256::ZERO, y: U256::ONE, z: U256::ZERO };
+
+    fn is_infinity(&self) -> bool {
+        self.z.is_zero()
+    }
+
+    fn double(&self) -> Self {
+        if self.is_infinity() || self.y.is_zero() {
+            return Self::INFINITY;
+        }
+        let a = fp_mul(self.x, self.x);
+        let b = fp_mul(self.y, self.y);
+        let c = fp_mul(b, b);
+        let xb = fp_add(self.x, b);
+        let d = fp_sub(fp_sub(fp_mul(xb, xb), a), c);
+        let d = fp_add(d, d);
+        let e = fp_add(fp_add(a, a), a);
+        let f = fp_mul(e, e);
+        let x = fp_sub(f, fp_add(d, d));
+        let c8 = fp_add(c, c);
+        let c8 = fp_add(c8, c8);
+        let c8 = fp_add(c8, c8);
+        let y = fp_sub(fp_mul(e, fp_sub(d, x)), c8);
+        let yz = fp_mul(self.y, self.z);
+        Self { x, y, z: fp_add(yz, yz) }
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        if self.is_infinity() {
+            return *other;
+        }
+        if other.is_infinity() {
+            return *self;
+        }
+        let z1z1 = fp_mul(self.z, self.z);
+        let z2z2 = fp_mul(other.z, other.z);
+        let u1 = fp_mul(self.x, z2z2);
+        let u2 = fp_mul(other.x, z1z1);
+        let s1 = fp_mul(fp_mul(self.y, other.z), z2z2);
+        let s2 = fp_mul(fp_mul(other.y, self.z), z1z1);
+        if u1 == u2 {
+            return if s1 == s2 { self.double() } else { Self::INFINITY };
+        }
+        let h = fp_sub(u2, u1);
+        let r = fp_sub(s2, s1);
+        let h2 = fp_mul(h, h);
+        let h3 = fp_mul(h, h2);
+        let u1h2 = fp_mul(u1, h2);
+        let x = fp_sub(fp_sub(fp_mul(r, r), h3), fp_add(u1h2, u1h2));
+        let y = fp_sub(fp_mul(r, fp_sub(u1h2, x)), fp_mul(s1, h3));
+        let z = fp_mul(fp_mul(h, self.z), other.z);
+        Self { x, y, z }
+    }
+
+    /// Compute `k1 * p1 + k2 * p2` with a shared doubling chain
+    fn multi_mul(k1: U256, p1: &Self, k2: U256, p2: &Self) -> Self {
+        let both = p1.add(p2);
+        let mut acc = Self::INFINITY;
+        for bit in (0..256).rev() {
+            acc = acc.double();
+            acc = match (k1.bit(bit), k2.bit(bit)) {
+                (true, true) => acc.add(&both),
+                (true, false) => acc.add(p1),
+                (false, true) => acc.add(p2),
+                (false, false) => acc,
+            };
+        }
+        acc
+    }
+
+    fn to_affine(self) -> Option<(U256, U256)> {
+        if self.is_infinity() {
+            return None;
+        }
+        let z_inv = fp_pow(self.z, P.wrapping_sub(U256::from(2u64)));
+        let z_inv2 = fp_mul(z_inv, z_inv);
+        Some((fp_mul(self.x, z_inv2), fp_mul(self.y, fp_mul(z_inv2, z_inv))))
+    }
+}
+
+trait Bits {
+    fn bit(&self, index: usize) -> bool;
+    fn shr1(self) -> Self;
+}
+
+impl Bits for U256 {
+    fn bit(&self, index: usize) -> bool {
+        (self.0[index / 64] >> (index % 64)) & 1 == 1
+    }
+
+    fn shr1(self) -> Self {
+        let mut limbs = self.0;
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            *limb >>= 1;
+            if i < 3 {
+                *limb |= self.0[i + 1] << 63;
+            }
+        }
+        Self(limbs)
+    }
+}
+
+/// `a + b`, reporting whether the sum wrapped past 2^256
+fn overflowing_add(a: U256, b: U256) -> (U256, bool) {
+    let sum = a.wrapping_add(b);
+    (sum, sum < a)
+}
+
+/// `(a + b) mod m` for `a, b < m`
+fn add_mod(a: U256, b: U256, m: U256) -> U256 {
+    let (sum, carry) = overflowing_add(a, b);
+    if carry || sum >= m { sum.wrapping_sub(m) } else { sum }
+}
+
+/// `(a - b) mod m` for `a, b < m`
+fn sub_mod(a: U256, b: U256, m: U256) -> U256 {
+    let diff = a.wrapping_sub(b);
+    if b > a { diff.wrapping_add(m) } else { diff }
+}
+
+fn fp_add(a: U256, b: U256) -> U256 {
+    add_mod(a, b, P)
+}
+
+fn fp_sub(a: U256, b: U256) -> U256 {
+    sub_mod(a, b, P)
+}
+
+/// Multiply mod p, folding the high half with 2^256 ≡ 2^32 + 977
+fn fp_mul(a: U256, b: U256) -> U256 {
+    let mut wide = [0u64; 8];
+    for i in 0..4 {
+        let mut carry = 0u128;
+        for j in 0..4 {
+            let t = wide[i + j] as u128 + (a.0[i] as u128) * (b.0[j] as u128) + carry;
+            wide[i + j] = t as u64;
+            carry = t >> 64;
+        }
+        wide[i + 4] = carry as u64;
+    }
+
+    let mut folded = [0u64; 5];
+    let mut carry = 0u128;
+    for i in 0..4 {
+        let t = wide[i] as u128 + (wide[i + 4] as u128) * (P_FOLD as u128) + carry;
+        folded[i] = t as u64;
+        carry = t >> 64;
+    }
+    folded[4] = carry as u64;
+
+    let mut limbs = [0u64; 4];
+    let mut carry = (folded[4] as u128) * (P_FOLD as u128);
+    for i in 0..4 {
+        let t = folded[i] as u128 + carry;
+        limbs[i] = t as u64;
+        carry = t >> 64;
+    }
+    let mut result = U256(limbs);
+    if carry != 0 {
+        result = result.wrapping_add(U256::from(P_FOLD));
+    }
+    if result >= P { result.wrapping_sub(P) } else { result }
+}
+
+fn fp_pow(base: U256, exp: U256) -> U256 {
+    pow_with(base, exp, fp_mul)
+}
+
+/// Multiply mod n by shift-and-add; only a handful are needed per recovery
+fn fn_mul(a: U256, b: U256) -> U256 {
+    let mut result = U256::ZERO;
+    for bit in (0..256).rev() {
+        result = add_mod(result, result, N);
+        if b.bit(bit) {
+            result = add_mod(result, a, N);
+        }
+    }
+    result
+}
+
+fn fn_pow(base: U256, exp: U256) -> U256 {
+    pow_with(base, exp, fn_mul)
+}
+
+fn pow_with(base: U256, exp: U256, mul: fn(U256, U256) -> U256) -> U256 {
+    let mut result = U256::ONE;
+    for bit in (0..256).rev() {
+        result = mul(result, result);
+        if exp.bit(bit) {
+            result = mul(result, base);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(hex: &str) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_precompile_addresses() {
+        assert_eq!(Precompile::from_address(&Address::from_slice(&[0x02])), Some(Precompile::Sha256));
+        assert_eq!(Precompile::from_address(&Address::from_slice(&[0x03])), None);
+        assert_eq!(Precompile::from_address(&Address::from_slice(&[0x01, 0x04])), None);
+        assert_eq!(Precompile::Identity.address(), Address::from_slice(&[0x04]));
+    }
+
+    #[test]
+    fn test_generator_is_on_curve() {
+        let lhs = fp_mul(G.y, G.y);
+        let rhs = fp_add(fp_mul(fp_mul(G.x, G.x), G.x), U256::from(7u64));
+        assert_eq!(lhs, rhs);
+    }
+
+    #[test]
+    fn test_ecrecover_known_signature() {
+        let mut input = Vec::new();
+        input.extend_from_slice(&word("456e9aea5e197a1f1af7a3e85a3212fa4049a3ba34c2289b4c860fc0b0c64ef3"));
+        input.extend_from_slice(&U256::from(28u64).to_be_bytes());
+        input.extend_from_slice(&word("9242685bf161793cc25603c231bc2f568eb630ea16aa137d2664ac8038825608"));
+        input.extend_from_slice(&word("4f8ae3bd7535248d0bd448298cc2e2071e56992d0774dc340c368ae950852ada"));
+
+        let output = Precompile::EcRecover.execute(&input);
+        assert_eq!(&output[..12], &[0u8; 12]);
+        assert_eq!(
+            &output[12..],
+            &word("0000000000000000000000007156526fbd7a3c72969b54f64e42c10fbb768c8a")[12..]
+        );
+
+        // A bad recovery id yields empty output rather than failing the call
+        input[63] = 29;
+        assert!(Precompile::EcRecover.execute(&input).is_empty());
+    }
+}
@@ -1,9 +1,9 @@
 //! Reverse execution - applying inverse operations
 
 use crate::core::{VmError, VmResult};
-use crate::vm::Vm;
+use crate::vm::{HashMode, Vm};
 use crate::journal::JournalEntry;
-use crate::executor::StepResult;
+use crate::executor::{Opcode, StepResult};
 
 /// Apply the inverse of a journal entry to restore previous state.
 pub fn apply_inverse(vm: &mut Vm, entry: JournalEntry) -> VmResult<()> {
@@ -17,8 +17,8 @@ pub fn apply_inverse(vm: &mut Vm, entry: JournalEntry) -> VmResult<()> {
         JournalEntry::MemoryWrite { offset, old_data, .. } => {
             vm.state.memory.restore_bytes(offset, &old_data);
         }
-        JournalEntry::StorageWrite { key, old_value, .. } => {
-            vm.state.storage.insert(key, old_value);
+        JournalEntry::StorageWrite { address, key, old_value, .. } => {
+            vm.storage_mut(address).insert(key, old_value);
         }
         JournalEntry::PcChange { old_pc, .. } => {
             vm.state.pc = old_pc;
@@ -26,18 +26,39 @@ pub fn apply_inverse(vm: &mut Vm, entry: JournalEntry) -> VmResult<()> {
         JournalEntry::GasChange { old_gas, .. } => {
             vm.state.gas = old_gas;
         }
-        JournalEntry::CallEnter { caller_frame: _ } => {
-            vm.call_stack.pop();
+        JournalEntry::CallEnter { .. } => {
+            if let Some(mut frame) = vm.call_stack.pop() {
+                vm.swap_call_context(&mut frame);
+            }
             vm.state.call_depth = vm.state.call_depth.saturating_sub(1);
         }
-        JournalEntry::CallExit { callee_frame: _, return_data: _ } => {
+        JournalEntry::CallExit { mut callee_frame, .. } => {
+            vm.swap_call_context(&mut callee_frame);
+            vm.call_stack.push(*callee_frame);
             vm.state.call_depth += 1;
         }
         JournalEntry::ReturnDataSet { old_data, .. } => {
             vm.state.return_data = old_data;
         }
-        JournalEntry::MemoryExpansion { old_size: _, .. } => {
-            // Memory pages remain allocated - this is a known limitation
+        JournalEntry::MemoryExpansion { old_size, .. } => {
+            vm.state.memory.truncate(old_size);
+        }
+        JournalEntry::LogEmitted { index } => {
+            vm.state.logs.truncate(index);
+        }
+        JournalEntry::LogsDiscarded { logs } => {
+            vm.state.logs.extend(logs);
+        }
+        JournalEntry::TransactionCommitted { originals } => {
+            for (address, original) in originals {
+                vm.storage_mut(address).restore_original(original);
+            }
+        }
+        JournalEntry::SlotWarmed { address, key } => {
+            vm.state.accessed_slots.remove(&(address, key));
+        }
+        JournalEntry::AddressWarmed { address } => {
+            vm.state.accessed_addresses.remove(&address);
         }
     }
     Ok(())
@@ -48,13 +69,24 @@ impl Vm {
     pub fn step_backward(&mut self) -> VmResult<StepResult> {
         let insn = self.journal.pop()
             .ok_or(VmError::JournalExhausted)?;
+        self.invalidate_hash();
+        let opcode = Opcode::from_u8(insn.opcode).unwrap_or(Opcode::Invalid);
+        let pc = insn.pc;
 
         // Apply inverse operations in reverse order
         for entry in insn.entries.into_iter().rev() {
             apply_inverse(self, entry)?;
         }
 
-        Ok(StepResult::Rewound { steps: 1 })
+        if self.verify_on_rewind && self.hash_mode == HashMode::EveryStep
+            && let Some(previous) = self.journal.peek()
+            && previous.state_hash != [0u8; 32]
+            && previous.state_hash != self.compute_state_hash()
+        {
+            return Err(VmError::ReversibilityViolation { at: self.journal.end_index() });
+        }
+
+        Ok(StepResult::Rewound { steps: 1, opcode, pc })
     }
 
     /// Rewind N steps backward
@@ -70,38 +102,152 @@ impl Vm {
         Ok(rewound)
     }
 
-    /// Rewind to a specific instruction index
-    pub fn rewind_to(&mut self, target_index: usize) -> VmResult<()> {
-        let current = self.journal.len();
-        
-        if target_index >= current {
-            return Ok(());
+    /// Rewind to a specific instruction index, jumping to the nearest
+    /// checkpoint at or after it first when there is one. Indices count
+    /// from the start of execution, so one the journal no longer reaches
+    /// fails with `JournalExhausted`.
+    ///
+    /// Returns the number of instructions undone by stepping backward.
+    pub fn rewind_to(&mut self, target_index: usize) -> VmResult<usize> {
+        let Some(target_index) = target_index.checked_sub(self.journal.first_index()) else {
+            return Err(VmError::JournalExhausted);
+        };
+        if target_index >= self.journal.len() {
+            return Ok(0);
         }
 
-        // For now, simple step-by-step rewind
-        // A more efficient implementation would use checkpoints
-        let steps = current - target_index;
+        // Snapshots hold no call frames, so one can only stand in for the
+        // live state while no call is active on either side
+        if self.call_stack.is_empty()
+            && let Some(checkpoint) = self.journal.find_checkpoint_at_or_after(target_index)
+            && checkpoint.state_snapshot.call_depth == 0
+        {
+            let index = checkpoint.instruction_index;
+            let snapshot = checkpoint.state_snapshot.clone();
+            // Snapshots don't hold original storage values, so undo any
+            // commit among the skipped instructions by hand
+            let commits: Vec<JournalEntry> = self.journal.iter().skip(index).rev()
+                .flat_map(|insn| insn.entries.iter().rev())
+                .filter(|entry| matches!(entry, JournalEntry::TransactionCommitted { .. }))
+                .cloned()
+                .collect();
+            self.restore_from_snapshot(&snapshot);
+            for entry in commits {
+                apply_inverse(self, entry)?;
+            }
+            self.journal.truncate(index);
+        }
+
+        let steps = self.journal.len() - target_index;
         self.rewind(steps)?;
-        
-        Ok(())
+
+        // Nothing past the target may survive, so the next forward step
+        // branches a fresh future
+        self.journal.truncate(target_index);
+        Ok(steps)
     }
 
     /// Restore VM state from a snapshot
     pub fn restore_from_snapshot(&mut self, snapshot: &crate::journal::StateSnapshot) {
+        self.invalidate_hash();
         self.state.stack.restore_from(&snapshot.stack);
-        self.state.memory.restore_from(&snapshot.memory);
+        match &snapshot.sparse_memory {
+            Some(sparse) => self.state.memory.restore_from_sparse(sparse),
+            None => self.state.memory.restore_from(&snapshot.memory),
+        }
         self.state.storage.restore_from(snapshot.storage.clone());
+        for (address, account) in self.state.accounts.iter_mut() {
+            let slots = snapshot.account_storage.get(address).cloned().unwrap_or_default();
+            account.storage.restore_from(slots);
+        }
         self.state.pc = snapshot.pc;
         self.state.gas = snapshot.gas;
         self.state.call_depth = snapshot.call_depth;
         self.state.return_data = snapshot.return_data.clone();
+        self.state.logs = snapshot.logs.clone();
+        self.state.accessed_addresses = snapshot.accessed_addresses.clone();
+        self.state.accessed_slots = snapshot.accessed_slots.clone();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::core::BlockContext;
+    use crate::core::{BlockContext, U256};
+    use crate::vm::Memory;
+
+    /// Caller stores 0x11 at memory 0 and CALLs itself with one byte of
+    /// input; the callee writes storage slot 0 and its own memory, then ends
+    /// with `callee_exit` (RETURN or REVERT of empty data).
+    fn subcall_bytecode(callee_exit: u8) -> Vec<u8> {
+        vec![
+            0x36, 0x60, 0x18, 0x57,
+            0x60, 0x11, 0x60, 0x00, 0x52,
+            0x60, 0x00, 0x60, 0x00, 0x60, 0x01, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00,
+            0x5A, 0xF1, 0x00,
+            0x5B, 0x60, 0x07, 0x60, 0x00, 0x55,
+            0x60, 0x99, 0x60, 0x00, 0x52,
+            0x60, 0x00, 0x60, 0x00, callee_exit,
+        ]
+    }
+
+    #[test]
+    fn test_log_reverses() {
+        // PUSH1 0xAA, PUSH1 32, PUSH1 0x40, LOG1, STOP
+        let bytecode = vec![0x60, 0xAA, 0x60, 0x20, 0x60, 0x40, 0xA1, 0x00];
+        let mut vm = Vm::new(bytecode, 100_000, BlockContext::default());
+        let result = vm.run().unwrap();
+
+        assert_eq!(vm.state().logs.len(), 1);
+        assert_eq!(vm.state().logs[0].topics, vec![U256::from(0xAAu64)]);
+        assert_eq!(vm.state().logs[0].data, vec![0u8; 32]);
+        assert_eq!(vm.state().memory.size(), 0x60);
+        assert_eq!(result.gas_used(), 3 * 3 + 750 + 8 * 32 + Memory::expansion_cost(0, 0x60));
+
+        vm.rewind(2).unwrap();
+        assert!(vm.state().logs.is_empty());
+        assert_eq!(vm.state().memory.size(), 0, "the expansion for the log data is undone");
+        assert_eq!(vm.state().stack.len(), 3);
+    }
+
+    #[test]
+    fn test_rewind_across_subcall() {
+        for (callee_exit, slot_after) in [(0xF3, 7u64), (0xFD, 0)] {
+            let mut vm = Vm::new(subcall_bytecode(callee_exit), 1_000_000, BlockContext::default());
+            // Run up to the CALL
+            while vm.state().pc != 22 {
+                vm.step_forward().unwrap();
+            }
+            let pre_call_index = vm.journal().len();
+            let pre_call_hash = vm.state_hash();
+            let pre_call_memory = vm.state().memory.snapshot();
+            let pre_call_stack = vm.state().stack.to_vec();
+
+            vm.run().unwrap();
+            assert_eq!(vm.state().storage.get(&U256::ZERO), U256::from(slot_after));
+            assert_eq!(vm.state().memory.snapshot(), pre_call_memory, "callee memory is its own");
+
+            vm.rewind_to(pre_call_index).unwrap();
+            assert_eq!(vm.state().call_depth, 0);
+            assert_eq!(vm.state().storage.get(&U256::ZERO), U256::ZERO);
+            assert_eq!(vm.state().memory.snapshot(), pre_call_memory);
+            assert_eq!(vm.state().stack.to_vec(), pre_call_stack);
+            assert_eq!(vm.state_hash(), pre_call_hash);
+            assert!(vm.state().return_data.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_reverted_subcall_rewinds_into_callee_writes() {
+        let mut vm = Vm::new(subcall_bytecode(0xFD), 1_000_000, BlockContext::default());
+        vm.run().unwrap();
+        assert_eq!(vm.state().stack.to_vec(), vec![U256::ZERO]);
+
+        // Back over STOP and the REVERT: the callee's write is live again
+        vm.rewind(2).unwrap();
+        assert_eq!(vm.state().call_depth, 1);
+        assert_eq!(vm.state().storage.get(&U256::ZERO), U256::from(7u64));
+    }
 
     #[test]
     fn test_forward_backward_equivalence() {
@@ -214,4 +360,86 @@ mod tests {
         // Storage should be back to 0
         assert_eq!(vm.state.storage.get(&key).as_u64(), 0);
     }
+
+    #[test]
+    fn test_jumpi_not_taken_rewind() {
+        // PUSH1 0 (cond), PUSH1 7 (dest), JUMPI, STOP, ..., JUMPDEST
+        let bytecode = vec![0x60, 0x00, 0x60, 0x07, 0x57, 0x00, 0x00, 0x5B];
+        let mut vm = Vm::new(bytecode, 100_000, BlockContext::default());
+
+        vm.step_forward().unwrap();
+        vm.step_forward().unwrap();
+        vm.step_forward().unwrap(); // JUMPI not taken
+        assert_eq!(vm.state.pc, 5, "not-taken JUMPI falls through by one byte");
+        assert!(vm.state.stack.is_empty());
+
+        vm.step_backward().unwrap();
+        assert_eq!(vm.state.pc, 4);
+        assert_eq!(vm.state.stack.len(), 2);
+        assert_eq!(vm.state.stack.peek(0).unwrap().as_u64(), 7);
+        assert_eq!(vm.state.stack.peek(1).unwrap().as_u64(), 0);
+    }
+
+    #[test]
+    fn test_jumpi_invalid_destination_leaves_state_intact() {
+        // PUSH1 1 (cond), PUSH1 6 (dest, not a JUMPDEST), JUMPI, STOP
+        let bytecode = vec![0x60, 0x01, 0x60, 0x06, 0x57, 0x00, 0x00];
+        let mut vm = Vm::new(bytecode, 100_000, BlockContext::default());
+
+        vm.step_forward().unwrap();
+        vm.step_forward().unwrap();
+        let gas = vm.state.gas;
+        assert_eq!(vm.step_forward().unwrap_err(), VmError::InvalidJump { destination: 6 });
+
+        assert_eq!(vm.journal.len(), 2, "failed instruction must not be journaled");
+        assert_eq!(vm.state.pc, 4);
+        assert_eq!(vm.state.gas, gas);
+        assert_eq!(vm.state.stack.len(), 2);
+
+        vm.rewind(2).unwrap();
+        assert!(vm.state.stack.is_empty());
+        assert_eq!(vm.state.pc, 0);
+    }
+
+    #[test]
+    fn test_verify_on_rewind() {
+        for callee_exit in [0xF3, 0xFD] {
+            let mut vm = Vm::new(subcall_bytecode(callee_exit), 1_000_000, BlockContext::default());
+            vm.set_verify_on_rewind(true);
+            vm.run().unwrap();
+            while !vm.journal().is_empty() {
+                vm.step_backward().unwrap();
+            }
+        }
+
+        // A stack push the journal never saw stands in for a broken inverse
+        let mut vm = Vm::new(vec![0x60, 0x01, 0x60, 0x02, 0x01, 0x00], 100_000, BlockContext::default());
+        vm.set_verify_on_rewind(true);
+        vm.run().unwrap();
+        vm.state_mut().stack.push(U256::ONE).unwrap();
+        let err = vm.step_backward().unwrap_err();
+        assert!(matches!(err, VmError::ReversibilityViolation { at: 3 }), "{err:?}");
+    }
+
+    #[test]
+    fn test_verify_on_rewind_catches_broken_storage_inverse() {
+        // PUSH1 0x2A, PUSH1 0x01, SSTORE, STOP
+        let mut vm = Vm::new(vec![0x60, 0x2A, 0x60, 0x01, 0x55, 0x00], 100_000, BlockContext::default());
+        vm.set_verify_on_rewind(true);
+        vm.run().unwrap();
+        vm.step_backward().unwrap();
+
+        // Undoing the SSTORE now restores the wrong value; everything else
+        // the hash used to cover still lines up
+        let mut sstore = vm.journal.pop().unwrap();
+        for entry in &mut sstore.entries {
+            if let JournalEntry::StorageWrite { old_value, .. } = entry {
+                *old_value = U256::from(7u64);
+            }
+        }
+        vm.journal.record(sstore);
+
+        let err = vm.step_backward().unwrap_err();
+        assert!(matches!(err, VmError::ReversibilityViolation { at: 2 }), "{err:?}");
+    }
 }
@@ -0,0 +1,183 @@
+//! Import of EIP-3155 structured execution traces
+
+use crate::core::U256;
+
+/// One step of an external trace: the state just before `op` ran
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceStep {
+    pub pc: usize,
+    pub op: u8,
+    /// Gas left before the instruction
+    pub gas: u64,
+    /// Stack, bottom first
+    pub stack: Vec<U256>,
+}
+
+/// Parse an EIP-3155 trace, one JSON object per line. Lines without a
+/// `pc`, such as the closing summary, and lines that don't parse are
+/// skipped. Numbers may be given as JSON numbers or `0x` strings.
+pub fn load_eip3155_trace(json: &str) -> Vec<TraceStep> {
+    json.lines().filter_map(parse_step).collect()
+}
+
+fn parse_step(line: &str) -> Option<TraceStep> {
+    let fields = Parser { bytes: line.trim().as_bytes(), pos: 0 }.object()?;
+    let field = |name: &str| fields.iter().find(|(key, _)| key == name).map(|(_, value)| value);
+
+    let stack = match field("stack") {
+        Some(Value::Array(items)) => items.iter().map(Value::as_u256).collect::<Option<_>>()?,
+        Some(_) => return None,
+        None => Vec::new(),
+    };
+    Some(TraceStep {
+        pc: usize::try_from(field("pc")?.as_u64()?).ok()?,
+        op: u8::try_from(field("op")?.as_u64()?).ok()?,
+        gas: field("gas")?.as_u64()?,
+        stack,
+    })
+}
+
+/// The subset of JSON a trace line uses; numbers keep their source text
+enum Value {
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Other,
+}
+
+impl Value {
+    fn as_u64(&self) -> Option<u64> {
+        match self {
+            Self::Number(text) => text.parse().ok(),
+            Self::String(text) => match text.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                None => text.parse().ok(),
+            },
+            _ => None,
+        }
+    }
+
+    fn as_u256(&self) -> Option<U256> {
+        match self {
+            Self::String(text) => U256::from_hex(text.strip_prefix("0x")?),
+            _ => self.as_u64().map(U256::from),
+        }
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn object(&mut self) -> Option<Vec<(String, Value)>> {
+        self.expect(b'{')?;
+        let mut fields = Vec::new();
+        if self.eat(b'}') {
+            return Some(fields);
+        }
+        loop {
+            let key = self.string()?;
+            self.expect(b':')?;
+            fields.push((key, self.value()?));
+            if self.eat(b'}') {
+                return Some(fields);
+            }
+            self.expect(b',')?;
+        }
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        self.skip_whitespace();
+        match *self.bytes.get(self.pos)? {
+            b'"' => self.string().map(Value::String),
+            b'[' => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                if self.eat(b']') {
+                    return Some(Value::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    if self.eat(b']') {
+                        return Some(Value::Array(items));
+                    }
+                    self.expect(b',')?;
+                }
+            }
+            b'{' => self.object().map(|_| Value::Other),
+            _ => {
+                let start = self.pos;
+                while self.bytes.get(self.pos).is_some_and(|b| !matches!(b, b',' | b'}' | b']') && !b.is_ascii_whitespace()) {
+                    self.pos += 1;
+                }
+                let text = std::str::from_utf8(&self.bytes[start..self.pos]).ok()?;
+                match text {
+                    "" => None,
+                    "true" | "false" | "null" => Some(Value::Other),
+                    _ => Some(Value::Number(text.to_string())),
+                }
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.expect(b'"')?;
+        let mut out = Vec::new();
+        loop {
+            match *self.bytes.get(self.pos)? {
+                b'"' => {
+                    self.pos += 1;
+                    return String::from_utf8(out).ok();
+                }
+                b'\\' => {
+                    // Escapes never occur in the fields we read; keep the
+                    // escaped byte so the string still ends in the right place
+                    out.push(*self.bytes.get(self.pos + 1)?);
+                    self.pos += 2;
+                }
+                byte => {
+                    out.push(byte);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.bytes.get(self.pos) == Some(&byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        self.eat(byte).then_some(())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.bytes.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_trace_lines() {
+        let json = r#"{"pc":0,"op":96,"gas":"0x186a0","gasCost":"0x3","memSize":0,"stack":[],"depth":1,"refund":0,"opName":"PUSH1"}
+{"pc":2,"op":96,"gas":"0x1869d","gasCost":"0x3","memSize":0,"stack":["0xa"],"depth":1,"refund":0,"opName":"PUSH1","error":null}
+not json
+{"output":"","gasUsed":"0x12","pass":true}"#;
+        let steps = load_eip3155_trace(json);
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0], TraceStep { pc: 0, op: 0x60, gas: 100_000, stack: vec![] });
+        assert_eq!(steps[1], TraceStep { pc: 2, op: 0x60, gas: 99_997, stack: vec![U256::from(10u64)] });
+    }
+}
@@ -1,7 +1,8 @@
 //! Checkpoint structures for fast rewind to distant states
 
-use crate::core::U256;
-use std::collections::HashMap;
+use crate::core::{Address, U256};
+use crate::vm::{LogRecord, SparseMemory};
+use std::collections::{HashMap, HashSet};
 
 /// A full state snapshot at a point in execution.
 #[derive(Clone, Debug)]
@@ -17,10 +18,15 @@ pub struct Checkpoint {
 pub struct StateSnapshot {
     /// Stack contents
     pub stack: Vec<U256>,
-    /// Memory contents (compressed)
+    /// Memory contents (compressed); empty when `sparse_memory` is set
     pub memory: Vec<u8>,
-    /// Storage state
+    /// Memory as a sparse page map, taken instead of `memory` when the VM
+    /// records sparse checkpoints
+    pub sparse_memory: Option<SparseMemory>,
+    /// Storage state of the root contract
     pub storage: HashMap<U256, U256>,
+    /// Storage state of other accounts
+    pub account_storage: HashMap<Address, HashMap<U256, U256>>,
     /// Program counter
     pub pc: usize,
     /// Remaining gas
@@ -29,6 +35,12 @@ pub struct StateSnapshot {
     pub call_depth: usize,
     /// Return data
     pub return_data: Vec<u8>,
+    /// Logs emitted so far
+    pub logs: Vec<LogRecord>,
+    /// Accounts warmed so far
+    pub accessed_addresses: HashSet<Address>,
+    /// Storage slots warmed so far
+    pub accessed_slots: HashSet<(Address, U256)>,
 }
 
 impl StateSnapshot {
@@ -37,11 +49,16 @@ impl StateSnapshot {
         Self {
             stack: Vec::new(),
             memory: Vec::new(),
+            sparse_memory: None,
             storage: HashMap::new(),
+            account_storage: HashMap::new(),
             pc: 0,
             gas: 0,
             call_depth: 0,
             return_data: Vec::new(),
+            logs: Vec::new(),
+            accessed_addresses: HashSet::new(),
+            accessed_slots: HashSet::new(),
         }
     }
 
@@ -50,8 +67,25 @@ impl StateSnapshot {
         std::mem::size_of::<Self>()
             + self.stack.len() * std::mem::size_of::<U256>()
             + self.memory.len()
+            + self.sparse_memory.as_ref().map_or(0, SparseMemory::memory_usage)
             + self.storage.len() * (std::mem::size_of::<U256>() * 2)
+            + self
+                .account_storage
+                .values()
+                .map(|slots| std::mem::size_of::<Address>() + slots.len() * std::mem::size_of::<U256>() * 2)
+                .sum::<usize>()
             + self.return_data.len()
+            + self
+                .logs
+                .iter()
+                .map(|log| {
+                    std::mem::size_of::<LogRecord>()
+                        + log.topics.len() * std::mem::size_of::<U256>()
+                        + log.data.len()
+                })
+                .sum::<usize>()
+            + self.accessed_addresses.len() * std::mem::size_of::<Address>()
+            + self.accessed_slots.len() * std::mem::size_of::<(Address, U256)>()
     }
 }
 
@@ -1,7 +1,10 @@
 //! Journal entry types for instruction-level reversibility
 
-use crate::core::U256;
-use crate::vm::CallFrameSnapshot;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::core::{Address, U256};
+use crate::vm::{CallFrame, CallFrameSnapshot, LogRecord};
 
 /// A single state mutation that can be reversed.
 #[derive(Clone, Debug)]
@@ -21,6 +24,7 @@ pub enum JournalEntry {
     
     /// Storage write (reverse: restore old_value)
     StorageWrite {
+        address: Address,
         key: U256,
         old_value: U256,
         new_value: U256,
@@ -38,14 +42,14 @@ pub enum JournalEntry {
         new_gas: u64,
     },
     
-    /// Entering a call (reverse: pop frame)
+    /// Entering a call (reverse: pop frame, restoring the caller's context)
     CallEnter {
-        caller_frame: CallFrameSnapshot,
+        callee_frame: CallFrameSnapshot,
     },
     
-    /// Exiting a call (reverse: push frame, clear return data)
+    /// Exiting a call (reverse: push frame, restoring the callee's context)
     CallExit {
-        callee_frame: CallFrameSnapshot,
+        callee_frame: Box<CallFrame>,
         return_data: Vec<u8>,
     },
     
@@ -60,6 +64,23 @@ pub enum JournalEntry {
         old_size: usize,
         new_size: usize,
     },
+
+    /// Log appended at `index` of the log buffer (reverse: pop it)
+    LogEmitted { index: usize },
+
+    /// Logs of a failed call dropped from the end of the buffer
+    /// (reverse: append them again)
+    LogsDiscarded { logs: Vec<LogRecord> },
+
+    /// Original storage values replaced by `commit_transaction`
+    /// (reverse: restore them)
+    TransactionCommitted { originals: Vec<(Address, HashMap<U256, U256>)> },
+
+    /// Storage slot warmed by its first access (reverse: make it cold)
+    SlotWarmed { address: Address, key: U256 },
+
+    /// Account warmed by its first access (reverse: make it cold)
+    AddressWarmed { address: Address },
 }
 
 impl JournalEntry {
@@ -69,17 +90,177 @@ impl JournalEntry {
             Self::MemoryWrite { old_data, new_data, .. } => {
                 old_data.len() + new_data.len()
             }
-            Self::CallEnter { .. } | Self::CallExit { .. } => {
-                std::mem::size_of::<CallFrameSnapshot>()
+            Self::CallEnter { .. } => std::mem::size_of::<CallFrameSnapshot>(),
+            Self::CallExit { callee_frame, return_data } => {
+                std::mem::size_of::<CallFrame>()
+                    + callee_frame.code.len()
+                    + callee_frame.calldata.len()
+                    + callee_frame.saved.stack.len() * std::mem::size_of::<U256>()
+                    + callee_frame.saved.memory.size()
+                    + callee_frame.saved.code.len() * 2
+                    + return_data.len()
             }
             Self::ReturnDataSet { old_data, new_data } => {
                 old_data.len() + new_data.len()
             }
+            Self::LogsDiscarded { logs } => logs
+                .iter()
+                .map(|log| {
+                    std::mem::size_of::<LogRecord>()
+                        + log.topics.len() * std::mem::size_of::<U256>()
+                        + log.data.len()
+                })
+                .sum(),
+            Self::TransactionCommitted { originals } => originals
+                .iter()
+                .map(|(_, slots)| slots.len() * std::mem::size_of::<U256>() * 2)
+                .sum(),
             _ => 0,
         }
     }
 }
 
+impl fmt::Display for JournalEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StackPush { value } => write!(f, "push {value:#x}"),
+            Self::StackPop { value } => write!(f, "pop {value:#x}"),
+            Self::MemoryWrite { offset, new_data, .. } => {
+                write!(f, "mem[{:#x}..{:#x}]", offset, offset + new_data.len())
+            }
+            Self::StorageWrite { key, old_value, new_value, .. } => {
+                write!(f, "sstore[{key:#x}] {old_value:#x}\u{2192}{new_value:#x}")
+            }
+            Self::PcChange { old_pc, new_pc } => write!(f, "pc {old_pc:#06x}\u{2192}{new_pc:#06x}"),
+            Self::GasChange { old_gas, new_gas } => write!(f, "gas {old_gas}\u{2192}{new_gas}"),
+            Self::CallEnter { callee_frame } => write!(f, "enter {:#x}", callee_frame.address),
+            Self::CallExit { return_data, .. } => write!(f, "exit ({} bytes)", return_data.len()),
+            Self::ReturnDataSet { old_data, new_data } => {
+                write!(f, "returndata {}\u{2192}{} bytes", old_data.len(), new_data.len())
+            }
+            Self::MemoryExpansion { old_size, new_size } => write!(f, "msize {old_size}\u{2192}{new_size}"),
+            Self::LogEmitted { index } => write!(f, "log #{index}"),
+            Self::LogsDiscarded { logs } => write!(f, "discard {} logs", logs.len()),
+            Self::TransactionCommitted { .. } => write!(f, "commit"),
+            Self::SlotWarmed { key, .. } => write!(f, "warm slot {key:#x}"),
+            Self::AddressWarmed { address } => write!(f, "warm {address:#x}"),
+        }
+    }
+}
+
+impl JournalEntry {
+    /// Append a canonical byte encoding of this entry, for digests
+    pub(super) fn encode_into(&self, out: &mut Vec<u8>) {
+        fn bytes(out: &mut Vec<u8>, data: &[u8]) {
+            out.extend_from_slice(&(data.len() as u64).to_be_bytes());
+            out.extend_from_slice(data);
+        }
+        fn word(out: &mut Vec<u8>, value: &U256) {
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+        fn int(out: &mut Vec<u8>, value: u64) {
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+
+        match self {
+            Self::StackPush { value } => {
+                out.push(0);
+                word(out, value);
+            }
+            Self::StackPop { value } => {
+                out.push(1);
+                word(out, value);
+            }
+            Self::MemoryWrite { offset, old_data, new_data } => {
+                out.push(2);
+                int(out, *offset as u64);
+                bytes(out, old_data);
+                bytes(out, new_data);
+            }
+            Self::StorageWrite { address, key, old_value, new_value } => {
+                out.push(3);
+                out.extend_from_slice(&address.0);
+                word(out, key);
+                word(out, old_value);
+                word(out, new_value);
+            }
+            Self::PcChange { old_pc, new_pc } => {
+                out.push(4);
+                int(out, *old_pc as u64);
+                int(out, *new_pc as u64);
+            }
+            Self::GasChange { old_gas, new_gas } => {
+                out.push(5);
+                int(out, *old_gas);
+                int(out, *new_gas);
+            }
+            Self::CallEnter { callee_frame } => {
+                out.push(6);
+                out.extend_from_slice(&callee_frame.address.0);
+                out.extend_from_slice(&callee_frame.caller.0);
+                word(out, &callee_frame.value);
+                int(out, callee_frame.gas);
+                out.push(callee_frame.is_static as u8);
+            }
+            Self::CallExit { callee_frame, return_data } => {
+                out.push(7);
+                out.extend_from_slice(&callee_frame.address.0);
+                int(out, callee_frame.saved.gas);
+                bytes(out, return_data);
+            }
+            Self::ReturnDataSet { old_data, new_data } => {
+                out.push(8);
+                bytes(out, old_data);
+                bytes(out, new_data);
+            }
+            Self::MemoryExpansion { old_size, new_size } => {
+                out.push(9);
+                int(out, *old_size as u64);
+                int(out, *new_size as u64);
+            }
+            Self::LogEmitted { index } => {
+                out.push(10);
+                int(out, *index as u64);
+            }
+            Self::LogsDiscarded { logs } => {
+                out.push(11);
+                int(out, logs.len() as u64);
+                for log in logs {
+                    out.extend_from_slice(&log.address.0);
+                    int(out, log.topics.len() as u64);
+                    for topic in &log.topics {
+                        word(out, topic);
+                    }
+                    bytes(out, &log.data);
+                }
+            }
+            Self::TransactionCommitted { originals } => {
+                out.push(12);
+                int(out, originals.len() as u64);
+                for (address, slots) in originals {
+                    out.extend_from_slice(&address.0);
+                    let mut slots: Vec<_> = slots.iter().collect();
+                    slots.sort();
+                    int(out, slots.len() as u64);
+                    for (key, value) in slots {
+                        word(out, key);
+                        word(out, value);
+                    }
+                }
+            }
+            Self::SlotWarmed { address, key } => {
+                out.push(13);
+                out.extend_from_slice(&address.0);
+                word(out, key);
+            }
+            Self::AddressWarmed { address } => {
+                out.push(14);
+                out.extend_from_slice(&address.0);
+            }
+        }
+    }
+}
+
 /// Complete journal for a single instruction execution.
 #[derive(Clone, Debug)]
 pub struct InstructionJournal {
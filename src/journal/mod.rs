@@ -3,9 +3,29 @@
 mod entry;
 mod checkpoint;
 
+use std::collections::{HashMap, HashSet};
+
+use crate::core::hash::keccak256;
+use crate::core::{Address, U256};
+
 pub use entry::{JournalEntry, InstructionJournal};
 pub use checkpoint::{Checkpoint, StateSnapshot};
 
+/// How the journal bounds the history it keeps
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HistoryMode {
+    /// Past `max_size`, drop the oldest tenth and renumber what's left
+    #[default]
+    Truncate,
+    /// Keep roughly the last `capacity` instructions, always starting at a
+    /// checkpoint. Positions stay absolute, so rewinding anywhere in the
+    /// window is exact and rewinding before it fails with `JournalExhausted`.
+    ///
+    /// The window only moves up to a checkpoint taken outside any call, so
+    /// it can outgrow `capacity` while a long call is running.
+    Ring { capacity: usize },
+}
+
 /// Journal managing instruction-level state deltas and checkpoints.
 /// 
 /// The journal enables O(1) single-step rewind and O(√N) arbitrary rewind
@@ -16,20 +36,29 @@ pub struct Journal {
     instructions: Vec<InstructionJournal>,
     /// Periodic full-state checkpoints
     checkpoints: Vec<Checkpoint>,
-    /// Interval between checkpoints
+    /// Interval between checkpoints (0 disables checkpointing)
     checkpoint_interval: usize,
     /// Maximum journal size before truncation
     max_size: usize,
+    /// How old instructions are dropped
+    history_mode: HistoryMode,
+    /// Instructions dropped from the front in ring mode
+    dropped: usize,
 }
 
 impl Journal {
-    /// Create a new journal
+    /// Create a new journal.
+    ///
+    /// A `checkpoint_interval` of 0 disables checkpointing, so far rewinds
+    /// fall back to stepping backward one instruction at a time.
     pub fn new(checkpoint_interval: usize, max_size: usize) -> Self {
         Self {
             instructions: Vec::new(),
             checkpoints: Vec::new(),
             checkpoint_interval,
             max_size,
+            history_mode: HistoryMode::Truncate,
+            dropped: 0,
         }
     }
 
@@ -37,26 +66,58 @@ impl Journal {
     pub fn record(&mut self, insn: InstructionJournal) {
         self.instructions.push(insn);
         
-        // Create checkpoint at interval
-        if self.instructions.len() % self.checkpoint_interval == 0 {
-            // Checkpoint creation is deferred to executor
-        }
+        // Checkpoint creation at interval is deferred to the executor
         
-        // Truncate old entries if over limit
-        if self.instructions.len() > self.max_size {
-            let trim = self.max_size / 10;
-            self.instructions.drain(0..trim);
-            // Adjust checkpoint indices
-            self.checkpoints.retain(|c| c.instruction_index >= trim);
-            for c in &mut self.checkpoints {
-                c.instruction_index -= trim;
+        let len = self.instructions.len();
+        match self.history_mode {
+            HistoryMode::Truncate if len > self.max_size => {
+                // At least one, or a max_size under 10 would never trim
+                self.drop_front((self.max_size / 10).max(1));
+            }
+            HistoryMode::Ring { capacity } if len > capacity => {
+                let start = self.checkpoints.iter().find(|c| {
+                    c.instruction_index > 0
+                        && len - c.instruction_index <= capacity
+                        && c.state_snapshot.call_depth == 0
+                });
+                if let Some(start) = start.map(|c| c.instruction_index) {
+                    self.drop_front(start);
+                    self.dropped += start;
+                }
             }
+            _ => {}
         }
     }
 
-    /// Pop the most recent instruction journal (for rewind)
+    /// Drop the oldest `count` instructions and the checkpoints among them
+    fn drop_front(&mut self, count: usize) {
+        self.instructions.drain(0..count);
+        self.checkpoints.retain(|c| c.instruction_index >= count);
+        for c in &mut self.checkpoints {
+            c.instruction_index -= count;
+        }
+    }
+
+    /// Pop the most recent instruction journal (for rewind), dropping any
+    /// checkpoint taken after it
     pub fn pop(&mut self) -> Option<InstructionJournal> {
-        self.instructions.pop()
+        let insn = self.instructions.pop()?;
+        self.drop_checkpoints_after(self.instructions.len());
+        Some(insn)
+    }
+
+    /// Discard every instruction from index `len` on, along with the
+    /// checkpoints taken after them, so the next recorded instruction
+    /// continues from position `len`
+    pub fn truncate(&mut self, len: usize) {
+        self.instructions.truncate(len);
+        self.drop_checkpoints_after(self.instructions.len());
+    }
+
+    fn drop_checkpoints_after(&mut self, len: usize) {
+        while self.checkpoints.last().is_some_and(|c| c.instruction_index > len) {
+            self.checkpoints.pop();
+        }
     }
 
     /// Peek at the most recent instruction journal
@@ -64,11 +125,21 @@ impl Journal {
         self.instructions.last()
     }
 
+    /// The most recent instruction journal, for effects recorded after it ran
+    pub(crate) fn peek_mut(&mut self) -> Option<&mut InstructionJournal> {
+        self.instructions.last_mut()
+    }
+
     /// Get instruction at index
     pub fn get(&self, index: usize) -> Option<&InstructionJournal> {
         self.instructions.get(index)
     }
 
+    /// Recorded instructions, oldest first
+    pub fn iter(&self) -> std::slice::Iter<'_, InstructionJournal> {
+        self.instructions.iter()
+    }
+
     /// Number of recorded instructions
     pub fn len(&self) -> usize {
         self.instructions.len()
@@ -79,10 +150,42 @@ impl Journal {
         self.instructions.is_empty()
     }
 
+    /// Keccak-256 over every retained instruction's pc, opcode, gas and
+    /// journal entries, oldest first. Equal digests mean two sessions
+    /// executed the same way; state hashes and checkpoints aren't included,
+    /// so the hash mode doesn't matter.
+    pub fn session_digest(&self) -> [u8; 32] {
+        let mut data = Vec::new();
+        for insn in &self.instructions {
+            data.extend_from_slice(&(insn.pc as u64).to_be_bytes());
+            data.push(insn.opcode);
+            data.extend_from_slice(&insn.gas_before.to_be_bytes());
+            data.extend_from_slice(&insn.gas_after.to_be_bytes());
+            data.extend_from_slice(&(insn.entries.len() as u64).to_be_bytes());
+            for entry in &insn.entries {
+                entry.encode_into(&mut data);
+            }
+        }
+        keccak256(&data)
+    }
+
+    /// Absolute position of the oldest retained instruction; non-zero only
+    /// once ring mode has dropped history
+    pub fn first_index(&self) -> usize {
+        self.dropped
+    }
+
+    /// Absolute position after the newest instruction: the number of
+    /// instructions recorded, dropped ones included
+    pub fn end_index(&self) -> usize {
+        self.dropped + self.instructions.len()
+    }
+
     /// Clear the journal
     pub fn clear(&mut self) {
         self.instructions.clear();
         self.checkpoints.clear();
+        self.dropped = 0;
     }
 
     /// Add a checkpoint
@@ -98,18 +201,372 @@ impl Journal {
             .find(|c| c.instruction_index < index)
     }
 
+    /// Find the earliest checkpoint at or after instruction index
+    pub fn find_checkpoint_at_or_after(&self, index: usize) -> Option<&Checkpoint> {
+        self.checkpoints.iter().find(|c| c.instruction_index >= index)
+    }
+
     /// Get all checkpoints
     pub fn checkpoints(&self) -> &[Checkpoint] {
         &self.checkpoints
     }
 
-    /// Check if checkpoint should be created
+    /// Check if checkpoint should be created. Ring mode also checkpoints
+    /// every half capacity, giving the window places to start.
     pub fn should_checkpoint(&self) -> bool {
-        self.instructions.len() % self.checkpoint_interval == 0
+        let position = self.end_index();
+        let periodic = self.checkpointing_enabled() && position.is_multiple_of(self.checkpoint_interval);
+        let ring_start = match self.history_mode {
+            HistoryMode::Ring { capacity } => position.is_multiple_of((capacity / 2).max(1)),
+            HistoryMode::Truncate => false,
+        };
+        periodic || ring_start
     }
 
     /// Get checkpoint interval
     pub fn checkpoint_interval(&self) -> usize {
         self.checkpoint_interval
     }
+
+    /// Get maximum journal size before truncation
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// How old instructions are dropped
+    pub fn history_mode(&self) -> HistoryMode {
+        self.history_mode
+    }
+
+    /// Change how old instructions are dropped, from the next one recorded
+    pub fn set_history_mode(&mut self, mode: HistoryMode) {
+        self.history_mode = mode;
+    }
+
+    /// Set checkpoint interval (0 disables checkpointing)
+    pub fn set_checkpoint_interval(&mut self, interval: usize) {
+        self.checkpoint_interval = interval;
+    }
+
+    /// Whether periodic checkpoints are taken
+    pub fn checkpointing_enabled(&self) -> bool {
+        self.checkpoint_interval != 0
+    }
+
+    /// Collapse redundant writes among instructions `from..to`.
+    ///
+    /// Only the first write to each storage slot or memory region in the
+    /// range is kept, carrying the range's final value. Instruction indices
+    /// are unchanged and rewinding to `from` or `to` stays exact, but
+    /// positions strictly inside the range may no longer restore the
+    /// intermediate values of compacted slots. The recorded hashes of those
+    /// positions are cleared so verification on rewind skips them.
+    ///
+    /// Indices are into the retained instructions; `Vm::compact_journal`
+    /// takes execution positions instead.
+    ///
+    /// Returns the number of entries removed.
+    pub fn compact_range(&mut self, from: usize, to: usize) -> usize {
+        let to = to.min(self.instructions.len());
+        if from >= to {
+            return 0;
+        }
+
+        let mut final_storage: HashMap<(Address, U256), U256> = HashMap::new();
+        let mut final_memory: HashMap<(usize, usize), Vec<u8>> = HashMap::new();
+        for insn in &self.instructions[from..to] {
+            for entry in &insn.entries {
+                match entry {
+                    JournalEntry::StorageWrite { address, key, new_value, .. } => {
+                        final_storage.insert((*address, *key), *new_value);
+                    }
+                    JournalEntry::MemoryWrite { offset, new_data, .. } => {
+                        final_memory.insert((*offset, new_data.len()), new_data.clone());
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut seen_storage = HashSet::new();
+        let mut seen_memory = HashSet::new();
+        let mut removed = 0;
+        for insn in &mut self.instructions[from..to] {
+            let before = insn.entries.len();
+            insn.entries.retain_mut(|entry| match entry {
+                JournalEntry::StorageWrite { address, key, new_value, .. } => {
+                    let slot = (*address, *key);
+                    if !seen_storage.insert(slot) {
+                        return false;
+                    }
+                    *new_value = final_storage[&slot];
+                    true
+                }
+                JournalEntry::MemoryWrite { offset, new_data, .. } => {
+                    let region = (*offset, new_data.len());
+                    if !seen_memory.insert(region) {
+                        return false;
+                    }
+                    new_data.clone_from(&final_memory[&region]);
+                    true
+                }
+                _ => true,
+            });
+            removed += before - insn.entries.len();
+        }
+        for insn in &mut self.instructions[from..to - 1] {
+            insn.state_hash = [0u8; 32];
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{BlockContext, VmError};
+    use crate::vm::Vm;
+
+    /// Counts down from 10, writing the counter to slot 1 on each iteration.
+    fn slot_loop_vm() -> Vm {
+        let bytecode = vec![
+            0x60, 0x0A,       // PUSH1 10
+            0x5B,             // JUMPDEST
+            0x80,             // DUP1
+            0x60, 0x01,       // PUSH1 1
+            0x55,             // SSTORE
+            0x60, 0x01,       // PUSH1 1
+            0x90,             // SWAP1
+            0x03,             // SUB
+            0x80,             // DUP1
+            0x60, 0x02,       // PUSH1 2
+            0x57,             // JUMPI
+            0x00,             // STOP
+        ];
+        Vm::new(bytecode, 1_000_000, BlockContext::default())
+    }
+
+    /// Counts down from 400, roughly 2400 instructions in total.
+    fn long_loop_vm() -> Vm {
+        let bytecode = vec![
+            0x61, 0x01, 0x90, // PUSH2 400
+            0x5B,             // JUMPDEST
+            0x60, 0x01,       // PUSH1 1
+            0x90,             // SWAP1
+            0x03,             // SUB
+            0x80,             // DUP1
+            0x60, 0x03,       // PUSH1 3
+            0x57,             // JUMPI
+            0x00,             // STOP
+        ];
+        Vm::new(bytecode, 1_000_000, BlockContext::default())
+    }
+
+    fn storage_writes(journal: &Journal, from: usize, to: usize) -> usize {
+        (from..to)
+            .flat_map(|i| &journal.get(i).unwrap().entries)
+            .filter(|e| matches!(e, JournalEntry::StorageWrite { .. }))
+            .count()
+    }
+
+    #[test]
+    fn test_compact_range_preserves_endpoints() {
+        let slot = U256::from(1u64);
+        let mut vm = slot_loop_vm();
+
+        // Stop just after the first SSTORE so the range starts mid-loop
+        for _ in 0..5 {
+            vm.step_forward().unwrap();
+        }
+        let from = vm.journal().len();
+        let from_hash = vm.compute_state_hash();
+        let from_value = vm.state().storage.get(&slot);
+        assert_eq!(from_value.as_u64(), 10);
+
+        vm.run().unwrap();
+        let to = vm.journal().len();
+        let to_hash = vm.compute_state_hash();
+        assert_eq!(storage_writes(vm.journal(), from, to), 9);
+
+        let removed = vm.compact_journal(from, to).unwrap();
+        assert_eq!(removed, 8);
+        assert_eq!(storage_writes(vm.journal(), from, to), 1);
+        assert_eq!(vm.journal().len(), to, "instruction indices are unchanged");
+
+        vm.rewind_to(to).unwrap();
+        assert_eq!(vm.compute_state_hash(), to_hash);
+        assert_eq!(vm.state().storage.get(&slot).as_u64(), 1);
+
+        vm.rewind_to(from).unwrap();
+        assert_eq!(vm.compute_state_hash(), from_hash);
+        assert_eq!(vm.state().storage.get(&slot), from_value);
+    }
+
+    #[test]
+    fn test_compact_whole_run() {
+        let slot = U256::from(1u64);
+        let mut vm = slot_loop_vm();
+        let initial = vm.compute_state_hash();
+        vm.run().unwrap();
+
+        let len = vm.journal().len();
+        vm.compact_journal(0, len).unwrap();
+        assert_eq!(storage_writes(vm.journal(), 0, len), 1);
+
+        vm.rewind_to(0).unwrap();
+        assert_eq!(vm.compute_state_hash(), initial);
+        assert_eq!(vm.state().storage.get(&slot), U256::ZERO);
+    }
+
+    #[test]
+    fn test_compact_journal_verifies_on_rewind_after_history_drops() {
+        let slot = U256::from(1u64);
+        let mut reference = slot_loop_vm();
+        let mut vm = slot_loop_vm();
+        vm.set_checkpoint_interval(8);
+        vm.set_history_mode(HistoryMode::Ring { capacity: 40 });
+        vm.set_verify_on_rewind(true);
+        reference.run().unwrap();
+        vm.run().unwrap();
+
+        let first = vm.journal().first_index();
+        let end = vm.journal().end_index();
+        assert!(first > 0);
+        assert!(matches!(vm.compact_journal(first - 1, end), Err(VmError::JournalExhausted)));
+
+        let from = first + 5;
+        assert!(vm.compact_journal(from, end).unwrap() > 0);
+
+        // Step back through every compacted instruction rather than
+        // jumping to a checkpoint
+        vm.rewind(end - from).unwrap();
+        reference.rewind_to(from).unwrap();
+        assert_eq!(vm.compute_state_hash(), reference.compute_state_hash());
+        assert_eq!(vm.state().storage.get(&slot), reference.state().storage.get(&slot));
+
+        vm.rewind_to(first).unwrap();
+        reference.rewind_to(first).unwrap();
+        assert_eq!(vm.compute_state_hash(), reference.compute_state_hash());
+    }
+
+    #[test]
+    fn test_checkpointing_disabled() {
+        let mut vm = long_loop_vm();
+        vm.set_checkpoint_interval(0);
+        let initial = vm.compute_state_hash();
+
+        vm.run().unwrap();
+        assert!(vm.journal().len() > 2000);
+        assert!(vm.journal().checkpoints().is_empty());
+
+        vm.rewind_to(0).unwrap();
+        assert!(vm.journal().is_empty());
+        assert_eq!(vm.compute_state_hash(), initial);
+    }
+
+    #[test]
+    fn test_zero_interval_journal_records_without_panicking() {
+        let mut journal = Journal::new(0, 5);
+        for pc in 0..1000 {
+            journal.record(InstructionJournal::new(pc, 0x5B, 1_000));
+            assert!(!journal.should_checkpoint());
+        }
+        assert!(journal.len() <= 5);
+        assert_eq!(journal.peek().unwrap().pc, 999);
+    }
+
+    #[test]
+    fn test_manual_checkpoint_shortens_rewind() {
+        let mut vm = long_loop_vm();
+        vm.set_checkpoint_interval(0);
+        for _ in 0..500 {
+            vm.step_forward().unwrap();
+        }
+        let mut with_checkpoint = vm.clone();
+        with_checkpoint.add_manual_checkpoint();
+        with_checkpoint.add_manual_checkpoint();
+        assert_eq!(with_checkpoint.journal().checkpoints().len(), 1);
+
+        for _ in 0..500 {
+            vm.step_forward().unwrap();
+            with_checkpoint.step_forward().unwrap();
+        }
+        assert_eq!(vm.rewind_to(490).unwrap(), 510);
+        assert_eq!(with_checkpoint.rewind_to(490).unwrap(), 10);
+        assert_eq!(with_checkpoint.compute_state_hash(), vm.compute_state_hash());
+        assert_eq!(with_checkpoint.journal().len(), 490);
+        assert!(with_checkpoint.journal().checkpoints().is_empty());
+    }
+
+    #[test]
+    fn test_checkpointing_enabled_by_default() {
+        let mut vm = long_loop_vm();
+        vm.run().unwrap();
+        assert_eq!(vm.journal().checkpoints().len(), vm.journal().len() / 1000);
+    }
+
+    #[test]
+    fn test_branching_after_rewind_drops_old_future() {
+        let mut vm = slot_loop_vm();
+        vm.set_checkpoint_interval(4);
+        vm.run().unwrap();
+        let full_len = vm.journal().len();
+
+        // Back to the top of the second iteration, with the counter at 9
+        vm.rewind_to(11).unwrap();
+        assert_eq!(vm.journal().len(), 11);
+        assert!(vm.journal().checkpoints().iter().all(|c| c.instruction_index <= 11));
+
+        // Branch: a smaller counter ends the loop sooner
+        vm.state_mut().stack.pop().unwrap();
+        vm.state_mut().stack.push(U256::from(2u64)).unwrap();
+        vm.run().unwrap();
+        assert!(vm.journal().len() < full_len);
+
+        let indices: Vec<usize> = vm.journal().checkpoints().iter().map(|c| c.instruction_index).collect();
+        let expected: Vec<usize> = (1..=vm.journal().len() / 4).map(|i| i * 4).collect();
+        assert_eq!(indices, expected, "no stale or duplicate checkpoints");
+    }
+
+    #[test]
+    fn test_ring_history_rewinds_exactly_within_window() {
+        let mut reference = long_loop_vm();
+        let mut ring = long_loop_vm();
+        ring.set_history_mode(HistoryMode::Ring { capacity: 100 });
+        reference.run().unwrap();
+        ring.run().unwrap();
+
+        let end = ring.journal().end_index();
+        let first = ring.journal().first_index();
+        assert_eq!(end, reference.journal().len());
+        assert!(first > 0 && ring.journal().len() <= 100);
+        assert_eq!(ring.journal().checkpoints()[0].instruction_index, 0, "the window starts at a checkpoint");
+
+        for target in [end - 1, end - 30, first] {
+            reference.rewind_to(target).unwrap();
+            ring.rewind_to(target).unwrap();
+            assert_eq!(ring.journal().end_index(), target);
+            assert_eq!(ring.compute_state_hash(), reference.compute_state_hash(), "at {target}");
+        }
+
+        assert!(matches!(ring.rewind_to(first - 1), Err(VmError::JournalExhausted)));
+        assert_eq!(ring.journal().end_index(), first, "a failed rewind leaves the state alone");
+    }
+
+    #[test]
+    fn test_session_digest() {
+        // PUSH1 10, PUSH1 20, ADD, PUSH1 0, MSTORE, STOP
+        let demo = vec![0x60, 0x0A, 0x60, 0x14, 0x01, 0x60, 0x00, 0x52, 0x00];
+        let digest = |bytecode: &[u8]| {
+            let mut vm = Vm::new(bytecode.to_vec(), 100_000, BlockContext::default());
+            vm.run().unwrap();
+            vm.journal().session_digest()
+        };
+
+        assert_eq!(digest(&demo), digest(&demo));
+        let mut modified = demo.clone();
+        modified[1] = 0x0B;
+        assert_ne!(digest(&demo), digest(&modified));
+        assert_ne!(digest(&demo), Journal::new(0, 10).session_digest());
+    }
 }
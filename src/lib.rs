@@ -16,7 +16,33 @@ pub mod journal;
 pub mod executor;
 pub mod debugger;
 pub mod bytecode;
+pub mod testing;
 
 pub use crate::core::{U256, Address, BlockContext, VmError, VmResult};
 pub use crate::debugger::TimeTravel;
 pub use crate::vm::Vm;
+
+use crate::executor::ExecutionResult;
+
+/// Assemble `src` (see `bytecode::assemble`) and run it to a halt with the
+/// default block context
+pub fn run_asm(src: &str, gas: u64) -> VmResult<ExecutionResult> {
+    let bytecode = bytecode::assemble(src)?;
+    Vm::new(bytecode, gas, BlockContext::default()).run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_asm() {
+        let src = "PUSH1 0x02\nPUSH1 0x03\nADD\nPUSH1 0x00\nMSTORE\nPUSH1 0x20\nPUSH1 0x00\nRETURN";
+        let ExecutionResult::Success { return_data, .. } = run_asm(src, 100_000).unwrap() else {
+            panic!("the program returns");
+        };
+        assert_eq!(return_data, U256::from(5u64).to_be_bytes());
+
+        assert!(matches!(run_asm("PUSH1 0x02\nBOGUS", 100_000), Err(VmError::InvalidAssembly { line: 2, .. })));
+    }
+}
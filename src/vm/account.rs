@@ -0,0 +1,27 @@
+//! Accounts other than the root contract
+
+use std::sync::Arc;
+
+use crate::core::U256;
+use crate::vm::Storage;
+
+/// Code, balance and storage of an account reachable by CALL.
+///
+/// The root contract's code and storage live on the VM itself; an entry at
+/// the root address only supplies its balance.
+#[derive(Clone, Default)]
+pub struct Account {
+    pub code: Arc<[u8]>,
+    pub balance: U256,
+    pub storage: Storage,
+}
+
+impl Account {
+    pub fn new(code: Arc<[u8]>) -> Self {
+        Self {
+            code,
+            balance: U256::ZERO,
+            storage: Storage::new(),
+        }
+    }
+}
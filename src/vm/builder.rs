@@ -0,0 +1,329 @@
+//! Builder for configuring a VM before execution
+
+use std::collections::HashMap;
+
+use crate::core::{Address, BlockContext, U256, VmError, VmResult};
+use crate::executor::GasSchedule;
+use crate::vm::{Account, DivByZero, Storage, Vm, MAX_CALL_DEPTH, MAX_STACK_SIZE};
+
+/// Configures optional VM settings that `Vm::new` leaves at their defaults.
+///
+/// Gas defaults to the block gas limit of the configured context.
+pub struct VmBuilder {
+    bytecode: Vec<u8>,
+    gas: Option<u64>,
+    context: BlockContext,
+    address: Address,
+    calldata: Vec<u8>,
+    call_value: U256,
+    max_call_depth: usize,
+    start_pc: usize,
+    initial_stack: Vec<U256>,
+    gas_schedule: GasSchedule,
+    gas_metering: bool,
+    accounts: Vec<(Address, Account)>,
+    access_costs: bool,
+    access_list: (Vec<Address>, Vec<(Address, U256)>),
+    division_by_zero: DivByZero,
+    enforce_block_gas_limit: bool,
+}
+
+impl VmBuilder {
+    pub fn new(bytecode: Vec<u8>) -> Self {
+        Self {
+            bytecode,
+            gas: None,
+            context: BlockContext::default(),
+            address: Address::ZERO,
+            calldata: Vec::new(),
+            call_value: U256::ZERO,
+            max_call_depth: MAX_CALL_DEPTH,
+            start_pc: 0,
+            initial_stack: Vec::new(),
+            gas_schedule: GasSchedule::default(),
+            gas_metering: true,
+            accounts: Vec::new(),
+            access_costs: false,
+            access_list: (Vec::new(), Vec::new()),
+            division_by_zero: DivByZero::Zero,
+            enforce_block_gas_limit: false,
+        }
+    }
+
+    /// Gas available to the root call
+    pub fn gas(mut self, gas: u64) -> Self {
+        self.gas = Some(gas);
+        self
+    }
+
+    /// Block context for environmental opcodes
+    pub fn context(mut self, context: BlockContext) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Address the root contract runs at
+    pub fn address(mut self, address: Address) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// Call data of the root call
+    pub fn calldata(mut self, calldata: Vec<u8>) -> Self {
+        self.calldata = calldata;
+        self
+    }
+
+    /// Value sent with the root call
+    pub fn call_value(mut self, value: U256) -> Self {
+        self.call_value = value;
+        self
+    }
+
+    /// Deepest call nesting allowed; CALL past it fails with
+    /// `CallDepthExceeded`. Capped at `MAX_CALL_DEPTH`.
+    pub fn max_call_depth(mut self, depth: usize) -> Self {
+        self.max_call_depth = depth.min(MAX_CALL_DEPTH);
+        self
+    }
+
+    /// Prices of the configurable opcodes
+    pub fn gas_schedule(mut self, schedule: GasSchedule) -> Self {
+        self.gas_schedule = schedule;
+        self
+    }
+
+    /// Charge gas for execution (the default). Turning it off gives a dry
+    /// run for analysis: gas stays constant and never runs out.
+    pub fn gas_metering(mut self, enabled: bool) -> Self {
+        self.gas_metering = enabled;
+        self
+    }
+
+    /// Offset execution starts at instead of 0. It needn't be a JUMPDEST,
+    /// so internal functions can be entered directly.
+    pub fn start_pc(mut self, pc: usize) -> Self {
+        self.start_pc = pc;
+        self
+    }
+
+    /// Stack to start with, bottom first, e.g. the arguments of an
+    /// internal function
+    pub fn initial_stack(mut self, stack: Vec<U256>) -> Self {
+        self.initial_stack = stack;
+        self
+    }
+
+    /// Charge EIP-2929 cold prices for the first access to each account and
+    /// storage slot
+    pub fn access_costs(mut self, enabled: bool) -> Self {
+        self.access_costs = enabled;
+        self
+    }
+
+    /// EIP-2930 access list: addresses and storage slots that are warm from
+    /// the start. Declaring one turns on `access_costs`.
+    pub fn access_list(mut self, addresses: Vec<Address>, slots: Vec<(Address, U256)>) -> Self {
+        self.access_list = (addresses, slots);
+        self.access_costs = true;
+        self
+    }
+
+    /// Make `build` fail with `GasAboveBlockLimit` if the gas exceeds the
+    /// context's block gas limit, as a real transaction's would
+    pub fn enforce_block_gas_limit(mut self, enforce: bool) -> Self {
+        self.enforce_block_gas_limit = enforce;
+        self
+    }
+
+    /// What DIV does with a zero divisor
+    pub fn division_by_zero(mut self, mode: DivByZero) -> Self {
+        self.division_by_zero = mode;
+        self
+    }
+
+    /// Preload an account in the world state. At the root address `code`
+    /// is ignored, since the root runs the builder's bytecode, but the
+    /// balance and storage still apply. A later call for the same address
+    /// replaces an earlier one.
+    pub fn account(mut self, address: Address, code: Vec<u8>, balance: U256, storage: HashMap<U256, U256>) -> Self {
+        self.accounts.retain(|(existing, _)| *existing != address);
+        let account = Account { code: code.into(), balance, storage: Storage::with_state(storage) };
+        self.accounts.push((address, account));
+        self
+    }
+
+    /// Build the VM, failing with `StackOverflow` if the initial stack
+    /// exceeds `MAX_STACK_SIZE`, or with `GasAboveBlockLimit` when enforced
+    pub fn build(self) -> VmResult<Vm> {
+        if self.initial_stack.len() > MAX_STACK_SIZE {
+            return Err(VmError::StackOverflow { max: MAX_STACK_SIZE });
+        }
+        let gas = self.gas.unwrap_or(self.context.gas_limit);
+        if self.enforce_block_gas_limit && gas > self.context.gas_limit {
+            return Err(VmError::GasAboveBlockLimit { gas, limit: self.context.gas_limit });
+        }
+        let mut vm = Vm::new(self.bytecode, gas, self.context);
+        vm.address = self.address;
+        vm.calldata = self.calldata;
+        vm.call_value = self.call_value;
+        vm.max_call_depth = self.max_call_depth;
+        vm.gas_schedule = self.gas_schedule;
+        vm.gas_metering = self.gas_metering;
+        vm.entry_pc = self.start_pc;
+        vm.entry_stack = self.initial_stack;
+        vm.access_costs = self.access_costs;
+        vm.division_by_zero = self.division_by_zero;
+        let (addresses, slots) = self.access_list;
+        vm.set_access_list(addresses, slots);
+        vm.state.pc = vm.entry_pc;
+        vm.state.stack.restore_from(&vm.entry_stack);
+        for (address, mut account) in self.accounts {
+            if address == vm.address {
+                vm.state.storage = std::mem::take(&mut account.storage);
+                account.code = Default::default();
+            }
+            vm.state.accounts.insert(address, account);
+        }
+        Ok(vm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{ExecutionResult, Opcode, StepResult, COLD_SLOAD_COST, WARM_STORAGE_READ_COST};
+
+    #[test]
+    fn test_builder_defaults_match_new() {
+        let vm = VmBuilder::new(vec![0x00]).build().unwrap();
+        assert_eq!(vm.state().gas, BlockContext::default().gas_limit);
+        assert_eq!(vm.max_call_depth(), MAX_CALL_DEPTH);
+        assert_eq!(vm.address(), Address::ZERO);
+        assert!(vm.calldata().is_empty());
+    }
+
+    #[test]
+    fn test_builder_caps_call_depth() {
+        let vm = VmBuilder::new(vec![0x00]).max_call_depth(5000).build().unwrap();
+        assert_eq!(vm.max_call_depth(), MAX_CALL_DEPTH);
+    }
+
+    #[test]
+    fn test_dry_run_outlives_gas() {
+        // Count down from 50, storing each counter value in its own slot
+        let bytecode = vec![
+            0x60, 0x32, 0x5B, 0x80, 0x80, 0x55, 0x60, 0x01, 0x90, 0x03, 0x80, 0x60, 0x02, 0x57, 0x00,
+        ];
+        let mut metered = VmBuilder::new(bytecode.clone()).gas(2_000).build().unwrap();
+        let err = metered.run().unwrap_err();
+        assert!(matches!(err, VmError::OutOfGas { .. }));
+        let reached = metered.journal().len();
+
+        let mut dry = VmBuilder::new(bytecode).gas(2_000).gas_metering(false).build().unwrap();
+        for _ in 0..reached {
+            dry.step_forward().unwrap();
+        }
+        assert_eq!(dry.state().storage.snapshot(), metered.state().storage.snapshot());
+        assert_eq!(dry.state().stack.to_vec(), metered.state().stack.to_vec());
+
+        assert!(matches!(dry.run().unwrap(), ExecutionResult::Success { .. }));
+        assert_eq!(dry.state().gas, 2_000);
+        assert_eq!(dry.state().storage.get(&U256::from(1u64)), U256::ONE);
+    }
+
+    #[test]
+    fn test_start_mid_contract() {
+        // PUSH1 1, JUMPDEST, ADD, STOP: enter at the ADD with two arguments
+        let mut vm = VmBuilder::new(vec![0x60, 0x01, 0x5B, 0x01, 0x00])
+            .start_pc(3)
+            .initial_stack(vec![U256::from(40u64), U256::from(2u64)])
+            .build()
+            .unwrap();
+        vm.step_forward().unwrap();
+        assert_eq!(vm.state().stack.as_slice(), &[U256::from(42u64)]);
+        assert_eq!(vm.state().pc, 4);
+
+        vm.reset(1_000);
+        assert_eq!(vm.state().pc, 3);
+        assert_eq!(vm.state().stack.len(), 2);
+
+        let overflow = VmBuilder::new(vec![0x00]).initial_stack(vec![U256::ZERO; MAX_STACK_SIZE + 1]).build();
+        assert!(matches!(overflow, Err(VmError::StackOverflow { .. })));
+    }
+
+    #[test]
+    fn test_call_preloaded_account() {
+        let root = Address::from_word(U256::from(0xAAu64));
+        let callee = Address::from_word(U256::from(0xBBu64));
+        // Callee: MSTORE(0, SLOAD(7) + SELFBALANCE), RETURN(0, 32)
+        let callee_code = vec![
+            0x60, 0x07, 0x54, 0x47, 0x01, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xF3,
+        ];
+        // Root: CALL callee into memory 0..32, then SSTORE(0, MLOAD(0)) and
+        // SSTORE(1, SELFBALANCE + EXTCODESIZE(callee) + BALANCE(callee))
+        let mut root_code = vec![0x60, 0x20, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0xBB, 0x61, 0xFF, 0xFF, 0xF1, 0x50];
+        root_code.extend([0x60, 0x00, 0x51, 0x60, 0x00, 0x55]);
+        root_code.extend([0x47, 0x60, 0xBB, 0x3B, 0x01, 0x60, 0xBB, 0x31, 0x01, 0x60, 0x01, 0x55, 0x00]);
+
+        let storage = HashMap::from([(U256::from(7u64), U256::from(1_000u64))]);
+        let mut vm = VmBuilder::new(root_code)
+            .address(root)
+            .gas(1_000_000)
+            .account(callee, callee_code.clone(), U256::from(5u64), storage)
+            .account(root, vec![0xFE], U256::from(9u64), HashMap::new())
+            .build()
+            .unwrap();
+        assert!(matches!(vm.run().unwrap(), ExecutionResult::Success { .. }));
+        assert_eq!(vm.storage_at(root, &U256::ZERO), U256::from(1_005u64));
+        assert_eq!(vm.storage_at(root, &U256::ONE), U256::from(callee_code.len() as u64 + 9 + 5));
+        assert_eq!(vm.balance_of(callee), U256::from(5u64));
+    }
+
+    #[test]
+    fn test_access_list_prewarms_slot() {
+        // SLOAD(1), SLOAD(2), SLOAD(2), STOP
+        let bytecode = vec![0x60, 0x01, 0x54, 0x60, 0x02, 0x54, 0x60, 0x02, 0x54, 0x00];
+        let mut vm = VmBuilder::new(bytecode)
+            .gas(100_000)
+            .access_list(vec![], vec![(Address::ZERO, U256::ONE)])
+            .build()
+            .unwrap();
+        let mut sload_costs = Vec::new();
+        while let Ok(StepResult::Executed { opcode, gas_used, .. }) = vm.step_forward() {
+            if opcode == Opcode::SLoad {
+                sload_costs.push(gas_used);
+            }
+        }
+        assert_eq!(sload_costs, [WARM_STORAGE_READ_COST, COLD_SLOAD_COST, WARM_STORAGE_READ_COST]);
+
+        vm.reset(100_000);
+        assert!(vm.is_warm_slot(Address::ZERO, U256::ONE));
+        assert!(!vm.is_warm_slot(Address::ZERO, U256::from(2u64)));
+    }
+
+    #[test]
+    fn test_rewind_makes_slot_cold_again() {
+        // PUSH1 1, SLOAD, STOP
+        let mut vm = VmBuilder::new(vec![0x60, 0x01, 0x54, 0x00]).gas(100_000).access_costs(true).build().unwrap();
+        vm.step_forward().unwrap();
+        for _ in 0..3 {
+            let Ok(StepResult::Executed { opcode, gas_used, .. }) = vm.step_forward() else { panic!("SLOAD failed") };
+            assert_eq!((opcode, gas_used), (Opcode::SLoad, COLD_SLOAD_COST));
+            assert!(vm.is_warm_slot(Address::ZERO, U256::ONE));
+            vm.step_backward().unwrap();
+            assert!(!vm.is_warm_slot(Address::ZERO, U256::ONE));
+        }
+    }
+
+    #[test]
+    fn test_enforce_block_gas_limit() {
+        let limit = BlockContext::default().gas_limit;
+        let over = VmBuilder::new(vec![0x00]).gas(limit + 1).enforce_block_gas_limit(true).build();
+        assert!(matches!(over, Err(VmError::GasAboveBlockLimit { gas, limit: l }) if gas == limit + 1 && l == limit));
+
+        let vm = VmBuilder::new(vec![0x00]).gas(limit).enforce_block_gas_limit(true).build().unwrap();
+        assert_eq!(vm.block_gas_limit(), limit);
+        assert!(VmBuilder::new(vec![0x00]).gas(limit + 1).build().is_ok(), "not enforced by default");
+    }
+}
@@ -1,14 +1,18 @@
 //! Call frame management for the TTBD virtual machine
 
+use std::sync::Arc;
+
 use crate::core::{U256, Address};
+use crate::executor::Opcode;
+use crate::vm::Memory;
 
 /// A call frame representing a single execution context
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct CallFrame {
     /// Program counter
     pub pc: usize,
     /// Bytecode being executed
-    pub code: Vec<u8>,
+    pub code: Arc<[u8]>,
     /// Current contract address
     pub address: Address,
     /// Caller address
@@ -25,11 +29,21 @@ pub struct CallFrame {
     pub return_offset: usize,
     /// Return data size
     pub return_size: usize,
+    /// Context on the other side of the call boundary: the caller's while
+    /// this frame runs, the callee's final one once it has exited
+    pub saved: SavedContext,
+    /// Journal position, counted from the start of execution, when the
+    /// frame was entered; every storage write the frame made is journaled
+    /// after it
+    pub journal_index: usize,
+    /// Number of logs when the frame was entered; a failed frame discards
+    /// the logs from here on
+    pub log_index: usize,
 }
 
 impl CallFrame {
     pub fn new(
-        code: Vec<u8>,
+        code: Arc<[u8]>,
         address: Address,
         caller: Address,
         value: U256,
@@ -48,6 +62,9 @@ impl CallFrame {
             is_static,
             return_offset: 0,
             return_size: 0,
+            saved: SavedContext::default(),
+            journal_index: 0,
+            log_index: 0,
         }
     }
 
@@ -64,6 +81,18 @@ impl CallFrame {
     }
 }
 
+/// Execution context set aside while another frame runs
+#[derive(Clone, Debug, Default)]
+pub struct SavedContext {
+    pub pc: usize,
+    pub gas: u64,
+    pub stack: Vec<U256>,
+    pub memory: Memory,
+    pub code: Arc<[u8]>,
+    pub jump_dests: Arc<[bool]>,
+    pub decoded: Arc<[Option<(Opcode, u8)>]>,
+}
+
 /// Minimal snapshot of a call frame for journaling
 #[derive(Clone, Debug)]
 pub struct CallFrameSnapshot {
@@ -0,0 +1,12 @@
+//! Event logs emitted by LOG0..LOG4
+
+use crate::core::{Address, U256};
+
+/// A single emitted event
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogRecord {
+    /// Account whose code emitted the log
+    pub address: Address,
+    pub topics: Vec<U256>,
+    pub data: Vec<u8>,
+}
@@ -1,6 +1,10 @@
 //! Linear memory for the TTBD virtual machine
 
-use crate::core::U256;
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::core::{U256, VmError, VmResult};
 
 /// Page size for memory allocation (4KB)
 const PAGE_SIZE: usize = 4096;
@@ -14,6 +18,77 @@ pub struct Memory {
     pages: Vec<Option<Box<[u8; PAGE_SIZE]>>>,
     /// Current logical size (high water mark)
     size: usize,
+    /// Highest byte offset (exclusive) the checked accessors may touch
+    ceiling: usize,
+    /// Content digest, refreshed lazily for the pages written since
+    digest: RefCell<PageDigests>,
+}
+
+/// Memory contents kept as the pages holding non-zero bytes plus the exact
+/// size, for checkpoints of large, mostly empty memories
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SparseMemory {
+    size: usize,
+    pages: Vec<(usize, Box<[u8; PAGE_SIZE]>)>,
+}
+
+impl SparseMemory {
+    /// Memory size in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Estimate memory usage
+    pub fn memory_usage(&self) -> usize {
+        self.pages.len() * (std::mem::size_of::<usize>() + PAGE_SIZE)
+    }
+}
+
+/// Per-page content digests folded into a single root.
+///
+/// The root is the XOR of every page's digest mixed with its index, so a
+/// rewritten page updates it in O(1) once the page is rehashed. All-zero
+/// pages digest to 0 and contribute nothing, making the root independent of
+/// which zero pages happen to be allocated.
+#[derive(Clone, Default)]
+struct PageDigests {
+    pages: Vec<u64>,
+    dirty: Vec<usize>,
+    is_dirty: Vec<bool>,
+    root: u64,
+}
+
+impl PageDigests {
+    fn mark(&mut self, page_idx: usize) {
+        if page_idx >= self.is_dirty.len() {
+            self.is_dirty.resize(page_idx + 1, false);
+            self.pages.resize(page_idx + 1, 0);
+        }
+        if !self.is_dirty[page_idx] {
+            self.is_dirty[page_idx] = true;
+            self.dirty.push(page_idx);
+        }
+    }
+
+    /// Drop the contribution of every page from `page_count` on
+    fn truncate(&mut self, page_count: usize) {
+        for (idx, &digest) in self.pages.iter().enumerate().skip(page_count) {
+            self.root ^= mix(idx, digest);
+        }
+        self.pages.truncate(page_count);
+        self.is_dirty.truncate(page_count);
+        self.dirty.retain(|&idx| idx < page_count);
+    }
+}
+
+/// A page's contribution to the root
+fn mix(page_idx: usize, digest: u64) -> u64 {
+    if digest == 0 {
+        return 0;
+    }
+    let mut hasher = DefaultHasher::new();
+    (page_idx, digest).hash(&mut hasher);
+    hasher.finish()
 }
 
 impl Memory {
@@ -22,6 +97,102 @@ impl Memory {
         Self {
             pages: Vec::new(),
             size: 0,
+            ceiling: usize::MAX,
+            digest: RefCell::default(),
+        }
+    }
+
+    /// Create new empty memory whose checked accessors stop at `ceiling`
+    pub fn with_ceiling(ceiling: usize) -> Self {
+        Self {
+            ceiling,
+            ..Self::new()
+        }
+    }
+
+    /// Ceiling enforced by the checked accessors
+    pub fn ceiling(&self) -> usize {
+        self.ceiling
+    }
+
+    /// Set the ceiling enforced by the checked accessors
+    pub fn set_ceiling(&mut self, ceiling: usize) {
+        self.ceiling = ceiling;
+    }
+
+    /// Load a 256-bit word, failing if the access reaches past the ceiling
+    pub fn try_load(&mut self, offset: usize) -> VmResult<U256> {
+        self.check_access(offset, 32)?;
+        Ok(self.load(offset))
+    }
+
+    /// Load a single byte, failing if the access reaches past the ceiling
+    pub fn try_load_byte(&mut self, offset: usize) -> VmResult<u8> {
+        self.check_access(offset, 1)?;
+        Ok(self.load_byte(offset))
+    }
+
+    /// Store a 256-bit word, failing if the access reaches past the ceiling
+    pub fn try_store(&mut self, offset: usize, value: U256) -> VmResult<Vec<u8>> {
+        self.check_access(offset, 32)?;
+        Ok(self.store(offset, value))
+    }
+
+    /// Store a single byte, failing if the access reaches past the ceiling
+    pub fn try_store_byte(&mut self, offset: usize, value: u8) -> VmResult<u8> {
+        self.check_access(offset, 1)?;
+        Ok(self.store_byte(offset, value))
+    }
+
+    /// Load a 256-bit word at a stack-supplied offset, failing when the
+    /// offset doesn't fit in a usize or the access reaches past the ceiling
+    pub fn try_load_at(&mut self, offset: U256) -> VmResult<U256> {
+        let (offset, _) = Self::checked_range(offset, U256::from(32u64))?;
+        self.try_load(offset)
+    }
+
+    /// Store a 256-bit word at a stack-supplied offset, failing when the
+    /// offset doesn't fit in a usize or the access reaches past the ceiling
+    pub fn try_store_at(&mut self, offset: U256, value: U256) -> VmResult<Vec<u8>> {
+        let (offset, _) = Self::checked_range(offset, U256::from(32u64))?;
+        self.try_store(offset, value)
+    }
+
+    /// Convert a stack-supplied region to usizes, failing when `offset + size`
+    /// doesn't fit in a usize. An empty region never fails and starts at 0,
+    /// whatever its offset.
+    pub fn checked_range(offset: U256, size: U256) -> VmResult<(usize, usize)> {
+        if size.is_zero() {
+            return Ok((0, 0));
+        }
+        let as_usize = |word: U256| {
+            word.0[1..]
+                .iter()
+                .all(|&limb| limb == 0)
+                .then(|| usize::try_from(word.0[0]).ok())
+                .flatten()
+        };
+        match (as_usize(offset), as_usize(size)) {
+            (Some(start), Some(len)) if start.checked_add(len).is_some() => Ok((start, len)),
+            (start, len) => Err(VmError::OutOfBoundsMemory {
+                offset: start.unwrap_or(usize::MAX),
+                size: len.unwrap_or(usize::MAX),
+            }),
+        }
+    }
+
+    /// Grow memory to at least `min_size` bytes, failing past the ceiling
+    pub fn try_expand(&mut self, min_size: usize) -> VmResult<()> {
+        self.check_access(0, min_size)?;
+        self.expand(min_size);
+        Ok(())
+    }
+
+    /// Verify `offset..offset + size` lies below the ceiling
+    fn check_access(&self, offset: usize, size: usize) -> VmResult<()> {
+        match offset.checked_add(size) {
+            Some(end) if end <= self.ceiling => Ok(()),
+            _ => Err(VmError::OutOfBoundsMemory { offset, size }),
         }
     }
 
@@ -80,12 +251,38 @@ impl Memory {
         self.size
     }
 
+    /// Shrink memory back to `new_size` (for rewinding an expansion).
+    ///
+    /// Pages wholly past the new size are released and the tail of the last
+    /// retained page is zeroed, so a later expansion sees zero-filled memory.
+    pub fn truncate(&mut self, new_size: usize) {
+        if new_size >= self.size {
+            return;
+        }
+        let page_count = new_size.div_ceil(PAGE_SIZE);
+        self.pages.truncate(page_count);
+        self.digest.get_mut().truncate(page_count);
+        let tail = new_size % PAGE_SIZE;
+        if tail != 0
+            && let Some(Some(page)) = self.pages.last_mut()
+        {
+            page[tail..].fill(0);
+            self.digest.get_mut().mark(page_count - 1);
+        }
+        self.size = new_size;
+    }
+
+    /// Grow memory to at least `min_size` bytes without writing to it
+    pub fn expand(&mut self, min_size: usize) {
+        self.ensure_size(min_size);
+    }
+
     /// Ensure memory is at least min_size bytes
     fn ensure_size(&mut self, min_size: usize) {
         if min_size <= self.size {
             return;
         }
-        let pages_needed = (min_size + PAGE_SIZE - 1) / PAGE_SIZE;
+        let pages_needed = min_size.div_ceil(PAGE_SIZE);
         while self.pages.len() < pages_needed {
             self.pages.push(None); // Lazy zero-filled pages
         }
@@ -125,6 +322,30 @@ impl Memory {
         if let Some(ref mut page) = self.pages[page_idx] {
             page[page_offset] = value;
         }
+        self.digest.get_mut().mark(page_idx);
+    }
+
+    /// Digest of the memory contents, rehashing only pages written since
+    /// the last call. Equal contents give equal digests regardless of
+    /// size; hash the size separately where it matters.
+    pub fn digest(&self) -> u64 {
+        let mut digests = self.digest.borrow_mut();
+        let PageDigests { pages, dirty, is_dirty, root } = &mut *digests;
+        for idx in dirty.drain(..) {
+            let new = match self.pages.get(idx) {
+                Some(Some(page)) if page.iter().any(|&b| b != 0) => {
+                    let mut hasher = DefaultHasher::new();
+                    page[..].hash(&mut hasher);
+                    // Reserve 0 for all-zero pages
+                    hasher.finish().max(1)
+                }
+                _ => 0,
+            };
+            *root ^= mix(idx, pages[idx]) ^ mix(idx, new);
+            pages[idx] = new;
+            is_dirty[idx] = false;
+        }
+        *root
     }
 
     /// Read a slice of bytes into dst
@@ -158,15 +379,40 @@ impl Memory {
     /// Restore from a snapshot
     pub fn restore_from(&mut self, snapshot: &[u8]) {
         self.pages.clear();
+        self.digest = RefCell::default();
         self.size = 0;
         if !snapshot.is_empty() {
             self.store_bytes(0, snapshot);
         }
     }
 
+    /// Snapshot only the pages holding non-zero bytes, keeping the exact size
+    pub fn sparse_snapshot(&self) -> SparseMemory {
+        let pages = self
+            .pages
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, page)| Some((idx, page.as_ref()?.clone())))
+            .filter(|(_, page)| page.iter().any(|&b| b != 0))
+            .collect();
+        SparseMemory { size: self.size, pages }
+    }
+
+    /// Restore from a sparse snapshot, size included
+    pub fn restore_from_sparse(&mut self, snapshot: &SparseMemory) {
+        self.pages = vec![None; snapshot.size.div_ceil(PAGE_SIZE)];
+        self.digest = RefCell::default();
+        for (idx, page) in &snapshot.pages {
+            self.pages[*idx] = Some(page.clone());
+            self.digest.get_mut().mark(*idx);
+        }
+        self.size = snapshot.size;
+    }
+
     /// Clear all memory
     pub fn clear(&mut self) {
         self.pages.clear();
+        self.digest = RefCell::default();
         self.size = 0;
     }
 
@@ -175,11 +421,20 @@ impl Memory {
         if new_size <= current_size {
             return 0;
         }
-        let new_words = (new_size + 31) / 32;
-        let old_words = (current_size + 31) / 32;
+        let new_words = new_size.div_ceil(32) as u128;
+        let old_words = current_size.div_ceil(32) as u128;
         let new_cost = (new_words * new_words) / 512 + 3 * new_words;
         let old_cost = (old_words * old_words) / 512 + 3 * old_words;
-        (new_cost - old_cost) as u64
+        u64::try_from(new_cost - old_cost).unwrap_or(u64::MAX)
+    }
+}
+
+impl std::fmt::Debug for Memory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Memory")
+            .field("size", &self.size)
+            .field("ceiling", &self.ceiling)
+            .finish()
     }
 }
 
@@ -194,6 +449,8 @@ impl Clone for Memory {
         let mut new_mem = Self::new();
         new_mem.pages = self.pages.clone();
         new_mem.size = self.size;
+        new_mem.ceiling = self.ceiling;
+        new_mem.digest = self.digest.clone();
         new_mem
     }
 }
@@ -226,6 +483,45 @@ mod tests {
         assert!(mem.size() >= 1032); // 1000 + 32
     }
 
+    #[test]
+    fn test_try_load_ceiling() {
+        let mut mem = Memory::with_ceiling(1024);
+        assert!(mem.try_load(1024 - 32).is_ok());
+        assert_eq!(
+            mem.try_load(1025),
+            Err(VmError::OutOfBoundsMemory { offset: 1025, size: 32 })
+        );
+        assert_eq!(mem.size(), 1024, "failed access must not grow memory");
+        assert!(mem.try_store(usize::MAX - 8, U256::ONE).is_err());
+    }
+
+    #[test]
+    fn test_u256_offsets_are_checked() {
+        let mut mem = Memory::new();
+        let wrapping = U256([5, 1, 0, 0]);
+        assert_eq!(
+            mem.try_store_at(wrapping, U256::ONE).unwrap_err(),
+            VmError::OutOfBoundsMemory { offset: usize::MAX, size: 32 }
+        );
+        assert_eq!(mem.size(), 0);
+        assert!(Memory::checked_range(U256::from(usize::MAX), U256::ONE).is_err());
+        assert_eq!(Memory::checked_range(U256::MAX, U256::ZERO).unwrap(), (0, 0));
+
+        mem.try_store_at(U256::from(5u64), U256::from(0xABu64)).unwrap();
+        assert_eq!(mem.try_load_at(U256::from(5u64)).unwrap(), U256::from(0xABu64));
+    }
+
+    #[test]
+    fn test_truncate() {
+        let mut mem = Memory::new();
+        mem.store_byte(10, 0xAA);
+        mem.store_byte(PAGE_SIZE + 10, 0xBB);
+        mem.truncate(5);
+        assert_eq!(mem.size(), 5);
+        assert_eq!(mem.load_byte(10), 0, "truncated bytes read back as zero");
+        assert_eq!(mem.load_byte(PAGE_SIZE + 10), 0);
+    }
+
     #[test]
     fn test_snapshot_restore() {
         let mut mem = Memory::new();
@@ -241,4 +537,34 @@ mod tests {
         assert_eq!(mem.load_byte(1), 2);
         assert_eq!(mem.load_byte(2), 3);
     }
+
+    #[test]
+    fn test_digest_tracks_page_contents() {
+        let mut a = Memory::new();
+        let mut b = Memory::new();
+        a.store(0, U256::from(7u64));
+        b.store(0, U256::from(7u64));
+        a.expand(3 * PAGE_SIZE);
+        b.expand(3 * PAGE_SIZE);
+        assert_eq!(a.digest(), b.digest());
+
+        b.store_byte(2 * PAGE_SIZE + 5, 0x01);
+        assert_ne!(a.digest(), b.digest());
+
+        // Zeroing the byte again leaves an allocated but all-zero page
+        b.store_byte(2 * PAGE_SIZE + 5, 0x00);
+        assert_eq!(a.digest(), b.digest());
+
+        // Same bytes on a different page
+        let mut c = Memory::new();
+        c.store(PAGE_SIZE, U256::from(7u64));
+        c.expand(3 * PAGE_SIZE);
+        assert_ne!(a.digest(), c.digest());
+
+        b.store_byte(PAGE_SIZE + 1, 0x02);
+        b.digest();
+        b.truncate(PAGE_SIZE);
+        a.truncate(PAGE_SIZE);
+        assert_eq!(a.digest(), b.digest());
+    }
 }
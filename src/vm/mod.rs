@@ -4,10 +4,16 @@ mod stack;
 mod memory;
 mod storage;
 mod frame;
+mod account;
+mod log;
 mod state;
+mod builder;
 
-pub use stack::Stack;
-pub use memory::Memory;
-pub use storage::Storage;
-pub use frame::{CallFrame, CallFrameSnapshot};
-pub use state::{VmState, Vm};
+pub use stack::{Stack, MAX_STACK_SIZE};
+pub use memory::{Memory, SparseMemory};
+pub use storage::{Storage, StorageDiff};
+pub use frame::{CallFrame, CallFrameSnapshot, SavedContext, MAX_CALL_DEPTH};
+pub use account::Account;
+pub use log::LogRecord;
+pub use state::{VmState, Vm, HashMode, CheckpointHook, DivByZero};
+pub use builder::VmBuilder;
@@ -101,7 +101,9 @@ impl Stack {
     // === Unsafe hot-path methods ===
 
     /// Pop without bounds checking.
-    /// # Safety: Caller must ensure stack has at least 1 element.
+    ///
+    /// # Safety
+    /// Caller must ensure stack has at least 1 element.
     #[inline(always)]
     pub unsafe fn pop_unchecked(&mut self) -> U256 {
         self.len -= 1;
@@ -109,7 +111,9 @@ impl Stack {
     }
 
     /// Pop two values without bounds checking.
-    /// # Safety: Caller must ensure stack has at least 2 elements.
+    ///
+    /// # Safety
+    /// Caller must ensure stack has at least 2 elements.
     #[inline(always)]
     pub unsafe fn pop2_unchecked(&mut self) -> (U256, U256) {
         let a = unsafe { *self.data.get_unchecked(self.len - 1) };
@@ -119,7 +123,9 @@ impl Stack {
     }
 
     /// Push without bounds checking.
-    /// # Safety: Caller must ensure stack has room.
+    ///
+    /// # Safety
+    /// Caller must ensure stack has room.
     #[inline(always)]
     pub unsafe fn push_unchecked(&mut self, value: U256) {
         unsafe { *self.data.get_unchecked_mut(self.len) = value };
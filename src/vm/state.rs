@@ -1,19 +1,35 @@
 //! VM state and main VM struct
 
-use crate::core::BlockContext;
-use crate::vm::{Stack, Memory, Storage, CallFrame};
-use crate::journal::Journal;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::core::{Address, BlockContext, U256, VmError, VmResult};
+use crate::vm::{Account, LogRecord, Stack, Memory, Storage, CallFrame, MAX_CALL_DEPTH};
+use crate::journal::{HistoryMode, Journal, JournalEntry, StateSnapshot};
+use crate::vm::VmBuilder;
+use crate::bytecode::{decode_instruction, DecodedInstruction};
+use crate::executor::{GasSchedule, Opcode, Precompile};
 
 /// Complete VM state at a point in time
 #[derive(Clone)]
 pub struct VmState {
     pub stack: Stack,
     pub memory: Memory,
+    /// Storage of the root contract
     pub storage: Storage,
+    /// Other accounts reachable by CALL
+    pub accounts: HashMap<Address, Account>,
     pub pc: usize,
     pub gas: u64,
     pub call_depth: usize,
     pub return_data: Vec<u8>,
+    /// Logs emitted so far, oldest first
+    pub logs: Vec<LogRecord>,
+    /// Addresses already accessed, which EIP-2929 prices warm
+    pub accessed_addresses: HashSet<Address>,
+    /// Storage slots already accessed, by account
+    pub accessed_slots: HashSet<(Address, U256)>,
 }
 
 impl VmState {
@@ -22,42 +38,159 @@ impl VmState {
             stack: Stack::new(),
             memory: Memory::new(),
             storage: Storage::new(),
+            accounts: HashMap::new(),
             pc: 0,
             gas,
             call_depth: 0,
             return_data: Vec::new(),
+            logs: Vec::new(),
+            accessed_addresses: HashSet::new(),
+            accessed_slots: HashSet::new(),
         }
     }
 }
 
+/// Controls when the VM computes state hashes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HashMode {
+    /// Hash after every instruction and record it in the journal
+    #[default]
+    EveryStep,
+    /// Hash lazily when requested; journal entries carry a zeroed hash
+    OnDemand,
+}
+
+/// What DIV does with a zero divisor
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DivByZero {
+    /// Push zero, as the EVM defines
+    #[default]
+    Zero,
+    /// Fail with `VmError::DivisionByZero`, for non-EVM experiments
+    Trap,
+}
+
+/// Called with the instruction index and snapshot size in bytes whenever
+/// execution takes a periodic checkpoint
+pub type CheckpointHook = Arc<dyn Fn(usize, usize) + Send + Sync>;
+
 /// The main virtual machine
 pub struct Vm {
     /// Current execution state
     pub(crate) state: VmState,
-    /// Bytecode being executed
-    pub(crate) bytecode: Vec<u8>,
+    /// Bytecode being executed, shared with clones and call frames
+    pub(crate) bytecode: Arc<[u8]>,
     /// Journal for time-travel debugging
     pub(crate) journal: Journal,
     /// Block context (deterministic inputs)
     pub(crate) context: BlockContext,
     /// Valid jump destinations (cached)
-    pub(crate) jump_dests: Vec<bool>,
+    pub(crate) jump_dests: Arc<[bool]>,
+    /// Decoded opcode and immediate size at each pc (cached)
+    pub(crate) decoded: Arc<[Option<(Opcode, u8)>]>,
     /// Call stack for nested calls
     pub(crate) call_stack: Vec<CallFrame>,
+    /// When state hashes are computed
+    pub(crate) hash_mode: HashMode,
+    /// Hash of the current state, if still valid
+    pub(crate) cached_hash: Cell<Option<[u8; 32]>>,
+    /// Use the ceiling-checked memory accessors in the interpreter
+    pub(crate) strict_memory: bool,
+    /// Call data of the root call
+    pub(crate) calldata: Vec<u8>,
+    /// Value sent with the root call
+    pub(crate) call_value: U256,
+    /// Address of the contract running the root call
+    pub(crate) address: Address,
+    /// Deepest call nesting allowed before CALL fails
+    pub(crate) max_call_depth: usize,
+    /// Offset the root call starts at
+    pub(crate) entry_pc: usize,
+    /// Stack the root call starts with, bottom first
+    pub(crate) entry_stack: Vec<U256>,
+    /// Gas the root call started with
+    pub(crate) initial_gas: u64,
+    /// Prices of the configurable opcodes
+    pub(crate) gas_schedule: GasSchedule,
+    /// Journal index the open transaction started at
+    pub(crate) transaction_start: usize,
+    /// Charge gas; when off, gas stays constant and never runs out
+    pub(crate) gas_metering: bool,
+    /// Opcodes charged no gas, indexed by byte
+    pub(crate) free_opcodes: [bool; 256],
+    /// Fail on valid opcodes the interpreter doesn't implement instead of
+    /// skipping them
+    pub(crate) strict_opcodes: bool,
+    /// Charge EIP-2929 cold prices on the first access to an account or slot
+    pub(crate) access_costs: bool,
+    /// Addresses warm from the start of the root call
+    pub(crate) warm_addresses: Vec<Address>,
+    /// Storage slots warm from the start of the root call
+    pub(crate) warm_slots: Vec<(Address, U256)>,
+    /// Notified of each periodic checkpoint
+    pub(crate) checkpoint_hook: Option<CheckpointHook>,
+    /// Check each rewind against the recorded state hashes
+    pub(crate) verify_on_rewind: bool,
+    /// What DIV does with a zero divisor
+    pub(crate) division_by_zero: DivByZero,
+    /// Snapshot memory as a sparse page map rather than a dense copy
+    pub(crate) sparse_checkpoints: bool,
 }
 
 impl Vm {
     /// Create a new VM instance
     pub fn new(bytecode: Vec<u8>, gas: u64, context: BlockContext) -> Self {
-        let jump_dests = Self::analyze_jump_dests(&bytecode);
+        let jump_dests = Self::analyze_jump_dests(&bytecode).into();
+        let decoded = Self::decode_table(&bytecode).into();
         Self {
             state: VmState::new(gas),
-            bytecode,
+            bytecode: bytecode.into(),
             journal: Journal::new(1000, 10_000_000),
             context,
             jump_dests,
+            decoded,
             call_stack: Vec::new(),
+            hash_mode: HashMode::default(),
+            cached_hash: Cell::new(None),
+            strict_memory: false,
+            calldata: Vec::new(),
+            call_value: U256::ZERO,
+            address: Address::ZERO,
+            max_call_depth: MAX_CALL_DEPTH,
+            entry_pc: 0,
+            entry_stack: Vec::new(),
+            initial_gas: gas,
+            gas_schedule: GasSchedule::default(),
+            transaction_start: 0,
+            gas_metering: true,
+            free_opcodes: [false; 256],
+            strict_opcodes: true,
+            access_costs: false,
+            warm_addresses: Vec::new(),
+            warm_slots: Vec::new(),
+            checkpoint_hook: None,
+            verify_on_rewind: false,
+            division_by_zero: DivByZero::Zero,
+            sparse_checkpoints: false,
+        }
+    }
+
+    /// Start a VM from a previously captured snapshot with an empty journal.
+    ///
+    /// Storage of accounts other than the root is installed as accounts
+    /// without code; use `set_code` to give them code.
+    pub fn from_snapshot(bytecode: Vec<u8>, context: BlockContext, snapshot: StateSnapshot) -> Self {
+        let mut vm = Self::new(bytecode, snapshot.gas, context);
+        for address in snapshot.account_storage.keys() {
+            vm.state.accounts.entry(*address).or_default();
         }
+        vm.restore_from_snapshot(&snapshot);
+        vm
+    }
+
+    /// Start configuring a VM for `bytecode`
+    pub fn builder(bytecode: Vec<u8>) -> VmBuilder {
+        VmBuilder::new(bytecode)
     }
 
     /// Get current state reference
@@ -67,14 +200,92 @@ impl Vm {
 
     /// Get mutable state reference
     pub fn state_mut(&mut self) -> &mut VmState {
+        self.invalidate_hash();
         &mut self.state
     }
 
+    /// Set when state hashes are computed
+    pub fn set_hash_mode(&mut self, mode: HashMode) {
+        self.hash_mode = mode;
+    }
+
+    /// Get the current hash mode
+    pub fn hash_mode(&self) -> HashMode {
+        self.hash_mode
+    }
+
+    /// In strict mode, memory accesses past the memory ceiling fail with
+    /// `OutOfBoundsMemory` instead of growing memory
+    pub fn set_strict_memory(&mut self, strict: bool) {
+        self.strict_memory = strict;
+    }
+
+    /// In strict mode (the default), a valid opcode the interpreter doesn't
+    /// implement fails with `UnimplementedOpcode`; lenient mode skips it
+    /// as a no-op that only costs gas
+    pub fn set_strict_opcodes(&mut self, strict: bool) {
+        self.strict_opcodes = strict;
+    }
+
+    /// Whether unimplemented opcodes fail rather than being skipped
+    pub fn strict_opcodes(&self) -> bool {
+        self.strict_opcodes
+    }
+
+    /// Check every `step_backward` by recomputing the state hash and
+    /// comparing it with the one recorded after the previous instruction,
+    /// failing with `ReversibilityViolation` on a mismatch. Only
+    /// `HashMode::EveryStep` records hashes to compare against, and
+    /// positions inside a `compact_journal` range are not checked.
+    pub fn set_verify_on_rewind(&mut self, verify: bool) {
+        self.verify_on_rewind = verify;
+    }
+
+    /// Choose what DIV does with a zero divisor; pushing zero is the default
+    pub fn set_division_by_zero(&mut self, mode: DivByZero) {
+        self.division_by_zero = mode;
+    }
+
+    /// What DIV does with a zero divisor
+    pub fn division_by_zero(&self) -> DivByZero {
+        self.division_by_zero
+    }
+
+    /// Store memory in snapshots as the non-zero pages and the exact size
+    /// instead of a dense copy; cheaper for large, mostly empty memories
+    pub fn set_sparse_checkpoints(&mut self, sparse: bool) {
+        self.sparse_checkpoints = sparse;
+    }
+
     /// Get journal reference
     pub fn journal(&self) -> &Journal {
         &self.journal
     }
 
+    /// Set the journal's checkpoint interval (0 disables checkpointing)
+    pub fn set_checkpoint_interval(&mut self, interval: usize) {
+        self.journal.set_checkpoint_interval(interval);
+    }
+
+    /// Set how the journal drops old history
+    pub fn set_history_mode(&mut self, mode: HistoryMode) {
+        self.journal.set_history_mode(mode);
+    }
+
+    /// Collapse redundant storage and memory writes among the instructions
+    /// at positions `from..to`, as counted by `rewind_to`. Rewinding to
+    /// `from` or `to` stays exact; see `Journal::compact_range`.
+    ///
+    /// Fails with `JournalExhausted` if `from` has already been dropped.
+    /// Returns the number of entries removed.
+    pub fn compact_journal(&mut self, from: usize, to: usize) -> VmResult<usize> {
+        let first = self.journal.first_index();
+        if from < first {
+            return Err(VmError::JournalExhausted);
+        }
+        Ok(self.journal.compact_range(from - first, to.saturating_sub(first)))
+    }
+
     /// Get block context
     pub fn context(&self) -> &BlockContext {
         &self.context
@@ -85,13 +296,202 @@ impl Vm {
         &self.bytecode
     }
 
+    /// Get the root call data
+    pub fn calldata(&self) -> &[u8] {
+        &self.calldata
+    }
+
+    /// Set the root call data used by subsequent execution
+    pub fn set_calldata(&mut self, calldata: Vec<u8>) {
+        self.calldata = calldata;
+    }
+
+    /// Get the value sent with the root call
+    pub fn call_value(&self) -> U256 {
+        self.call_value
+    }
+
+    /// Set the value sent with the root call
+    pub fn set_call_value(&mut self, value: U256) {
+        self.call_value = value;
+    }
+
+    /// Get the address of the contract running the root call
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    /// Get the gas the root call started with
+    pub fn initial_gas(&self) -> u64 {
+        self.initial_gas
+    }
+
+    /// Get the gas schedule used to price opcodes
+    pub fn gas_schedule(&self) -> &GasSchedule {
+        &self.gas_schedule
+    }
+
+    /// Whether instructions are charged gas
+    pub fn gas_metering(&self) -> bool {
+        self.gas_metering
+    }
+
+    /// Charge no gas for `set`, replacing any previous set; every other
+    /// opcode is priced as usual. An empty set restores normal pricing.
+    pub fn set_free_opcodes(&mut self, set: &[Opcode]) {
+        self.free_opcodes = [false; 256];
+        for &opcode in set {
+            self.free_opcodes[opcode as usize] = true;
+        }
+    }
+
+    /// Whether `opcode` is charged gas when executed
+    pub fn charges_gas(&self, opcode: Opcode) -> bool {
+        self.gas_metering && !self.free_opcodes[opcode as usize]
+    }
+
+    /// Gas limit of the block, from the context
+    pub fn block_gas_limit(&self) -> u64 {
+        self.context.gas_limit
+    }
+
+    /// Get the deepest call nesting allowed
+    pub fn max_call_depth(&self) -> usize {
+        self.max_call_depth
+    }
+
+    /// Install `code` at `address`, an account other than the root contract
+    pub fn set_code(&mut self, address: Address, code: Vec<u8>) {
+        self.state.accounts.entry(address).or_default().code = code.into();
+    }
+
+    /// Set the balance of the account at `address`, including the root
+    pub fn set_balance(&mut self, address: Address, balance: U256) {
+        self.state.accounts.entry(address).or_default().balance = balance;
+        self.invalidate_hash();
+    }
+
+    /// Balance of the account at `address`, zero if there is none
+    pub fn balance_of(&self, address: Address) -> U256 {
+        self.state.accounts.get(&address).map_or(U256::ZERO, |account| account.balance)
+    }
+
+    /// Code deployed at `address`, including the root contract's
+    pub fn code_at(&self, address: Address) -> &[u8] {
+        if address == self.address {
+            // While a call is active, the root code is set aside in the
+            // outermost frame
+            return self.call_stack.first().map_or(&self.bytecode, |frame| &frame.saved.code);
+        }
+        self.state.accounts.get(&address).map_or(&[], |account| &account.code)
+    }
+
+    /// Shared handle to the code at `address`, empty if there is none
+    pub(crate) fn shared_code_at(&self, address: Address) -> Arc<[u8]> {
+        if address == self.address {
+            return self.call_stack.first().map_or(&self.bytecode, |frame| &frame.saved.code).clone();
+        }
+        self.state.accounts.get(&address).map(|account| account.code.clone()).unwrap_or_default()
+    }
+
+    /// Overwrite root code at `offset` with `bytes` and redo the code
+    /// analysis, for what-if experiments. Only allowed before anything has
+    /// executed, e.g. right after `reset`.
+    pub fn patch_bytecode(&mut self, offset: usize, bytes: &[u8]) -> VmResult<()> {
+        if !self.journal.is_empty() || !self.call_stack.is_empty() {
+            return Err(VmError::HistoryNotEmpty { recorded: self.journal.len() });
+        }
+        let end = offset.checked_add(bytes.len()).filter(|&end| end <= self.bytecode.len());
+        let Some(end) = end else {
+            return Err(VmError::CodeOutOfBounds { offset, size: bytes.len() });
+        };
+
+        let mut code = self.bytecode.to_vec();
+        code[offset..end].copy_from_slice(bytes);
+        self.jump_dests = Self::analyze_jump_dests(&code).into();
+        self.decoded = Self::decode_table(&code).into();
+        self.bytecode = code.into();
+        self.invalidate_hash();
+        Ok(())
+    }
+
+    /// Read storage slot `key` of the account at `address`
+    pub fn storage_at(&self, address: Address, key: &U256) -> U256 {
+        if address == self.address {
+            return self.state.storage.get(key);
+        }
+        self.state.accounts.get(&address).map_or(U256::ZERO, |account| account.storage.get(key))
+    }
+
+    /// Storage of the account at `address`, created empty if missing
+    pub(crate) fn storage_mut(&mut self, address: Address) -> &mut Storage {
+        if address == self.address {
+            return &mut self.state.storage;
+        }
+        &mut self.state.accounts.entry(address).or_default().storage
+    }
+
+    /// Address of the active call frame (the root address at top level)
+    pub fn active_address(&self) -> Address {
+        self.call_stack.last().map_or(self.address, |frame| frame.address)
+    }
+
+    /// Caller of the active call frame (zero at top level)
+    pub fn active_caller(&self) -> Address {
+        self.call_stack.last().map_or(Address::ZERO, |frame| frame.caller)
+    }
+
+    /// Call data of the active call frame (the root call data at top level)
+    pub fn active_calldata(&self) -> &[u8] {
+        self.call_stack.last().map_or(&self.calldata, |frame| &frame.calldata)
+    }
+
+    /// Value of the active call frame (the root call value at top level)
+    pub fn active_call_value(&self) -> U256 {
+        self.call_stack.last().map_or(self.call_value, |frame| frame.value)
+    }
+
     /// Check if address is a valid jump destination
     pub fn is_valid_jump(&self, dest: usize) -> bool {
         self.jump_dests.get(dest).copied().unwrap_or(false)
     }
 
+    /// The instruction starting at `pc` in the active code, decoded. `pc`
+    /// isn't checked against instruction boundaries, so an offset inside a
+    /// PUSH immediate decodes the data byte there.
+    pub fn instruction_at(&self, pc: usize) -> Option<DecodedInstruction> {
+        decode_instruction(&self.bytecode, pc)
+    }
+
+    /// Instructions of the active code whose offsets fall in `start..end`,
+    /// decoded along the real instruction boundaries; undecodable bytes
+    /// are left out
+    pub fn instructions_in_range(&self, start: usize, end: usize) -> Vec<DecodedInstruction> {
+        let mut instructions = Vec::new();
+        let mut offset = 0;
+        while offset < end.min(self.bytecode.len()) {
+            let size = self.decoded[offset].map_or(0, |(_, immediate)| immediate as usize);
+            if offset >= start
+                && let Some(insn) = decode_instruction(&self.bytecode, offset)
+            {
+                instructions.push(insn);
+            }
+            offset += 1 + size;
+        }
+        instructions
+    }
+
+    /// Offsets of all valid jump destinations in the active code
+    pub fn jump_destinations(&self) -> Vec<usize> {
+        self.jump_dests
+            .iter()
+            .enumerate()
+            .filter_map(|(offset, &valid)| valid.then_some(offset))
+            .collect()
+    }
+
     /// Analyze bytecode to find valid JUMPDEST positions
-    fn analyze_jump_dests(bytecode: &[u8]) -> Vec<bool> {
+    pub(crate) fn analyze_jump_dests(bytecode: &[u8]) -> Vec<bool> {
         let mut result = vec![false; bytecode.len()];
         let mut i = 0;
         
@@ -101,7 +501,7 @@ impl Vm {
                 result[i] = true;
             }
             // Skip PUSH immediate data
-            if opcode >= 0x60 && opcode <= 0x7F {
+            if (0x60..=0x7F).contains(&opcode) {
                 let push_size = (opcode - 0x5F) as usize;
                 i += push_size;
             }
@@ -111,6 +511,31 @@ impl Vm {
         result
     }
 
+    /// Decode every byte of `bytecode` once so stepping can skip `from_u8`
+    pub(crate) fn decode_table(bytecode: &[u8]) -> Vec<Option<(Opcode, u8)>> {
+        bytecode
+            .iter()
+            .map(|&byte| Opcode::from_u8(byte).map(|op| (op, op.immediate_size() as u8)))
+            .collect()
+    }
+
+    /// Hash of the current state, reusing the cached value when the state
+    /// has not changed since it was last computed
+    pub fn state_hash(&self) -> [u8; 32] {
+        if let Some(hash) = self.cached_hash.get() {
+            return hash;
+        }
+        let hash = self.compute_state_hash();
+        self.cached_hash.set(Some(hash));
+        hash
+    }
+
+    /// Drop the cached state hash after a state mutation
+    #[inline]
+    pub(crate) fn invalidate_hash(&self) {
+        self.cached_hash.set(None);
+    }
+
     /// Compute a hash of the current state (for determinism verification)
     pub fn compute_state_hash(&self) -> [u8; 32] {
         use std::collections::hash_map::DefaultHasher;
@@ -129,8 +554,28 @@ impl Vm {
             val.0.hash(&mut hasher);
         }
         
-        // Hash memory size (not contents for performance)
+        // Hash memory size and a page-level digest of its contents
         self.state.memory.size().hash(&mut hasher);
+        self.state.memory.digest().hash(&mut hasher);
+        
+        // Hash everything else a broken inverse could leave behind. Maps
+        // and sets are folded order-independently, and zero slots and
+        // balances are skipped since rewinding a write leaves them in place.
+        let root = self.address;
+        let storage = self.state.storage.iter().map(|(key, value)| (root, key, value));
+        let accounts = self.state.accounts.iter().flat_map(|(address, account)| {
+            account.storage.iter().map(move |(key, value)| (*address, key, value))
+        });
+        unordered_digest(storage.chain(accounts).filter(|(_, _, value)| !value.is_zero())).hash(&mut hasher);
+        unordered_digest(self.state.accounts.iter().filter(|(_, account)| !account.balance.is_zero())
+            .map(|(address, account)| (address, account.balance))).hash(&mut hasher);
+        for log in &self.state.logs {
+            (&log.address, &log.topics, &log.data).hash(&mut hasher);
+        }
+        self.state.return_data.hash(&mut hasher);
+        self.state.call_depth.hash(&mut hasher);
+        unordered_digest(self.state.accessed_addresses.iter()).hash(&mut hasher);
+        unordered_digest(self.state.accessed_slots.iter()).hash(&mut hasher);
         
         let hash = hasher.finish();
         let mut result = [0u8; 32];
@@ -138,12 +583,173 @@ impl Vm {
         result
     }
 
-    /// Reset VM to initial state
+    /// Fork the live state into an independent VM.
+    ///
+    /// The fork copies state, bytecode and context but starts with an empty
+    /// journal (same checkpoint settings), so it cannot rewind before the
+    /// fork point. Use `clone` when the history is needed too.
+    pub fn fork_state(&self) -> Vm {
+        let mut journal = Journal::new(self.journal.checkpoint_interval(), self.journal.max_size());
+        journal.set_history_mode(self.journal.history_mode());
+        Self {
+            state: self.state.clone(),
+            bytecode: self.bytecode.clone(),
+            journal,
+            context: self.context.clone(),
+            jump_dests: self.jump_dests.clone(),
+            decoded: self.decoded.clone(),
+            call_stack: self.call_stack.clone(),
+            hash_mode: self.hash_mode,
+            cached_hash: self.cached_hash.clone(),
+            strict_memory: self.strict_memory,
+            calldata: self.calldata.clone(),
+            call_value: self.call_value,
+            address: self.address,
+            max_call_depth: self.max_call_depth,
+            entry_pc: self.entry_pc,
+            entry_stack: self.entry_stack.clone(),
+            initial_gas: self.initial_gas,
+            gas_schedule: self.gas_schedule,
+            transaction_start: 0,
+            gas_metering: self.gas_metering,
+            free_opcodes: self.free_opcodes,
+            strict_opcodes: self.strict_opcodes,
+            access_costs: self.access_costs,
+            warm_addresses: self.warm_addresses.clone(),
+            warm_slots: self.warm_slots.clone(),
+            checkpoint_hook: self.checkpoint_hook.clone(),
+            verify_on_rewind: self.verify_on_rewind,
+            division_by_zero: self.division_by_zero,
+            sparse_checkpoints: self.sparse_checkpoints,
+        }
+    }
+
+    /// Charge EIP-2929 cold prices for the first access to each account and
+    /// storage slot. The root address, precompiles and the access list are
+    /// warm from the start. First accesses are journaled, so rewinding past
+    /// one makes the account or slot cold again.
+    pub fn set_access_costs(&mut self, enabled: bool) {
+        self.access_costs = enabled;
+    }
+
+    /// Whether EIP-2929 cold access prices are charged
+    pub fn access_costs(&self) -> bool {
+        self.access_costs
+    }
+
+    /// Declare an EIP-2930 access list: these addresses and slots are warm
+    /// from the start of the root call, now and after every reset
+    pub fn set_access_list(&mut self, addresses: Vec<Address>, slots: Vec<(Address, U256)>) {
+        self.warm_addresses = addresses;
+        self.warm_slots = slots;
+        self.seed_access_list();
+        self.invalidate_hash();
+    }
+
+    /// Whether `address` has been accessed or is otherwise warm
+    pub fn is_warm_address(&self, address: Address) -> bool {
+        address == self.address
+            || Precompile::from_address(&address).is_some()
+            || self.state.accessed_addresses.contains(&address)
+    }
+
+    /// Whether slot `key` of `address` has been accessed or preloaded warm
+    pub fn is_warm_slot(&self, address: Address, key: U256) -> bool {
+        self.state.accessed_slots.contains(&(address, key))
+    }
+
+    fn seed_access_list(&mut self) {
+        self.state.accessed_addresses.extend(self.warm_addresses.iter().copied());
+        self.state.accessed_slots.extend(self.warm_slots.iter().copied());
+    }
+
+    /// Call `hook` with the instruction index and snapshot size in bytes
+    /// each time stepping takes a periodic checkpoint, e.g. to tune the
+    /// checkpoint interval. Clones share the hook.
+    pub fn set_checkpoint_hook(&mut self, hook: impl Fn(usize, usize) + Send + Sync + 'static) {
+        self.checkpoint_hook = Some(Arc::new(hook));
+    }
+
+    /// Stop notifying the checkpoint hook
+    pub fn clear_checkpoint_hook(&mut self) {
+        self.checkpoint_hook = None;
+    }
+
+    /// Reset VM to initial state, back at the configured entry pc and stack
     pub fn reset(&mut self, gas: u64) {
+        if let Some(root) = self.call_stack.first_mut() {
+            std::mem::swap(&mut self.bytecode, &mut root.saved.code);
+            std::mem::swap(&mut self.jump_dests, &mut root.saved.jump_dests);
+            std::mem::swap(&mut self.decoded, &mut root.saved.decoded);
+        }
+        let ceiling = self.state.memory.ceiling();
+        // Deployed code and balances survive a reset; storage does not
+        let accounts = std::mem::take(&mut self.state.accounts)
+            .into_iter()
+            .map(|(address, account)| (address, Account { balance: account.balance, ..Account::new(account.code) }))
+            .collect();
         self.state = VmState::new(gas);
+        self.initial_gas = gas;
+        self.state.memory.set_ceiling(ceiling);
+        self.state.accounts = accounts;
+        self.seed_access_list();
+        self.state.pc = self.entry_pc;
+        self.state.stack.restore_from(&self.entry_stack);
         self.journal.clear();
         self.call_stack.clear();
+        self.transaction_start = 0;
+        self.invalidate_hash();
     }
+
+    /// Reset VM to initial state with new root call data, keeping the
+    /// bytecode analysis
+    pub fn reset_with_calldata(&mut self, gas: u64, calldata: Vec<u8>) {
+        self.reset(gas);
+        self.calldata = calldata;
+    }
+
+    /// Open a transaction at the current journal position
+    pub fn begin_transaction(&mut self) {
+        self.transaction_start = self.journal.end_index();
+    }
+
+    /// Undo everything executed since the open transaction began.
+    ///
+    /// Without `begin_transaction` this rewinds to the start of execution,
+    /// or to the last `commit_transaction`.
+    pub fn rollback_transaction(&mut self) -> VmResult<()> {
+        self.rewind_to(self.transaction_start).map(|_| ())
+    }
+
+    /// Make the current storage the new baseline: the original values
+    /// `Storage::get_original` reports are updated and later rollbacks stop
+    /// here. The replaced originals are journaled with the last instruction,
+    /// so rewinding past the commit restores them.
+    pub fn commit_transaction(&mut self) {
+        let root = self.address;
+        let mut originals = vec![(root, self.state.storage.commit())];
+        originals.extend(
+            self.state.accounts.iter_mut()
+                .filter(|(address, _)| **address != root)
+                .map(|(address, account)| (*address, account.storage.commit())),
+        );
+        if let Some(last) = self.journal.peek_mut() {
+            last.entries.push(JournalEntry::TransactionCommitted { originals });
+        }
+        self.transaction_start = self.journal.end_index();
+    }
+}
+
+/// Sum of per-item hashes, so iteration order doesn't matter
+fn unordered_digest<T: std::hash::Hash>(items: impl Iterator<Item = T>) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::Hasher;
+
+    items.fold(0u64, |acc, item| {
+        let mut hasher = DefaultHasher::new();
+        item.hash(&mut hasher);
+        acc.wrapping_add(hasher.finish())
+    })
 }
 
 impl Clone for Vm {
@@ -154,7 +760,360 @@ impl Clone for Vm {
             journal: self.journal.clone(),
             context: self.context.clone(),
             jump_dests: self.jump_dests.clone(),
+            decoded: self.decoded.clone(),
             call_stack: self.call_stack.clone(),
+            hash_mode: self.hash_mode,
+            cached_hash: self.cached_hash.clone(),
+            strict_memory: self.strict_memory,
+            calldata: self.calldata.clone(),
+            call_value: self.call_value,
+            address: self.address,
+            max_call_depth: self.max_call_depth,
+            entry_pc: self.entry_pc,
+            entry_stack: self.entry_stack.clone(),
+            initial_gas: self.initial_gas,
+            gas_schedule: self.gas_schedule,
+            transaction_start: self.transaction_start,
+            gas_metering: self.gas_metering,
+            free_opcodes: self.free_opcodes,
+            strict_opcodes: self.strict_opcodes,
+            access_costs: self.access_costs,
+            warm_addresses: self.warm_addresses.clone(),
+            warm_slots: self.warm_slots.clone(),
+            checkpoint_hook: self.checkpoint_hook.clone(),
+            verify_on_rewind: self.verify_on_rewind,
+            division_by_zero: self.division_by_zero,
+            sparse_checkpoints: self.sparse_checkpoints,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::U256;
+    use crate::executor::StepResult;
+    use std::time::{Duration, Instant};
+
+    /// Writes a word to every 32-byte slot up to 0x4000 on top of a deep stack.
+    fn memory_heavy_vm() -> Vm {
+        let bytecode = vec![
+            0x60, 0x00,       // PUSH1 0
+            0x5B,             // JUMPDEST
+            0x80, 0x80,       // DUP1 DUP1
+            0x52,             // MSTORE
+            0x60, 0x20, 0x01, // PUSH1 32, ADD
+            0x80,             // DUP1
+            0x61, 0x40, 0x00, // PUSH2 0x4000
+            0x11,             // GT
+            0x60, 0x02,       // PUSH1 2
+            0x57,             // JUMPI
+            0x00,             // STOP
+        ];
+        let mut vm = Vm::new(bytecode, 10_000_000, BlockContext::default());
+        for i in 0..512u64 {
+            vm.state_mut().stack.push(U256::from(i)).unwrap();
+        }
+        vm
+    }
+
+    fn run_timed(vm: &mut Vm) -> Duration {
+        let start = Instant::now();
+        while let StepResult::Executed { .. } = vm.step_forward().unwrap() {}
+        start.elapsed()
+    }
+
+    #[test]
+    fn test_on_demand_hash_matches_every_step() {
+        let mut every_step = memory_heavy_vm();
+        let mut on_demand = memory_heavy_vm();
+        on_demand.set_hash_mode(HashMode::OnDemand);
+
+        run_timed(&mut every_step);
+        run_timed(&mut on_demand);
+
+        assert_eq!(every_step.state_hash(), on_demand.state_hash());
+        assert_eq!(on_demand.journal().peek().unwrap().state_hash, [0u8; 32]);
+    }
+
+    #[test]
+    #[ignore = "wall-clock timing; run with --ignored"]
+    fn test_on_demand_hash_is_faster() {
+        let mut every_step = memory_heavy_vm();
+        let mut on_demand = memory_heavy_vm();
+        on_demand.set_hash_mode(HashMode::OnDemand);
+
+        let every_step_time = run_timed(&mut every_step);
+        let on_demand_time = run_timed(&mut on_demand);
+        assert!(
+            on_demand_time < every_step_time,
+            "on-demand {on_demand_time:?} should beat every-step {every_step_time:?}"
+        );
+    }
+
+    #[test]
+    fn test_decode_cache_matches_opcode_decoding() {
+        // Count down from 20000: PUSH2 20000, JUMPDEST, PUSH1 1, SWAP1, SUB,
+        // DUP1, PUSH1 3, JUMPI, STOP
+        let bytecode = vec![
+            0x61, 0x4E, 0x20, 0x5B, 0x60, 0x01, 0x90, 0x03, 0x80, 0x60, 0x03, 0x57, 0x00,
+        ];
+        let mut vm = Vm::new(bytecode.clone(), 10_000_000, BlockContext::default());
+        vm.set_hash_mode(HashMode::OnDemand);
+        for (pc, &byte) in bytecode.iter().enumerate() {
+            let expected = Opcode::from_u8(byte).map(|op| (op, op.immediate_size() as u8));
+            assert_eq!(vm.decoded[pc], expected);
         }
+
+        vm.run().unwrap();
+        assert_eq!(vm.state().stack.as_slice(), &[U256::ZERO]);
+        assert_eq!(vm.journal().len(), 1 + 20_000 * 7 + 1);
+    }
+
+    #[test]
+    fn test_fork_state_is_independent() {
+        // PUSH1 1, PUSH1 0, SSTORE, STOP
+        let mut vm = Vm::new(vec![0x60, 0x01, 0x60, 0x00, 0x55, 0x00], 100_000, BlockContext::default());
+        vm.run().unwrap();
+
+        let mut fork = vm.fork_state();
+        assert!(fork.journal().is_empty());
+        assert_eq!(fork.state_hash(), vm.state_hash());
+
+        fork.state_mut().storage.insert(U256::ZERO, U256::from(99u64));
+        assert_eq!(vm.state().storage.get(&U256::ZERO), U256::ONE);
+        assert_eq!(fork.state().storage.get(&U256::ZERO), U256::from(99u64));
+
+        // The fork has no history to rewind into
+        assert!(fork.step_backward().is_err());
+        vm.step_backward().unwrap();
+    }
+
+    #[test]
+    fn test_clone_shares_bytecode() {
+        let vm = memory_heavy_vm();
+        assert_eq!(Arc::strong_count(&vm.bytecode), 1);
+
+        let copy = vm.clone();
+        let fork = vm.fork_state();
+        assert_eq!(Arc::strong_count(&vm.bytecode), 3);
+        assert!(Arc::ptr_eq(&copy.bytecode, &vm.bytecode));
+        assert!(Arc::ptr_eq(&fork.jump_dests, &vm.jump_dests));
+        assert_eq!(Arc::strong_count(&vm.decoded), 3);
+
+        drop(copy);
+        assert_eq!(Arc::strong_count(&vm.bytecode), 2);
+    }
+
+    #[test]
+    fn test_free_opcodes() {
+        // Add the counter to slot 1 for counter = 10..1, reading it with SLOAD
+        let bytecode = vec![
+            0x60, 0x0A, 0x5B, 0x80, 0x60, 0x01, 0x54, 0x01, 0x60, 0x01, 0x55,
+            0x60, 0x01, 0x90, 0x03, 0x80, 0x60, 0x02, 0x57, 0x00,
+        ];
+        let mut metered = Vm::new(bytecode.clone(), 1_000_000, BlockContext::default());
+        let mut free = Vm::new(bytecode, 1_000_000, BlockContext::default());
+        free.set_free_opcodes(&[Opcode::SLoad]);
+        assert!(!free.charges_gas(Opcode::SLoad) && free.charges_gas(Opcode::SStore));
+
+        let metered_gas = metered.run().unwrap().gas_used();
+        let free_gas = free.run().unwrap().gas_used();
+        assert_eq!(metered_gas - free_gas, 10 * metered.gas_schedule().cost(Opcode::SLoad));
+        assert_eq!(free.state().storage.get(&U256::ONE), U256::from(55u64));
+        assert_eq!(free.state().storage.get(&U256::ONE), metered.state().storage.get(&U256::ONE));
+
+        let sload = free.journal().iter().find(|insn| insn.opcode == Opcode::SLoad as u8).unwrap();
+        assert_eq!(sload.gas_before, sload.gas_after);
+    }
+
+    #[test]
+    fn test_patch_bytecode() {
+        // PUSH1 1, PUSH1 2, ADD, STOP
+        let mut vm = Vm::new(vec![0x60, 0x01, 0x60, 0x02, 0x01, 0x00], 100_000, BlockContext::default());
+        vm.step_forward().unwrap();
+        assert_eq!(vm.patch_bytecode(4, &[0x50]), Err(VmError::HistoryNotEmpty { recorded: 1 }));
+
+        vm.reset(100_000);
+        assert!(vm.patch_bytecode(5, &[0x00, 0x00]).is_err());
+        // ADD -> POP
+        vm.patch_bytecode(4, &[0x50]).unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.state().stack.to_vec(), vec![U256::ONE]);
+
+        // STOP -> JUMPDEST
+        vm.reset(100_000);
+        assert!(!vm.is_valid_jump(5));
+        vm.patch_bytecode(5, &[0x5B]).unwrap();
+        assert!(vm.is_valid_jump(5));
+        assert_eq!(vm.jump_destinations(), vec![5]);
+    }
+
+    #[test]
+    fn test_transaction_rollback_and_commit() {
+        // SSTORE 1 -> slot 0, 2 -> slot 1, 3 -> slot 2, STOP
+        let bytecode = vec![
+            0x60, 0x01, 0x60, 0x00, 0x55,
+            0x60, 0x02, 0x60, 0x01, 0x55,
+            0x60, 0x03, 0x60, 0x02, 0x55,
+            0x00,
+        ];
+        let mut vm = Vm::new(bytecode, 100_000, BlockContext::default());
+        let slots = |vm: &Vm| (0..3u64).map(|k| vm.state().storage.get(&U256::from(k)).as_u64()).collect::<Vec<_>>();
+
+        vm.begin_transaction();
+        for _ in 0..9 {
+            vm.step_forward().unwrap();
+        }
+        assert_eq!(slots(&vm), vec![1, 2, 3]);
+        vm.rollback_transaction().unwrap();
+        assert_eq!(slots(&vm), vec![0, 0, 0]);
+        assert_eq!(vm.state().pc, 0);
+
+        for _ in 0..9 {
+            vm.step_forward().unwrap();
+        }
+        vm.commit_transaction();
+        assert_eq!(vm.state().storage.get_original(&U256::from(2u64)), U256::from(3u64));
+        vm.rollback_transaction().unwrap();
+        assert_eq!(slots(&vm), vec![1, 2, 3]);
+        assert_eq!(vm.journal().len(), 9);
+    }
+
+    #[test]
+    fn test_rewind_past_commit_restores_originals() {
+        // SSTORE 1 -> slot 0, 2 -> slot 1, 3 -> slot 2, STOP
+        let bytecode = vec![
+            0x60, 0x01, 0x60, 0x00, 0x55,
+            0x60, 0x02, 0x60, 0x01, 0x55,
+            0x60, 0x03, 0x60, 0x02, 0x55,
+            0x00,
+        ];
+        let slot = U256::ZERO;
+        for interval in [0, 2] {
+            let mut vm = Vm::new(bytecode.clone(), 100_000, BlockContext::default());
+            vm.set_checkpoint_interval(interval);
+            for _ in 0..6 {
+                vm.step_forward().unwrap();
+            }
+            vm.commit_transaction();
+            assert_eq!(vm.state().storage.get_original(&slot), U256::ONE);
+            vm.run().unwrap();
+
+            // Back to the commit point the new baseline still holds
+            vm.rewind_to(6).unwrap();
+            assert_eq!(vm.state().storage.get_original(&slot), U256::ONE);
+
+            // Before it the commit never happened and the slot is dirty
+            // again, also when a checkpoint skips the committing step
+            vm.rewind_to(4).unwrap();
+            assert_eq!(vm.state().storage.get_original(&slot), U256::ZERO);
+            assert_eq!(vm.state().storage.get(&slot), U256::ONE);
+        }
+    }
+
+    #[test]
+    fn test_resume_from_snapshot() {
+        let mut vm = memory_heavy_vm();
+        for _ in 0..25 {
+            vm.step_forward().unwrap();
+        }
+        let snapshot = vm.create_state_snapshot();
+        let mut resumed = Vm::from_snapshot(vm.bytecode().to_vec(), BlockContext::default(), snapshot);
+        assert!(resumed.journal().is_empty());
+        assert_eq!(resumed.state_hash(), vm.state_hash());
+
+        for _ in 0..3 {
+            let expected = vm.step_forward().unwrap();
+            let actual = resumed.step_forward().unwrap();
+            assert_eq!(format!("{actual:?}"), format!("{expected:?}"));
+            assert_eq!(resumed.state_hash(), vm.state_hash());
+        }
+    }
+
+    #[test]
+    fn test_sparse_checkpoint_rewind_matches_step_rewind() {
+        let mut vm = memory_heavy_vm();
+        vm.set_sparse_checkpoints(true);
+        vm.set_checkpoint_interval(100);
+        vm.run().unwrap();
+        assert_eq!(vm.state().memory.size(), 0x4000);
+
+        for target in [1_234, 150, 3] {
+            let mut stepped = vm.clone();
+            stepped.rewind(stepped.journal().len() - target).unwrap();
+            let checkpoint = vm.journal().find_checkpoint_at_or_after(target).unwrap();
+            assert!(checkpoint.state_snapshot.sparse_memory.is_some());
+
+            vm.rewind_to(target).unwrap();
+            assert_eq!(vm.state().memory.size(), stepped.state().memory.size());
+            assert_eq!(vm.state().memory.snapshot(), stepped.state().memory.snapshot());
+            assert_eq!(vm.state_hash(), stepped.state_hash());
+        }
+    }
+
+    #[test]
+    fn test_cached_hash_invalidated_by_step() {
+        let mut vm = memory_heavy_vm();
+        vm.set_hash_mode(HashMode::OnDemand);
+        let before = vm.state_hash();
+        vm.step_forward().unwrap();
+        assert_ne!(vm.state_hash(), before);
+        vm.step_backward().unwrap();
+        assert_eq!(vm.state_hash(), before);
+    }
+
+    #[test]
+    fn test_cached_hash_invalidated_by_set_balance() {
+        let mut vm = memory_heavy_vm();
+        let before = vm.state_hash();
+        vm.set_balance(Address::from_word(U256::from(0x77u64)), U256::from(5u64));
+        assert_ne!(vm.state_hash(), before);
+        assert_eq!(vm.state_hash(), vm.compute_state_hash());
+    }
+
+    #[test]
+    fn test_cached_hash_invalidated_by_set_access_list() {
+        let mut vm = memory_heavy_vm();
+        let before = vm.state_hash();
+        let address = Address::from_word(U256::from(0x77u64));
+        vm.set_access_list(vec![address], vec![(address, U256::ONE)]);
+        assert_ne!(vm.state_hash(), before);
+        assert_eq!(vm.state_hash(), vm.compute_state_hash());
+    }
+
+    #[test]
+    fn test_checkpoint_hook_fires_per_interval() {
+        // JUMPDEST, PUSH1 0, JUMP forever
+        let mut vm = Vm::new(vec![0x5B, 0x60, 0x00, 0x56], 1_000_000, BlockContext::default());
+        vm.set_checkpoint_interval(1000);
+        let fired = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = fired.clone();
+        vm.set_checkpoint_hook(move |index, bytes| seen.lock().unwrap().push((index, bytes)));
+
+        for _ in 0..2500 {
+            vm.step_forward().unwrap();
+        }
+        let fired = fired.lock().unwrap();
+        assert_eq!(fired.iter().map(|&(index, _)| index).collect::<Vec<_>>(), [1000, 2000]);
+        assert!(fired.iter().all(|&(_, bytes)| bytes > 0));
+    }
+
+    #[test]
+    fn test_instructions_in_range() {
+        // PUSH1 10, PUSH1 20, ADD, PUSH1 0, MSTORE, STOP
+        let vm = Vm::new(vec![0x60, 0x0A, 0x60, 0x14, 0x01, 0x60, 0x00, 0x52, 0x00], 100_000, BlockContext::default());
+        let all = vm.instructions_in_range(0, usize::MAX);
+        let offsets: Vec<usize> = all.iter().map(|insn| insn.offset).collect();
+        assert_eq!(offsets, [0, 2, 4, 5, 7, 8]);
+        for pair in all.windows(2) {
+            assert_eq!(pair[0].offset + 1 + pair[0].opcode.immediate_size(), pair[1].offset);
+        }
+
+        // Starting inside the first PUSH's immediate skips to the next boundary
+        let middle: Vec<usize> = vm.instructions_in_range(1, 5).iter().map(|insn| insn.offset).collect();
+        assert_eq!(middle, [2, 4]);
+        assert_eq!(vm.instruction_at(4).unwrap().mnemonic, "ADD");
+        assert!(vm.instruction_at(9).is_none());
     }
 }
@@ -1,8 +1,26 @@
 //! Persistent key-value storage for the TTBD virtual machine
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use crate::core::U256;
 
+/// Slot-level differences between two storages, each list ordered by key
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StorageDiff {
+    /// Slots only set in the other storage, with their value there
+    pub added: Vec<(U256, U256)>,
+    /// Slots only set in this storage, with their value here
+    pub removed: Vec<(U256, U256)>,
+    /// Slots set in both with different values, as (slot, old, new)
+    pub changed: Vec<(U256, U256, U256)>,
+}
+
+impl StorageDiff {
+    /// Whether the two storages hold the same values
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
 /// Persistent storage (survives across calls within a transaction).
 /// 
 /// Each storage write is journaled for reversibility.
@@ -122,9 +140,31 @@ impl Storage {
         self.original.clear();
     }
 
-    /// Commit storage (make current state the new original)
-    pub fn commit(&mut self) {
-        self.original = self.data.clone();
+    /// Commit storage (make current state the new original), returning
+    /// the originals it replaces
+    pub fn commit(&mut self) -> HashMap<U256, U256> {
+        std::mem::replace(&mut self.original, self.data.clone())
+    }
+
+    /// Put back the originals a `commit` replaced
+    pub fn restore_original(&mut self, original: HashMap<U256, U256>) {
+        self.original = original;
+    }
+
+    /// Compare against `other`, taken as the newer state. A slot holding
+    /// zero counts as unset.
+    pub fn diff(&self, other: &Storage) -> StorageDiff {
+        let keys: BTreeSet<&U256> = self.data.keys().chain(other.data.keys()).collect();
+        let mut diff = StorageDiff::default();
+        for &key in keys {
+            match (self.get(&key), other.get(&key)) {
+                (old, new) if old == new => {}
+                (old, new) if old.is_zero() => diff.added.push((key, new)),
+                (old, new) if new.is_zero() => diff.removed.push((key, old)),
+                (old, new) => diff.changed.push((key, old, new)),
+            }
+        }
+        diff
     }
 
     /// Iterate over all key-value pairs
@@ -175,6 +215,21 @@ mod tests {
         assert_eq!(old2, U256::from(10u64));
     }
 
+    #[test]
+    fn test_diff() {
+        let slots = |pairs: &[(u64, u64)]| {
+            Storage::with_state(pairs.iter().map(|&(k, v)| (U256::from(k), U256::from(v))).collect())
+        };
+        let before = slots(&[(1, 10), (2, 20)]);
+        let after = slots(&[(1, 99), (3, 30)]);
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.changed, vec![(U256::from(1u64), U256::from(10u64), U256::from(99u64))]);
+        assert_eq!(diff.removed, vec![(U256::from(2u64), U256::from(20u64))]);
+        assert_eq!(diff.added, vec![(U256::from(3u64), U256::from(30u64))]);
+        assert!(before.diff(&before).is_empty());
+    }
+
     #[test]
     fn test_original_tracking() {
         let mut storage = Storage::new();